@@ -0,0 +1,163 @@
+use chrono::prelude::*;
+
+use crate::{domain::AlarmState, repository::Repository};
+
+/// An alarm of a particular event instance, at the time it goes off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DueAlarm<EventInstanceId> {
+    pub instance: EventInstanceId,
+    /// The `uid` of the alarm.
+    pub alarm: String,
+    /// When the alarm goes off, taking snoozing into account.
+    pub time: DateTime<Utc>,
+    /// The description of the alarm, or the summary of the event if the alarm
+    /// has no description.
+    pub message: String,
+}
+
+/// Returns the alarms that go off in the half-open range `[from, to)`, in
+/// order of time. Acknowledged alarms never go off, and snoozed alarms go off
+/// at the end of their snooze instead of at their trigger.
+pub fn alarms_due<R: Repository>(
+    repo: &R,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<DueAlarm<R::EventInstanceId>> {
+    let mut due: Vec<_> = pending_alarms(repo)
+        .into_iter()
+        .filter(|alarm| from <= alarm.time && alarm.time < to)
+        .collect();
+    due.sort_by_key(|alarm| alarm.time);
+    due
+}
+
+/// Returns the first alarm that goes off at or after `after`, if any.
+pub fn next_alarm<R: Repository>(
+    repo: &R,
+    after: DateTime<Utc>,
+) -> Option<DueAlarm<R::EventInstanceId>> {
+    pending_alarms(repo)
+        .into_iter()
+        .filter(|alarm| alarm.time >= after)
+        .min_by_key(|alarm| alarm.time)
+}
+
+/// Collects every alarm that has not been acknowledged.
+fn pending_alarms<R: Repository>(repo: &R) -> Vec<DueAlarm<R::EventInstanceId>> {
+    let Some(timeline) = repo.get_timeline() else {
+        return Vec::new();
+    };
+    let mut pending = Vec::new();
+    for &instance_id in timeline.events.values() {
+        let Ok(instance) = repo.get_event_instance(instance_id) else {
+            continue;
+        };
+        let Ok(body) = repo.get_event_body(instance.body) else {
+            continue;
+        };
+        for alarm in instance.all_alarms(&body) {
+            let time = match instance.alarm_states.get(&alarm.uid) {
+                Some(AlarmState::Acknowledged(_)) => continue,
+                Some(AlarmState::Snoozed(until)) => *until,
+                None => alarm.trigger.time(&instance.time_span),
+            };
+            let message = if alarm.description.is_empty() {
+                body.summary.clone()
+            } else {
+                alarm.description.clone()
+            };
+            pending.push(DueAlarm {
+                instance: instance_id,
+                alarm: alarm.uid.clone(),
+                time,
+                message,
+            });
+        }
+    }
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{add_event, Alarm, AlarmTrigger, MemoryRepo, TimeSpan};
+
+    #[test]
+    fn due_alarms_respect_state() {
+        let repo = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 12, 0, 0).unwrap();
+        let time_span = TimeSpan::Interval {
+            start,
+            duration: TimeDelta::hours(1),
+        };
+        let (instance_id, body_id, mut instance, mut body) =
+            add_event(&repo, time_span, "Lunch".to_owned(), String::new());
+        body.alarms.push(Alarm {
+            uid: "lunch".to_owned(),
+            trigger: AlarmTrigger::Start(TimeDelta::minutes(-15)),
+            description: String::new(),
+        });
+        instance.alarms.push(Alarm {
+            uid: "back".to_owned(),
+            trigger: AlarmTrigger::End(TimeDelta::zero()),
+            description: "Back to work".to_owned(),
+        });
+        drop((instance, body));
+
+        let due = alarms_due(
+            &repo,
+            start - TimeDelta::hours(1),
+            start + TimeDelta::hours(2),
+        );
+        assert_eq!(
+            due,
+            vec![
+                DueAlarm {
+                    instance: instance_id,
+                    alarm: "lunch".to_owned(),
+                    time: start - TimeDelta::minutes(15),
+                    message: "Lunch".to_owned(),
+                },
+                DueAlarm {
+                    instance: instance_id,
+                    alarm: "back".to_owned(),
+                    time: start + TimeDelta::hours(1),
+                    message: "Back to work".to_owned(),
+                },
+            ]
+        );
+
+        // the window is half-open
+        assert!(alarms_due(&repo, start, start + TimeDelta::hours(1)).is_empty());
+
+        let mut instance = repo.get_event_instance(instance_id).unwrap();
+        instance.snooze_alarm("lunch", start + TimeDelta::minutes(5));
+        instance.acknowledge_alarm("back", start);
+        drop(instance);
+
+        let next = next_alarm(&repo, start - TimeDelta::hours(1)).unwrap();
+        assert_eq!(next.alarm, "lunch");
+        assert_eq!(next.time, start + TimeDelta::minutes(5));
+        assert_eq!(next_alarm(&repo, start + TimeDelta::minutes(6)), None);
+
+        // an alarm put before the others leaves what was done about them as
+        // it was
+        repo.get_event_body(body_id).unwrap().alarms.insert(
+            0,
+            Alarm::new(AlarmTrigger::Start(-TimeDelta::hours(1)), String::new()),
+        );
+        let due = alarms_due(
+            &repo,
+            start - TimeDelta::hours(2),
+            start + TimeDelta::hours(2),
+        );
+        let times: Vec<_> = due.iter().map(|alarm| alarm.time).collect();
+        assert_eq!(
+            times,
+            [start - TimeDelta::hours(1), start + TimeDelta::minutes(5)]
+        );
+        assert_eq!(due[1].alarm, "lunch");
+    }
+}
@@ -0,0 +1,106 @@
+use std::{collections::BTreeMap, fs::File, io, path::Path, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use metime_core::MemoryRepo;
+use server::{CalDav, ResourceName};
+
+// the config file is shared with the CLI, which is what reads most of it
+#[allow(dead_code)]
+#[path = "../cli/config.rs"]
+mod config;
+mod server;
+
+/// Serves the repository over CalDAV, so that calendar applications can show
+/// and edit its events.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Path to the config file. Defaults to `$XDG_CONFIG_HOME/metime/config.toml`.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:5232")]
+    listen: String,
+    /// A file written by the CLI's `dump` command, whose events are served.
+    /// Changes made by clients are saved back to it, and the names of the
+    /// events on the server to a file next to it ending in `.names.json`.
+    #[arg(long)]
+    data: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+    let config = match config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let repo = config.storage.open_repo();
+    let mut names = BTreeMap::new();
+    if let Some(path) = args.data.as_deref().filter(|path| path.exists()) {
+        let result = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                metime_core::import_repo(&repo, io::BufReader::new(file)).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("could not read {}: {e}", path.display());
+            std::process::exit(1);
+        }
+        let names_path = names_path(path);
+        if names_path.exists() {
+            match load_names(&names_path) {
+                Ok(loaded) => names = loaded,
+                Err(e) => {
+                    eprintln!("could not read {}: {e}", names_path.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let server = match tiny_http::Server::http(&args.listen) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("could not listen on {}: {e}", args.listen);
+            std::process::exit(1);
+        }
+    };
+    println!("Serving the calendar at http://{}/", args.listen);
+    server::serve(&server, &mut CalDav::new(repo, names), |caldav| {
+        if let Some(path) = &args.data {
+            if let Err(e) = save(path, caldav) {
+                eprintln!("could not save to {}: {e}", path.display());
+            }
+        }
+    });
+}
+
+/// The file next to the data file that keeps the names of the events, so
+/// that clients see the same resources after a restart.
+fn names_path(data: &Path) -> PathBuf {
+    data.with_extension("names.json")
+}
+
+fn load_names(path: &Path) -> Result<BTreeMap<DateTime<Utc>, ResourceName>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    serde_json::from_reader(io::BufReader::new(file)).map_err(|e| e.to_string())
+}
+
+/// Dumps the repository to a file, and the names of its events to the file
+/// next to it, replacing each only once it is complete.
+fn save(path: &Path, caldav: &CalDav<MemoryRepo>) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    let file = File::create(&tmp).map_err(|e| e.to_string())?;
+    metime_core::export_repo(caldav.repo(), io::BufWriter::new(file)).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, path).map_err(|e| e.to_string())?;
+
+    let names_path = names_path(path);
+    let tmp = names_path.with_extension("tmp");
+    let file = File::create(&tmp).map_err(|e| e.to_string())?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), &caldav.names())
+        .map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, names_path).map_err(|e| e.to_string())
+}
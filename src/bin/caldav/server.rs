@@ -0,0 +1,1140 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::prelude::*;
+use metime_core::{
+    ical::{self, IcalEvent, StoreEventError},
+    Repository, TimeSpan,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+const PRINCIPAL_PATH: &str = "/principal/";
+const HOME_PATH: &str = "/calendars/";
+const CALENDAR_PATH: &str = "/calendars/metime/";
+const SYNC_TOKEN_PREFIX: &str = "http://metime.invalid/sync/";
+const EVENT_URL_PREFIX: &str = "http://metime.invalid/event/";
+
+/// A property of a WebDAV resource, named by its namespace and local name.
+type PropName = (String, String);
+
+/// An HTTP request, as far as the server cares about it.
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub headers: &'a [(String, String)],
+    pub body: &'a str,
+}
+
+impl Request<'_> {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl Response {
+    fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn with_body(self, content_type: &str, body: String) -> Self {
+        Self { body, ..self }.with_header("Content-Type", content_type)
+    }
+
+    fn xml(status: u16, body: String) -> Self {
+        Self::new(status).with_body(
+            "application/xml; charset=utf-8",
+            format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n{body}"),
+        )
+    }
+
+    /// A response refusing a request because a WebDAV precondition failed.
+    fn precondition(status: u16, namespace: &str, name: &str) -> Self {
+        Self::xml(
+            status,
+            format!(
+                "<d:error xmlns:d=\"DAV:\" xmlns:c=\"{CALDAV}\">{}</d:error>",
+                prop_xml(namespace, name, "")
+            ),
+        )
+    }
+}
+
+/// The things that the server has URLs for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target<'a> {
+    Root,
+    Principal,
+    /// The collection holding the calendars.
+    Home,
+    /// The one calendar, holding every event instance on the timeline.
+    Calendar,
+    /// An event instance, as a `.ics` file.
+    Resource(&'a str),
+}
+
+impl<'a> Target<'a> {
+    fn from_path(path: &'a str) -> Option<Self> {
+        let path = path.split('?').next().unwrap_or_default();
+        match path {
+            "" | "/" => Some(Target::Root),
+            "/principal" | PRINCIPAL_PATH => Some(Target::Principal),
+            "/calendars" | HOME_PATH => Some(Target::Home),
+            "/calendars/metime" | CALENDAR_PATH => Some(Target::Calendar),
+            _ => path
+                .strip_prefix(CALENDAR_PATH)
+                .filter(|name| !name.is_empty() && !name.contains('/'))
+                .map(Target::Resource),
+        }
+    }
+
+    fn href(&self) -> String {
+        match self {
+            Target::Root => "/".to_owned(),
+            Target::Principal => PRINCIPAL_PATH.to_owned(),
+            Target::Home => HOME_PATH.to_owned(),
+            Target::Calendar => CALENDAR_PATH.to_owned(),
+            Target::Resource(name) => format!("{CALENDAR_PATH}{name}"),
+        }
+    }
+}
+
+/// An event instance as it is served, or was served until it was deleted.
+#[derive(Debug)]
+struct Resource<EventInstanceId> {
+    /// The instance served, or `None` if the resource has been deleted.
+    instance: Option<EventInstanceId>,
+    uid: String,
+    etag: String,
+    /// The sync token at which the resource last changed.
+    changed: u64,
+    /// When the resource last changed, given as its `DTSTAMP`.
+    stamp: DateTime<Utc>,
+}
+
+/// The name and `UID` of the resource serving an instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceName {
+    pub name: String,
+    pub uid: String,
+}
+
+/// Serves the event instances on the timeline of a repository as a single
+/// CalDAV calendar.
+///
+/// Each instance is one `.ics` resource, which takes the name and `UID` a
+/// client put it under. The repository only keeps the `UID`s of instances
+/// that were put or synced, and its IDs don't last from one run to the next
+/// either, so both are kept by the start time
+/// of the instance: [`names`](Self::names) gives them out to be saved and
+/// [`new`](Self::new) takes them back. Instances that have
+/// none are named after their start time, so they keep their names as long
+/// as they stay put. Sync tokens are only valid for the server that gave
+/// them out, and clients holding older ones have to sync from scratch.
+pub struct CalDav<R: Repository> {
+    repo: R,
+    resources: BTreeMap<String, Resource<R::EventInstanceId>>,
+    names: BTreeMap<R::EventInstanceId, String>,
+    /// Names saved by an earlier run, for instances not yet seen.
+    saved: BTreeMap<DateTime<Utc>, ResourceName>,
+    /// Distinguishes the sync tokens of this server from those of earlier
+    /// runs.
+    session: String,
+    sync_token: u64,
+}
+
+impl<R: Repository> CalDav<R>
+where
+    R::EventInstanceId: Ord,
+    R::EventBodyId: PartialEq,
+{
+    /// Serves `repo`, giving the instances that start at the times in
+    /// `saved` the names they had when [`names`](Self::names) was called.
+    pub fn new(repo: R, saved: BTreeMap<DateTime<Utc>, ResourceName>) -> Self {
+        let mut caldav = Self {
+            repo,
+            resources: BTreeMap::new(),
+            names: BTreeMap::new(),
+            saved,
+            session: Uuid::new_v4().simple().to_string()[..8].to_owned(),
+            sync_token: 0,
+        };
+        caldav.refresh();
+        caldav
+    }
+
+    pub fn repo(&self) -> &R {
+        &self.repo
+    }
+
+    /// The name and `UID` of each instance on the timeline, by its start
+    /// time.
+    pub fn names(&self) -> BTreeMap<DateTime<Utc>, ResourceName> {
+        let Some(timeline) = self.repo.get_timeline() else {
+            return BTreeMap::new();
+        };
+        timeline
+            .events
+            .iter()
+            .filter_map(|(&time, id)| {
+                let name = self.names.get(id)?;
+                let uid = self.resources[name].uid.clone();
+                Some((
+                    time,
+                    ResourceName {
+                        name: name.clone(),
+                        uid,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    pub fn handle(&mut self, request: &Request) -> Response {
+        // the repository may have been changed by something other than the
+        // server since the last request
+        self.refresh();
+        let Some(target) = Target::from_path(request.path) else {
+            return Response::new(404);
+        };
+        match (request.method, target) {
+            ("OPTIONS", _) => Response::new(200)
+                .with_header("DAV", "1, 3, calendar-access")
+                .with_header("Allow", "OPTIONS, PROPFIND, REPORT, GET, HEAD, PUT, DELETE"),
+            ("PROPFIND", _) => self.propfind(request, target),
+            ("REPORT", Target::Calendar) => self.report(request),
+            ("GET" | "HEAD", Target::Resource(name)) => self.get(name),
+            ("PUT", Target::Resource(name)) => self.put(request, name),
+            ("DELETE", Target::Resource(name)) => self.delete(request, name),
+            _ => Response::new(405),
+        }
+    }
+
+    /// Brings the resources up to date with the timeline, recording a change
+    /// for every instance that was added, modified or removed behind the
+    /// server's back.
+    fn refresh(&mut self) {
+        let Some(events) = self
+            .repo
+            .get_timeline()
+            .map(|timeline| timeline.events.clone())
+        else {
+            return;
+        };
+        let mut changed = Vec::new();
+        let mut seen = BTreeSet::new();
+        for (time, id) in events {
+            seen.insert(id);
+            let (name, uid) = match self.names.get(&id) {
+                Some(name) => (name.clone(), self.resources[name].uid.clone()),
+                None => {
+                    let ResourceName { name, uid } = self.new_name(time);
+                    (name, uid)
+                }
+            };
+            let Some(event) = self.event(id, uid.clone()) else {
+                continue;
+            };
+            // recorded below, but taken from now on
+            self.names.insert(id, name.clone());
+            let etag = etag(&event);
+            if self
+                .resources
+                .get(&name)
+                .is_none_or(|resource| resource.etag != etag || resource.instance != Some(id))
+            {
+                changed.push((name, Some(id), uid, etag));
+            }
+        }
+        for (id, name) in &self.names {
+            if !seen.contains(id) {
+                let uid = self.resources[name].uid.clone();
+                changed.push((name.clone(), None, uid, String::new()));
+            }
+        }
+        for (name, instance, uid, etag) in changed {
+            self.record(name, instance, uid, etag);
+        }
+    }
+
+    /// Names an instance starting at `time` that has no resource yet, as it
+    /// was saved or else after the time, unless the name or `UID` is in use.
+    fn new_name(&mut self, time: DateTime<Utc>) -> ResourceName {
+        let taken = |caldav: &Self, candidate: &ResourceName| {
+            caldav.names.values().any(|name| *name == candidate.name)
+                || caldav
+                    .resources
+                    .values()
+                    .any(|resource| resource.instance.is_some() && resource.uid == candidate.uid)
+        };
+        if let Some(saved) = self.saved.remove(&time) {
+            if !taken(self, &saved) {
+                return saved;
+            }
+        }
+        (0..)
+            .map(|n| {
+                let url = match n {
+                    0 => format!("{EVENT_URL_PREFIX}{}", time.to_rfc3339()),
+                    n => format!("{EVENT_URL_PREFIX}{}/{n}", time.to_rfc3339()),
+                };
+                let uid = Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes()).to_string();
+                ResourceName {
+                    name: format!("{uid}.ics"),
+                    uid,
+                }
+            })
+            .find(|candidate| !taken(self, candidate))
+            .unwrap()
+    }
+
+    /// Records that a resource has changed, giving out a new sync token.
+    fn record(
+        &mut self,
+        name: String,
+        instance: Option<R::EventInstanceId>,
+        uid: String,
+        etag: String,
+    ) {
+        self.sync_token += 1;
+        if let Some(old) = self.resources.get(&name).and_then(|old| old.instance) {
+            self.names.remove(&old);
+        }
+        if let Some(id) = instance {
+            self.names.insert(id, name.clone());
+        }
+        self.resources.insert(
+            name,
+            Resource {
+                instance,
+                uid,
+                etag,
+                changed: self.sync_token,
+                stamp: Utc::now(),
+            },
+        );
+    }
+
+    fn event(&self, id: R::EventInstanceId, uid: String) -> Option<IcalEvent> {
+        let instance = self.repo.get_event_instance(id).ok()?;
+        let body = self.repo.get_event_body(instance.body).ok()?;
+        Some(IcalEvent::from_instance(uid, &instance, &body))
+    }
+
+    /// Returns the resource with the given name, unless it does not exist or
+    /// has been deleted.
+    fn live(&self, name: &str) -> Option<(&Resource<R::EventInstanceId>, R::EventInstanceId)> {
+        let resource = self.resources.get(name)?;
+        Some((resource, resource.instance?))
+    }
+
+    fn live_names(&self) -> Vec<String> {
+        self.resources
+            .iter()
+            .filter(|(_, resource)| resource.instance.is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    fn calendar_data(&self, name: &str) -> Option<String> {
+        let (resource, id) = self.live(name)?;
+        let event = self.event(id, resource.uid.clone())?;
+        Some(ical::write_vcalendar(&event, resource.stamp))
+    }
+
+    fn sync_token(&self) -> String {
+        format!("{SYNC_TOKEN_PREFIX}{}-{}", self.session, self.sync_token)
+    }
+
+    fn parse_sync_token(&self, token: &str) -> Option<u64> {
+        let (session, n) = token.strip_prefix(SYNC_TOKEN_PREFIX)?.split_once('-')?;
+        let n = n.parse().ok()?;
+        (session == self.session && n <= self.sync_token).then_some(n)
+    }
+
+    /// Returns the value of a property of a target, or `None` if it does not
+    /// have that property.
+    fn prop(&self, target: Target, (namespace, name): &PropName) -> Option<String> {
+        let href = |path: &str| format!("<d:href>{path}</d:href>");
+        let value = match (namespace.as_str(), name.as_str(), target) {
+            (DAV, "resourcetype", Target::Resource(_)) => String::new(),
+            (DAV, "resourcetype", Target::Principal) => "<d:collection/><d:principal/>".to_owned(),
+            (DAV, "resourcetype", Target::Calendar) => "<d:collection/><c:calendar/>".to_owned(),
+            (DAV, "resourcetype", _) => "<d:collection/>".to_owned(),
+            (DAV, "displayname", Target::Calendar | Target::Principal) => "metime".to_owned(),
+            (DAV, "current-user-principal", _) => href(PRINCIPAL_PATH),
+            (DAV, "principal-URL", Target::Principal) => href(PRINCIPAL_PATH),
+            (CALDAV, "calendar-home-set", Target::Root | Target::Principal) => href(HOME_PATH),
+            (DAV, "current-user-privilege-set", Target::Calendar | Target::Resource(_)) => {
+                ["read", "write", "write-content", "bind", "unbind"]
+                    .map(|privilege| format!("<d:privilege><d:{privilege}/></d:privilege>"))
+                    .concat()
+            }
+            (CALDAV, "supported-calendar-component-set", Target::Calendar) => {
+                "<c:comp name=\"VEVENT\"/>".to_owned()
+            }
+            (DAV, "supported-report-set", Target::Calendar) => [
+                prop_xml(CALDAV, "calendar-query", ""),
+                prop_xml(CALDAV, "calendar-multiget", ""),
+                prop_xml(DAV, "sync-collection", ""),
+            ]
+            .map(|report| {
+                format!("<d:supported-report><d:report>{report}</d:report></d:supported-report>")
+            })
+            .concat(),
+            (CALENDARSERVER, "getctag", Target::Calendar)
+            | (DAV, "sync-token", Target::Calendar) => escape_xml(&self.sync_token()),
+            (DAV, "getetag", Target::Resource(name)) => escape_xml(&self.live(name)?.0.etag),
+            (DAV, "getcontenttype", Target::Resource(_)) => {
+                "text/calendar; charset=utf-8; component=vevent".to_owned()
+            }
+            (CALDAV, "calendar-data", Target::Resource(name)) => {
+                escape_xml(&self.calendar_data(name)?)
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn propfind(&self, request: &Request, target: Target) -> Response {
+        if let Target::Resource(name) = target {
+            if self.live(name).is_none() {
+                return Response::new(404);
+            }
+        }
+        let props = match parse_xml(request.body) {
+            Ok(Some(document)) => requested_props(document.root_element()),
+            Ok(None) => None,
+            Err(response) => return response,
+        };
+        let names = self.live_names();
+        let mut targets = vec![target];
+        if request.header("Depth") != Some("0") {
+            match target {
+                Target::Root => targets.extend([Target::Principal, Target::Home]),
+                Target::Home => targets.push(Target::Calendar),
+                Target::Calendar => targets.extend(names.iter().map(|name| Target::Resource(name))),
+                Target::Principal | Target::Resource(_) => {}
+            }
+        }
+        let mut multistatus = Multistatus::default();
+        for target in targets {
+            self.respond_with_props(&mut multistatus, target, props.as_deref());
+        }
+        multistatus.finish(None)
+    }
+
+    /// Adds the properties of a target to a multistatus response. Without a
+    /// list of properties, every property that the target has is given.
+    fn respond_with_props(
+        &self,
+        multistatus: &mut Multistatus,
+        target: Target,
+        props: Option<&[PropName]>,
+    ) {
+        const ALL_PROPS: [(&str, &str); 6] = [
+            (DAV, "resourcetype"),
+            (DAV, "displayname"),
+            (DAV, "getetag"),
+            (DAV, "getcontenttype"),
+            (CALENDARSERVER, "getctag"),
+            (DAV, "sync-token"),
+        ];
+        let mut found = Vec::new();
+        let mut missing = Vec::new();
+        match props {
+            Some(props) => {
+                for prop in props {
+                    match self.prop(target, prop) {
+                        Some(value) => found.push((prop.clone(), value)),
+                        None => missing.push(prop.clone()),
+                    }
+                }
+            }
+            None => {
+                for (namespace, name) in ALL_PROPS {
+                    let prop = (namespace.to_owned(), name.to_owned());
+                    if let Some(value) = self.prop(target, &prop) {
+                        found.push((prop, value));
+                    }
+                }
+            }
+        }
+        multistatus.response(&target.href(), &found, &missing);
+    }
+
+    fn report(&self, request: &Request) -> Response {
+        let document = match parse_xml(request.body) {
+            Ok(Some(document)) => document,
+            Ok(None) => return Response::new(400),
+            Err(response) => return response,
+        };
+        let root = document.root_element();
+        let default_props = [(DAV.to_owned(), "getetag".to_owned())];
+        let props = requested_props(root);
+        let props = props.as_deref().unwrap_or(&default_props);
+        let mut multistatus = Multistatus::default();
+        match (root.tag_name().namespace(), root.tag_name().name()) {
+            (Some(CALDAV), "calendar-query") => {
+                let range = root
+                    .descendants()
+                    .find(|node| node.has_tag_name((CALDAV, "time-range")));
+                let bound = |attribute| {
+                    range
+                        .and_then(|range| range.attribute(attribute))
+                        .and_then(parse_utc)
+                };
+                let (from, to) = (bound("start"), bound("end"));
+                for name in self.live_names() {
+                    let Some((resource, id)) = self.live(&name) else {
+                        continue;
+                    };
+                    let Some(event) = self.event(id, resource.uid.clone()) else {
+                        continue;
+                    };
+                    if overlaps(&event.time_span, from, to) {
+                        self.respond_with_props(
+                            &mut multistatus,
+                            Target::Resource(&name),
+                            Some(props),
+                        );
+                    }
+                }
+                multistatus.finish(None)
+            }
+            (Some(CALDAV), "calendar-multiget") => {
+                for href in root
+                    .descendants()
+                    .filter(|node| node.has_tag_name((DAV, "href")))
+                {
+                    let href = href.text().unwrap_or_default().trim();
+                    let name = href
+                        .split_once(CALENDAR_PATH)
+                        .map(|(_, name)| name)
+                        .unwrap_or_default();
+                    if self.live(name).is_some() {
+                        self.respond_with_props(
+                            &mut multistatus,
+                            Target::Resource(name),
+                            Some(props),
+                        );
+                    } else {
+                        multistatus.not_found(href);
+                    }
+                }
+                multistatus.finish(None)
+            }
+            (Some(DAV), "sync-collection") => {
+                let token = root
+                    .children()
+                    .find(|node| node.has_tag_name((DAV, "sync-token")))
+                    .and_then(|node| node.text())
+                    .unwrap_or_default()
+                    .trim();
+                let since = if token.is_empty() {
+                    0
+                } else {
+                    match self.parse_sync_token(token) {
+                        Some(since) => since,
+                        None => return Response::precondition(403, DAV, "valid-sync-token"),
+                    }
+                };
+                for (name, resource) in &self.resources {
+                    match resource.instance {
+                        _ if resource.changed <= since => {}
+                        Some(_) => self.respond_with_props(
+                            &mut multistatus,
+                            Target::Resource(name),
+                            Some(props),
+                        ),
+                        // a client syncing from scratch has nothing to delete
+                        None if since == 0 => {}
+                        None => multistatus.not_found(&Target::Resource(name).href()),
+                    }
+                }
+                multistatus.finish(Some(&self.sync_token()))
+            }
+            _ => Response::precondition(403, DAV, "supported-report"),
+        }
+    }
+
+    fn get(&self, name: &str) -> Response {
+        match (self.live(name), self.calendar_data(name)) {
+            (Some((resource, _)), Some(data)) => Response::new(200)
+                .with_header("ETag", resource.etag.clone())
+                .with_body("text/calendar; charset=utf-8", data),
+            _ => Response::new(404),
+        }
+    }
+
+    /// Checks the `If-Match` and `If-None-Match` headers of a request against
+    /// the current state of a resource.
+    fn preconditions_hold(&self, request: &Request, name: &str) -> bool {
+        let etag = self.live(name).map(|(resource, _)| resource.etag.as_str());
+        let matches = |header: &str| {
+            header
+                .split(',')
+                .map(str::trim)
+                .any(|tag| etag.is_some_and(|etag| tag == "*" || tag == etag))
+        };
+        request.header("If-Match").is_none_or(matches)
+            && !request.header("If-None-Match").is_some_and(matches)
+    }
+
+    fn put(&mut self, request: &Request, name: &str) -> Response {
+        if !self.preconditions_hold(request, name) {
+            return Response::new(412);
+        }
+        let event = match ical::parse_vcalendar(request.body) {
+            Ok(event) => event,
+            Err(e) => return Response::new(400).with_body("text/plain", format!("{e}\n")),
+        };
+        let existing = self.live(name).map(|(_, id)| id);
+        let uid_taken = self.resources.iter().any(|(other, resource)| {
+            other != name && resource.instance.is_some() && resource.uid == event.uid
+        });
+        if uid_taken {
+            return Response::precondition(409, CALDAV, "no-uid-conflict");
+        }
+        let id = match ical::store_event(&self.repo, existing, &event) {
+            Ok(id) => id,
+            Err(StoreEventError::TimelineCollision(_)) => {
+                return Response::new(409).with_body(
+                    "text/plain",
+                    "another event already starts at the same time\n".to_owned(),
+                );
+            }
+            Err(StoreEventError::Retrieval(_)) => return Response::new(503),
+        };
+        let Some(stored) = self.event(id, event.uid.clone()) else {
+            return Response::new(503);
+        };
+        let etag = etag(&stored);
+        self.record(name.to_owned(), Some(id), event.uid, etag.clone());
+        let status = if existing.is_some() { 204 } else { 201 };
+        Response::new(status).with_header("ETag", etag)
+    }
+
+    fn delete(&mut self, request: &Request, name: &str) -> Response {
+        let Some((resource, id)) = self.live(name) else {
+            return Response::new(404);
+        };
+        if !self.preconditions_hold(request, name) {
+            return Response::new(412);
+        }
+        let uid = resource.uid.clone();
+        // the instance stays in the repository, which has no way to delete
+        // it, but it is no longer on the timeline
+        let Some(mut timeline) = self.repo.get_timeline() else {
+            return Response::new(503);
+        };
+        timeline.events.retain(|_, &mut other| other != id);
+        drop(timeline);
+        self.record(name.to_owned(), None, uid, String::new());
+        Response::new(204)
+    }
+}
+
+/// Collects the `<response>`s of a WebDAV multistatus response.
+#[derive(Default)]
+struct Multistatus {
+    responses: String,
+}
+
+impl Multistatus {
+    fn response(&mut self, href: &str, found: &[(PropName, String)], missing: &[PropName]) {
+        self.responses += &format!("<d:response><d:href>{}</d:href>", escape_xml(href));
+        if !found.is_empty() {
+            self.responses += "<d:propstat><d:prop>";
+            for ((namespace, name), value) in found {
+                self.responses += &prop_xml(namespace, name, value);
+            }
+            self.responses += "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>";
+        }
+        if !missing.is_empty() {
+            self.responses += "<d:propstat><d:prop>";
+            for (namespace, name) in missing {
+                self.responses += &prop_xml(namespace, name, "");
+            }
+            self.responses += "</d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>";
+        }
+        self.responses += "</d:response>";
+    }
+
+    fn not_found(&mut self, href: &str) {
+        self.responses += &format!(
+            "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+            escape_xml(href)
+        );
+    }
+
+    fn finish(self, sync_token: Option<&str>) -> Response {
+        let sync_token = sync_token
+            .map(|token| format!("<d:sync-token>{}</d:sync-token>", escape_xml(token)))
+            .unwrap_or_default();
+        Response::xml(
+            207,
+            format!(
+                "<d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"{CALDAV}\" xmlns:cs=\"{CALENDARSERVER}\">{}{sync_token}</d:multistatus>",
+                self.responses
+            ),
+        )
+    }
+}
+
+/// Parses the XML body of a request, which may be empty.
+fn parse_xml(body: &str) -> Result<Option<roxmltree::Document<'_>>, Response> {
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+    roxmltree::Document::parse(body)
+        .map(Some)
+        .map_err(|e| Response::new(400).with_body("text/plain", format!("{e}\n")))
+}
+
+/// Returns the properties listed in the `<prop>` element of a request, or
+/// `None` if it asks for all of them.
+fn requested_props(root: roxmltree::Node) -> Option<Vec<PropName>> {
+    let prop = root
+        .children()
+        .find(|node| node.has_tag_name((DAV, "prop")))?;
+    let props = prop
+        .children()
+        .filter(|node| node.is_element())
+        .map(|node| {
+            let tag = node.tag_name();
+            (
+                tag.namespace().unwrap_or_default().to_owned(),
+                tag.name().to_owned(),
+            )
+        })
+        .collect();
+    Some(props)
+}
+
+/// Writes a property element, using the prefixes declared on the multistatus
+/// element for the namespaces that the server knows.
+fn prop_xml(namespace: &str, name: &str, value: &str) -> String {
+    let (tag, declaration) = match namespace {
+        DAV => (format!("d:{name}"), String::new()),
+        CALDAV => (format!("c:{name}"), String::new()),
+        CALENDARSERVER => (format!("cs:{name}"), String::new()),
+        _ => (
+            format!("x:{name}"),
+            format!(" xmlns:x=\"{}\"", escape_xml(namespace)),
+        ),
+    };
+    if value.is_empty() {
+        format!("<{tag}{declaration}/>")
+    } else {
+        format!("<{tag}{declaration}>{value}</{tag}>")
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn parse_utc(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+/// Whether a time span overlaps a time range of a `calendar-query`, either
+/// end of which may be open.
+fn overlaps(time_span: &TimeSpan, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+    let starts_before_end = to.is_none_or(|to| time_span.earliest() < to);
+    let ends_after_start = from.is_none_or(|from| match time_span {
+        TimeSpan::Instant(time) => *time >= from,
+        TimeSpan::Interval { .. } => time_span.latest() > from,
+    });
+    starts_before_end && ends_after_start
+}
+
+/// Derives the ETag of an event from its content, so that it stays the same
+/// from one run of the server to the next.
+fn etag(event: &IcalEvent) -> String {
+    // FNV-1a, which unlike the standard hasher is stable across releases
+    let data = ical::write_vcalendar(event, DateTime::UNIX_EPOCH);
+    let hash = data.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("\"{hash:016x}\"")
+}
+
+/// Answers the requests that come to an HTTP server, calling `on_change`
+/// whenever a request has changed the repository.
+pub fn serve<R: Repository>(
+    server: &tiny_http::Server,
+    caldav: &mut CalDav<R>,
+    mut on_change: impl FnMut(&CalDav<R>),
+) where
+    R::EventInstanceId: Ord,
+    R::EventBodyId: PartialEq,
+{
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        let response = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => {
+                let headers: Vec<_> = request
+                    .headers()
+                    .iter()
+                    .map(|header| (header.field.to_string(), header.value.to_string()))
+                    .collect();
+                let method = request.method().to_string();
+                let response = caldav.handle(&Request {
+                    method: &method,
+                    path: request.url(),
+                    headers: &headers,
+                    body: &body,
+                });
+                if matches!(method.as_str(), "PUT" | "DELETE") && response.status < 300 {
+                    on_change(caldav);
+                }
+                response
+            }
+            Err(_) => Response::new(400),
+        };
+
+        let mut http =
+            tiny_http::Response::from_string(response.body).with_status_code(response.status);
+        for (name, value) in response.headers {
+            if let Ok(header) = tiny_http::Header::from_bytes(name, value) {
+                http.add_header(header);
+            }
+        }
+        if let Err(e) = request.respond(http) {
+            eprintln!("could not respond to a request: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        thread,
+    };
+
+    use chrono::TimeDelta;
+    use metime_core::{Alarm, AlarmTrigger, MemoryRepo};
+
+    use super::*;
+
+    fn listen(repo: MemoryRepo) -> SocketAddr {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        thread::spawn(move || serve(&server, &mut CalDav::new(repo, BTreeMap::new()), |_| {}));
+        addr
+    }
+
+    struct Reply {
+        status: u16,
+        head: String,
+        body: String,
+    }
+
+    impl Reply {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.head.lines().find_map(|line| {
+                let (field, value) = line.split_once(':')?;
+                field.eq_ignore_ascii_case(name).then(|| value.trim())
+            })
+        }
+
+        /// Returns the text of the first element with the given tag.
+        fn element(&self, tag: &str) -> &str {
+            let start = self.body.find(&format!("<{tag}>")).unwrap() + tag.len() + 2;
+            let end = self.body[start..].find(&format!("</{tag}>")).unwrap();
+            &self.body[start..start + end]
+        }
+    }
+
+    fn send(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Reply {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        for (name, value) in headers {
+            request += &format!("{name}: {value}\r\n");
+        }
+        request += "\r\n";
+        request += body;
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        let (head, body) = reply.split_once("\r\n\r\n").unwrap();
+        Reply {
+            status: head[9..12].parse().unwrap(),
+            head: head.to_owned(),
+            body: body.to_owned(),
+        }
+    }
+
+    fn standup(summary: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:standup-1\r\n\
+             DTSTART:20240610T090000Z\r\nDTEND:20240610T091500Z\r\nSUMMARY:{summary}\r\n\
+             END:VEVENT\r\nEND:VCALENDAR\r\n"
+        )
+    }
+
+    fn sync(addr: SocketAddr, token: &str) -> Reply {
+        let body = format!(
+            "<d:sync-collection xmlns:d=\"DAV:\"><d:sync-token>{token}</d:sync-token>\
+             <d:sync-level>1</d:sync-level><d:prop><d:getetag/></d:prop></d:sync-collection>"
+        );
+        send(addr, "REPORT", CALENDAR_PATH, &[], &body)
+    }
+
+    #[test]
+    fn discovery_lists_existing_events() {
+        let repo = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let _ = metime_core::add_event(
+            &repo,
+            TimeSpan::Interval {
+                start,
+                duration: TimeDelta::hours(1),
+            },
+            "Dentist".to_owned(),
+            String::new(),
+        );
+        let addr = listen(repo);
+
+        let principal = "<d:propfind xmlns:d=\"DAV:\"><d:prop><d:current-user-principal/></d:prop></d:propfind>";
+        let reply = send(addr, "PROPFIND", "/", &[("Depth", "0")], principal);
+        assert_eq!(reply.status, 207);
+        assert!(reply.body.contains("<d:href>/principal/</d:href>"));
+
+        let home = "<d:propfind xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\">\
+                    <d:prop><c:calendar-home-set/><d:owner/></d:prop></d:propfind>";
+        let reply = send(addr, "PROPFIND", PRINCIPAL_PATH, &[("Depth", "0")], home);
+        assert!(reply
+            .body
+            .contains("<c:calendar-home-set><d:href>/calendars/</d:href>"));
+        assert!(reply
+            .body
+            .contains("<d:owner/></d:prop><d:status>HTTP/1.1 404 Not Found"));
+
+        let reply = send(addr, "PROPFIND", CALENDAR_PATH, &[("Depth", "1")], "");
+        assert!(reply.body.contains("<c:calendar/>"));
+        assert_eq!(reply.body.matches(".ics</d:href>").count(), 1);
+        assert_eq!(send(addr, "GET", CALENDAR_PATH, &[], "").status, 405);
+    }
+
+    #[test]
+    fn put_get_report_sync_and_delete() {
+        let addr = listen(MemoryRepo::new());
+        let path = &format!("{CALENDAR_PATH}standup.ics");
+
+        let props = "<d:propfind xmlns:d=\"DAV:\"><d:prop><d:sync-token/></d:prop></d:propfind>";
+        let reply = send(addr, "PROPFIND", CALENDAR_PATH, &[("Depth", "0")], props);
+        let initial_token = reply.element("d:sync-token").to_owned();
+
+        let reply = send(
+            addr,
+            "PUT",
+            path,
+            &[("If-None-Match", "*")],
+            &standup("Standup"),
+        );
+        assert_eq!(reply.status, 201);
+        let etag = reply.header("ETag").unwrap().to_owned();
+        let reply = send(
+            addr,
+            "PUT",
+            path,
+            &[("If-None-Match", "*")],
+            &standup("Standup"),
+        );
+        assert_eq!(reply.status, 412);
+
+        let reply = send(addr, "GET", path, &[], "");
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.header("ETag"), Some(etag.as_str()));
+        assert!(reply.body.contains("UID:standup-1\r\n"));
+        assert!(reply.body.contains("SUMMARY:Standup\r\n"));
+
+        let reply = send(
+            addr,
+            "PUT",
+            path,
+            &[("If-Match", "\"stale\"")],
+            &standup("Sync"),
+        );
+        assert_eq!(reply.status, 412);
+        let reply = send(addr, "PUT", path, &[("If-Match", &etag)], &standup("Sync"));
+        assert_eq!(reply.status, 204);
+        let etag = reply.header("ETag").unwrap().to_owned();
+
+        let query = |start: &str, end: &str| {
+            format!(
+                "<c:calendar-query xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\">\
+                 <d:prop><d:getetag/><c:calendar-data/></d:prop><c:filter>\
+                 <c:comp-filter name=\"VCALENDAR\"><c:comp-filter name=\"VEVENT\">\
+                 <c:time-range start=\"{start}\" end=\"{end}\"/></c:comp-filter></c:comp-filter>\
+                 </c:filter></c:calendar-query>"
+            )
+        };
+        let reply = send(
+            addr,
+            "REPORT",
+            CALENDAR_PATH,
+            &[],
+            &query("20240610T000000Z", "20240611T000000Z"),
+        );
+        assert_eq!(reply.status, 207);
+        assert!(reply.body.contains("standup.ics"));
+        assert!(reply.body.contains("SUMMARY:Sync"));
+        let reply = send(
+            addr,
+            "REPORT",
+            CALENDAR_PATH,
+            &[],
+            &query("20240610T091500Z", "20240611T000000Z"),
+        );
+        assert!(!reply.body.contains("standup.ics"));
+
+        let multiget = format!(
+            "<c:calendar-multiget xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\">\
+             <d:prop><d:getetag/></d:prop><d:href>{path}</d:href>\
+             <d:href>{CALENDAR_PATH}missing.ics</d:href></c:calendar-multiget>"
+        );
+        let reply = send(addr, "REPORT", CALENDAR_PATH, &[], &multiget);
+        assert!(reply.body.contains(&escape_xml(&etag)));
+        assert!(reply
+            .body
+            .contains("missing.ics</d:href><d:status>HTTP/1.1 404"));
+
+        let reply = sync(addr, &initial_token);
+        assert!(reply.body.contains("standup.ics"));
+        let token = reply.element("d:sync-token").to_owned();
+        assert!(!sync(addr, &token).body.contains("standup.ics"));
+
+        assert_eq!(
+            send(addr, "DELETE", path, &[("If-Match", &etag)], "").status,
+            204
+        );
+        assert_eq!(send(addr, "GET", path, &[], "").status, 404);
+        let reply = sync(addr, &token);
+        assert!(reply
+            .body
+            .contains("standup.ics</d:href><d:status>HTTP/1.1 404"));
+        assert!(!sync(addr, "").body.contains("standup.ics"));
+        assert_eq!(sync(addr, "http://example.com/other").status, 403);
+    }
+
+    #[test]
+    fn puts_to_one_of_the_instances_of_a_body() {
+        let repo = MemoryRepo::new();
+        let at = |hour| TimeSpan::Interval {
+            start: Utc.with_ymd_and_hms(2024, 6, 10, hour, 0, 0).unwrap(),
+            duration: TimeDelta::hours(1),
+        };
+        let (first, body, mut instance, _) =
+            metime_core::add_event(&repo, at(9), "Standup".to_owned(), String::new());
+        let reminder = Alarm::new(AlarmTrigger::Start(-TimeDelta::minutes(10)), String::new());
+        instance.alarms.push(reminder.clone());
+        drop(instance);
+        let (second, _) = metime_core::add_event_instance(&repo, body, at(11));
+        let mut caldav = CalDav::new(repo, BTreeMap::new());
+        let name = &caldav.names()[&at(9).earliest()].name;
+        let path = format!("{CALENDAR_PATH}{name}");
+        let mut send = |method, body: &str| {
+            let headers = [];
+            let request = Request {
+                method,
+                path: &path,
+                headers: &headers,
+                body,
+            };
+            caldav.handle(&request)
+        };
+        let data = send("GET", "").body;
+        let reply = send("PUT", &data.replace("SUMMARY:Standup", "SUMMARY:Planning"));
+        assert_eq!(reply.status, 204);
+
+        // the other instance keeps the body, and the alarm stays the
+        // instance's own
+        let repo = caldav.repo();
+        let instance = repo.get_event_instance(first).unwrap();
+        assert_ne!(instance.body, body);
+        assert_eq!(instance.alarms, [reminder]);
+        let own = repo.get_event_body(instance.body).unwrap();
+        assert_eq!(own.summary, "Planning");
+        assert!(own.alarms.is_empty());
+        assert_eq!(repo.get_event_instance(second).unwrap().body, body);
+        assert_eq!(repo.get_event_body(body).unwrap().summary, "Standup");
+    }
+
+    #[test]
+    fn names_last_across_restarts() {
+        let repo = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 14, 0, 0).unwrap();
+        let _ = metime_core::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Review".to_owned(),
+            String::new(),
+        );
+        let mut caldav = CalDav::new(repo, BTreeMap::new());
+        let reply = caldav.handle(&Request {
+            method: "PUT",
+            path: &format!("{CALENDAR_PATH}standup.ics"),
+            headers: &[],
+            body: &standup("Standup"),
+        });
+        assert_eq!(reply.status, 201);
+        let names = caldav.names();
+        assert_eq!(names.len(), 2);
+
+        // what the data file and the names file hold after a restart
+        let mut dump = Vec::new();
+        metime_core::export_repo(caldav.repo(), &mut dump).unwrap();
+        let restarted = |saved| {
+            let repo = MemoryRepo::new();
+            metime_core::import_repo(&repo, dump.as_slice()).unwrap();
+            CalDav::new(repo, saved).names()
+        };
+        assert_eq!(restarted(names.clone()), names);
+        // events that no client named are named after their start time
+        let unsaved = restarted(BTreeMap::new());
+        assert_eq!(unsaved[&start], names[&start]);
+        assert_ne!(unsaved.values().next(), names.values().next());
+    }
+}
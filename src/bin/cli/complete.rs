@@ -0,0 +1,345 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use chrono::{prelude::*, Days};
+use clap::CommandFactory;
+use clap_repl::reedline::{Completer, Span, Suggestion};
+use metime_core::Repository;
+
+use crate::{for_each_event, for_each_task, Command};
+
+/// Tab completion for the REPL. On top of command and flag names, this
+/// completes values by looking at what is currently in the repository.
+pub struct ReplCompleter {
+    repo: Arc<crate::Repo>,
+}
+
+impl ReplCompleter {
+    pub fn new(repo: Arc<crate::Repo>) -> Self {
+        Self { repo }
+    }
+
+    fn titles(&self) -> Vec<Candidate> {
+        let mut titles = BTreeSet::new();
+        for_each_event(&self.repo, |_, _, body| {
+            if !body.summary.is_empty() {
+                titles.insert(body.summary.clone());
+            }
+        });
+        titles.into_iter().map(Candidate::quoted).collect()
+    }
+
+    fn tags(&self) -> Vec<Candidate> {
+        let mut tags = BTreeSet::new();
+        for_each_event(&self.repo, |_, _, body| {
+            tags.extend(body.categories.iter().cloned());
+        });
+        for_each_task(&self.repo, |_, task| {
+            tags.extend(task.categories.iter().cloned());
+        });
+        tags.into_iter().map(Candidate::quoted).collect()
+    }
+
+    fn calendar_names(&self) -> Vec<Candidate> {
+        let Some(calendar_list) = self.repo.get_calendar_list() else {
+            return Vec::new();
+        };
+        calendar_list
+            .calendars
+            .iter()
+            .map(|calendar| Candidate::quoted(calendar.name.clone()))
+            .collect()
+    }
+
+    fn instance_ids(&self) -> Vec<Candidate> {
+        let mut ids = Vec::new();
+        for_each_event(&self.repo, |id, _, body| {
+            ids.push(Candidate {
+                description: Some(body.summary.clone()),
+                ..Candidate::new(id.to_string())
+            });
+        });
+        ids
+    }
+
+    fn task_ids(&self) -> Vec<Candidate> {
+        let mut ids = Vec::new();
+        for_each_task(&self.repo, |id, task| {
+            if !task.is_completed() {
+                ids.push(Candidate {
+                    description: Some(task.summary.clone()),
+                    ..Candidate::new(id.to_string())
+                });
+            }
+        });
+        ids
+    }
+}
+
+impl Completer for ReplCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let (words, current_start) = split_words(&line[..pos]);
+        let current = &line[current_start..pos];
+
+        // descend through nested subcommands like `task add`
+        let mut cmd = Command::command();
+        let mut path = Vec::new();
+        let mut args = words.as_slice();
+        while cmd.has_subcommands() {
+            let Some((subcommand, rest)) = args.split_first() else {
+                break;
+            };
+            let Some(sub) = cmd.find_subcommand(subcommand).cloned() else {
+                return Vec::new();
+            };
+            path.push(sub.get_name().to_owned());
+            cmd = sub;
+            args = rest;
+        }
+
+        let candidates = if cmd.has_subcommands() {
+            subcommands(&cmd)
+        } else {
+            let previous = args.last().map(String::as_str);
+            // the flag before the cursor, if the word under the cursor is its
+            // value
+            let value_of = previous.and_then(|prev| {
+                cmd.get_arguments().find(|arg| {
+                    let is_flag = match prev.strip_prefix("--") {
+                        Some(long) => arg.get_long() == Some(long),
+                        None => prev
+                            .strip_prefix('-')
+                            .and_then(|short| short.parse().ok())
+                            .is_some_and(|short| arg.get_short() == Some(short)),
+                    };
+                    is_flag && arg.get_action().takes_values()
+                })
+            });
+            match value_of {
+                Some(arg) => match arg.get_id().as_str() {
+                    "time_span" | "due" => time_span_fragments(),
+                    "tags" => self.tags(),
+                    "calendar" | "calendars" => self.calendar_names(),
+                    _ => Vec::new(),
+                },
+                None if current.starts_with('-') => flags(&cmd),
+                None => match path.join(" ").as_str() {
+                    "create-event" | "track start" => self.titles(),
+                    "show-event" | "record" | "acknowledge-alarm" | "snooze-alarm" => {
+                        self.instance_ids()
+                    }
+                    "task done" | "task schedule" => self.task_ids(),
+                    _ => Vec::new(),
+                },
+            }
+        };
+
+        let typed = current.trim_start_matches(['"', '\'']).to_lowercase();
+        let span = Span::new(current_start, pos);
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.matches(&typed))
+            .map(|candidate| Suggestion {
+                value: candidate.value,
+                description: candidate.description,
+                style: None,
+                extra: None,
+                span,
+                append_whitespace: candidate.append_whitespace,
+            })
+            .collect()
+    }
+}
+
+/// A possible completion of the word under the cursor.
+struct Candidate {
+    /// The text that replaces the word.
+    value: String,
+    description: Option<String>,
+    /// Another word that also selects this candidate, like "tomorrow" for
+    /// tomorrow's date.
+    keyword: Option<String>,
+    append_whitespace: bool,
+}
+
+impl Candidate {
+    fn new(value: String) -> Self {
+        Self {
+            value,
+            description: None,
+            keyword: None,
+            append_whitespace: true,
+        }
+    }
+
+    /// A candidate for arbitrary text, quoted if it would otherwise be split
+    /// into several words.
+    fn quoted(text: String) -> Self {
+        let value = if text.contains(char::is_whitespace) || text.contains(['"', '\'']) {
+            format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            text.clone()
+        };
+        Self {
+            keyword: Some(text),
+            ..Self::new(value)
+        }
+    }
+
+    fn matches(&self, typed: &str) -> bool {
+        self.value.to_lowercase().starts_with(typed)
+            || self
+                .keyword
+                .as_ref()
+                .is_some_and(|keyword| keyword.to_lowercase().starts_with(typed))
+    }
+}
+
+fn subcommands(cmd: &clap::Command) -> Vec<Candidate> {
+    cmd.get_subcommands()
+        .map(|cmd| Candidate {
+            description: cmd.get_about().map(|about| about.to_string()),
+            ..Candidate::new(cmd.get_name().to_owned())
+        })
+        .collect()
+}
+
+fn flags(cmd: &clap::Command) -> Vec<Candidate> {
+    cmd.get_arguments()
+        .filter_map(|arg| {
+            let long = arg.get_long()?;
+            Some(Candidate {
+                description: arg.get_help().map(|help| help.to_string()),
+                ..Candidate::new(format!("--{long}"))
+            })
+        })
+        .collect()
+}
+
+/// Fragments of time spans around the present, which can be selected either
+/// by their value or by a name like "today".
+fn time_span_fragments() -> Vec<Candidate> {
+    let now = Local::now();
+    let today = now.date_naive();
+    let date_prefix = |name: String, date: NaiveDate| Candidate {
+        description: Some(name.clone()),
+        keyword: Some(name),
+        append_whitespace: false,
+        ..Candidate::new(date.format("%Y-%m-%dT").to_string())
+    };
+
+    let mut fragments = vec![
+        Candidate {
+            description: Some("now".to_owned()),
+            keyword: Some("now".to_owned()),
+            ..Candidate::new(now.format("%Y-%m-%dT%H:%M").to_string())
+        },
+        date_prefix("today".to_owned(), today),
+        date_prefix("tomorrow".to_owned(), today + Days::new(1)),
+    ];
+    // the rest of the coming week, by weekday name
+    for days in 2..7 {
+        let date = today + Days::new(days);
+        fragments.push(date_prefix(
+            date.format("%A").to_string().to_lowercase(),
+            date,
+        ));
+    }
+    fragments
+}
+
+/// Splits the text before the cursor into the complete words before the
+/// cursor, and the byte index at which the word under the cursor starts.
+/// Quotes group words the same way as when the line is parsed.
+fn split_words(line: &str) -> (Vec<String>, usize) {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut word_start = None;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => {
+                if word_start.take().is_some() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                if let Some((_, escaped)) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            (_, c) => word.push(c),
+        }
+        word_start.get_or_insert(i);
+    }
+    (words, word_start.unwrap_or(line.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_tracks_current_word() {
+        assert_eq!(split_words(""), (vec![], 0));
+        assert_eq!(split_words("show"), (vec![], 0));
+        assert_eq!(split_words("show "), (vec!["show".to_owned()], 5));
+        assert_eq!(
+            split_words("create-event \"team lunch\" -t 20"),
+            (
+                vec![
+                    "create-event".to_owned(),
+                    "team lunch".to_owned(),
+                    "-t".to_owned()
+                ],
+                29
+            )
+        );
+        assert_eq!(
+            split_words("create-event \"team lu"),
+            (vec!["create-event".to_owned()], 13)
+        );
+    }
+
+    #[test]
+    fn completes_values_from_repository() {
+        let repo = Arc::new(metime_core::IndexingRepo::new(
+            metime_core::MemoryRepo::new(),
+        ));
+        let time_span = metime_core::TimeSpan::Instant(Utc::now());
+        let (_, _, _, mut body) =
+            metime_core::add_event(&*repo, time_span, "Team lunch".to_owned(), String::new());
+        body.categories.push("work".to_owned());
+        drop(body);
+
+        let mut completer = ReplCompleter::new(repo);
+        let values = |suggestions: Vec<Suggestion>| {
+            suggestions.into_iter().map(|s| s.value).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            values(completer.complete("create-event te", 15)),
+            vec!["\"Team lunch\"".to_owned()]
+        );
+        assert_eq!(
+            values(completer.complete("create-event x --tag w", 22)),
+            vec!["work".to_owned()]
+        );
+        assert_eq!(
+            values(completer.complete("create-event x -t tomo", 22)),
+            vec![(Local::now().date_naive() + Days::new(1))
+                .format("%Y-%m-%dT")
+                .to_string()]
+        );
+        assert_eq!(
+            values(completer.complete("task sc", 7)),
+            vec!["schedule".to_owned()]
+        );
+        assert_eq!(
+            values(completer.complete("sh", 2)),
+            vec!["show".to_owned(), "show-event".to_owned()]
+        );
+    }
+}
@@ -0,0 +1,544 @@
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{prelude::*, TimeDelta};
+use derive_more::derive::{Display, Error, From};
+use metime_core::{ConflictPolicy, Disambiguation, MemoryRepo, TimeSpan, YearInference};
+use serde::{de, Deserialize, Deserializer};
+
+/// Settings for the CLI and the daemon, loaded from a TOML file. Every key is
+/// optional; any key that is missing takes its default value.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The text shown at the left of the REPL prompt.
+    pub prompt: String,
+    pub storage: StorageConfig,
+    pub display: DisplayConfig,
+    pub schedule: ScheduleConfig,
+    pub daemon: DaemonConfig,
+    pub tracker: TrackerConfig,
+    pub sync: SyncConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prompt: "metime".to_owned(),
+            storage: StorageConfig::default(),
+            display: DisplayConfig::default(),
+            schedule: ScheduleConfig::default(),
+            daemon: DaemonConfig::default(),
+            tracker: TrackerConfig::default(),
+            sync: SyncConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Where the backend keeps its data. Backends that do not persist
+    /// anything ignore this.
+    pub path: Option<PathBuf>,
+}
+
+impl StorageConfig {
+    /// Opens the repository that this config describes.
+    pub fn open_repo(&self) -> MemoryRepo {
+        match self.backend {
+            StorageBackend::Memory => MemoryRepo::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Display, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    /// Keep everything in memory; nothing survives the end of the session.
+    #[default]
+    #[display("memory")]
+    Memory,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// The time zone in which times are shown.
+    pub zone: DisplayZone,
+    /// The first day of the week, for anything that is grouped by week.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub week_start: Weekday,
+    pub output_format: OutputFormat,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            zone: DisplayZone::Local,
+            week_start: Weekday::Mon,
+            output_format: OutputFormat::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Display, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// A human-readable listing.
+    #[default]
+    #[display("text")]
+    Text,
+    /// The raw `Debug` representation of the data.
+    #[display("debug")]
+    Debug,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// When tasks are normally worked on; scheduling a task outside of them
+    /// asks for a second look.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub working_hours: WorkingHours,
+    /// If set, the duration in minutes given to events that are created with
+    /// only a start time. Otherwise such events are instantaneous.
+    #[serde(deserialize_with = "deserialize_minutes")]
+    pub default_event_duration: Option<TimeDelta>,
+    /// How to read times that happen twice or not at all because the clocks
+    /// change: `earliest`, `latest`, `reject` or `shift-forward`.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub disambiguation: Disambiguation,
+    /// How to pick the year of a date typed without one: `current`, `next` or
+    /// `nearest`.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub year_inference: YearInference,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            working_hours: WorkingHours {
+                start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            },
+            default_event_duration: None,
+            disambiguation: Disambiguation::Reject,
+            year_inference: YearInference::Next,
+        }
+    }
+}
+
+/// Settings for `metimed`, the daemon that delivers alarms.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub notifier: NotifierConfig,
+    /// Where the daemon remembers up to when it has delivered alarms.
+    /// Defaults to `$XDG_STATE_HOME/metime/metimed.state`.
+    pub state_path: Option<PathBuf>,
+    /// The longest time in seconds that the daemon waits before reloading
+    /// the repository to look for new alarms.
+    pub poll_interval: u32,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            notifier: NotifierConfig::default(),
+            state_path: None,
+            poll_interval: 60,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// The configured state path, or the default one if there is none.
+    pub fn state_path(&self) -> Option<PathBuf> {
+        self.state_path.clone().or_else(|| {
+            dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|dir| dir.join("metime").join("metimed.state"))
+        })
+    }
+}
+
+/// Settings for time tracking.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerConfig {
+    /// Where the running timer is kept between sessions. Defaults to
+    /// `$XDG_STATE_HOME/metime/tracker.state`.
+    pub state_path: Option<PathBuf>,
+}
+
+impl TrackerConfig {
+    /// The configured state path, or the default one if there is none.
+    pub fn state_path(&self) -> Option<PathBuf> {
+        self.state_path.clone().or_else(|| {
+            dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|dir| dir.join("metime").join("tracker.state"))
+        })
+    }
+}
+
+/// Settings for syncing with a CalDAV calendar.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    /// The URL of the calendar to sync with by default.
+    pub url: Option<String>,
+    /// Which side wins when an event was changed both locally and on the
+    /// server: `local` or `remote`.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub conflict: ConflictPolicy,
+    /// Where the ETags and sync tokens of each calendar are kept between
+    /// sessions. Defaults to `$XDG_STATE_HOME/metime/sync`.
+    pub state_dir: Option<PathBuf>,
+}
+
+impl SyncConfig {
+    /// The configured state directory, or the default one if there is none.
+    pub fn state_dir(&self) -> Option<PathBuf> {
+        self.state_dir.clone().or_else(|| {
+            dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|dir| dir.join("metime").join("sync"))
+        })
+    }
+}
+
+/// How alarms are delivered.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum NotifierConfig {
+    /// Print each alarm to standard output.
+    #[default]
+    Stdout,
+    /// Run a program for each alarm, with the alarm message as the last
+    /// argument.
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Append each alarm as a line to a file.
+    Log { path: PathBuf },
+}
+
+impl Display for NotifierConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifierConfig::Stdout => f.write_str("stdout"),
+            NotifierConfig::Command { program, args } => {
+                write!(f, "command {program:?}")?;
+                args.iter().try_for_each(|arg| write!(f, " {arg:?}"))
+            }
+            NotifierConfig::Log { path } => write!(f, "log {}", path.display()),
+        }
+    }
+}
+
+/// The time zone used for displaying times.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DisplayZone {
+    /// The local time zone of the machine.
+    Local,
+    Fixed(FixedOffset),
+    Named(chrono_tz::Tz),
+}
+
+impl DisplayZone {
+    /// Formats an instant as wall-clock time in this zone.
+    pub fn format(&self, time: DateTime<Utc>, fmt: &str) -> String {
+        match self {
+            DisplayZone::Local => time.with_timezone(&Local).format(fmt).to_string(),
+            DisplayZone::Fixed(offset) => time.with_timezone(offset).format(fmt).to_string(),
+            DisplayZone::Named(tz) => time.with_timezone(tz).format(fmt).to_string(),
+        }
+    }
+
+    /// The wall-clock time of an instant in this zone.
+    pub fn naive_local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            DisplayZone::Local => time.with_timezone(&Local).naive_local(),
+            DisplayZone::Fixed(offset) => time.with_timezone(offset).naive_local(),
+            DisplayZone::Named(tz) => time.with_timezone(tz).naive_local(),
+        }
+    }
+}
+
+impl FromStr for DisplayZone {
+    type Err = String;
+
+    /// Accepts `local`, `UTC`, a fixed offset such as `+02:00`, or an IANA
+    /// time zone name such as `Europe/Paris`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(DisplayZone::Local);
+        }
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(DisplayZone::Fixed(FixedOffset::east_opt(0).unwrap()));
+        }
+        if s.starts_with(['+', '-']) {
+            return s
+                .parse()
+                .map(DisplayZone::Fixed)
+                .map_err(|_| format!("invalid UTC offset: {s}"));
+        }
+        s.parse()
+            .map(DisplayZone::Named)
+            .map_err(|_| format!("unknown time zone: {s}"))
+    }
+}
+
+impl TryFrom<String> for DisplayZone {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for DisplayZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayZone::Local => f.write_str("local"),
+            DisplayZone::Fixed(offset) => offset.fmt(f),
+            DisplayZone::Named(tz) => tz.fmt(f),
+        }
+    }
+}
+
+/// The part of each day that is normally spent working, written `HH:MM-HH:MM`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Display)]
+#[display("{}-{}", start.format("%H:%M"), end.format("%H:%M"))]
+pub struct WorkingHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl WorkingHours {
+    /// Whether the time span lies within the working hours of a single day,
+    /// as seen in `zone`.
+    pub fn contain(&self, time_span: TimeSpan, zone: &DisplayZone) -> bool {
+        let start = zone.naive_local(time_span.earliest());
+        let end = zone.naive_local(time_span.latest());
+        start.date() == end.date() && self.start <= start.time() && end.time() <= self.end
+    }
+}
+
+impl FromStr for WorkingHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_time = |t: &str| {
+            NaiveTime::parse_from_str(t.trim(), "%H:%M")
+                .map_err(|_| format!("invalid time of day: {t}"))
+        };
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected HH:MM-HH:MM, got: {s}"))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if end <= start {
+            return Err(format!("working hours end before they start: {s}"));
+        }
+        Ok(WorkingHours { start, end })
+    }
+}
+
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(de::Error::custom)
+}
+
+fn deserialize_minutes<'de, D>(deserializer: D) -> Result<Option<TimeDelta>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let minutes = u32::deserialize(deserializer)?;
+    if minutes == 0 {
+        return Err(de::Error::custom("default event duration must be positive"));
+    }
+    Ok(Some(TimeDelta::minutes(minutes.into())))
+}
+
+#[derive(Debug, Display, From, Error)]
+pub enum ConfigError {
+    #[display("could not read config file {}: {source}", path.display())]
+    #[from(ignore)]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[display("invalid config file: {_0}")]
+    Parse(toml::de::Error),
+}
+
+/// The location of the config file when none is given explicitly, following
+/// the XDG base directory specification (`$XDG_CONFIG_HOME/metime/config.toml`).
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("metime").join("config.toml"))
+}
+
+/// Loads the config from `path`, or from the default location if no path is
+/// given. A missing file at the default location yields the default config;
+/// a missing file that was explicitly asked for is an error.
+pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+    let (path, explicit) = match path {
+        Some(path) => (path.to_owned(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Config::default());
+        }
+        Err(source) => return Err(ConfigError::Io { path, source }),
+    };
+    Ok(toml::from_str(&contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.prompt, "metime");
+        assert_eq!(config.storage.backend, StorageBackend::Memory);
+        assert_eq!(config.display.zone, DisplayZone::Local);
+        assert_eq!(config.display.week_start, Weekday::Mon);
+        assert_eq!(config.schedule.default_event_duration, None);
+        assert_eq!(config.schedule.disambiguation, Disambiguation::Reject);
+        assert_eq!(config.schedule.year_inference, YearInference::Next);
+    }
+
+    #[test]
+    fn full_config() {
+        let config: Config = toml::from_str(
+            r#"
+            prompt = "cal"
+
+            [storage]
+            backend = "memory"
+            path = "/tmp/metime"
+
+            [display]
+            zone = "Europe/Paris"
+            week_start = "sunday"
+            output_format = "debug"
+
+            [schedule]
+            working_hours = "08:30-16:00"
+            default_event_duration = 45
+            disambiguation = "shift-forward"
+            year_inference = "nearest"
+
+            [daemon]
+            poll_interval = 300
+
+            [tracker]
+            state_path = "/tmp/metime/tracker.state"
+
+            [sync]
+            url = "http://localhost:5232/calendars/metime/"
+            conflict = "local"
+
+            [daemon.notifier]
+            kind = "command"
+            program = "notify-send"
+            args = ["metime"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.prompt, "cal");
+        assert_eq!(config.storage.path, Some(PathBuf::from("/tmp/metime")));
+        assert_eq!(
+            config.display.zone,
+            DisplayZone::Named(chrono_tz::Europe::Paris)
+        );
+        assert_eq!(config.display.week_start, Weekday::Sun);
+        assert_eq!(config.display.output_format, OutputFormat::Debug);
+        assert_eq!(
+            config.schedule.working_hours.to_string(),
+            "08:30-16:00".to_owned()
+        );
+        assert_eq!(
+            config.schedule.default_event_duration,
+            Some(TimeDelta::minutes(45))
+        );
+        assert_eq!(config.schedule.disambiguation, Disambiguation::ShiftForward);
+        assert_eq!(config.schedule.year_inference, YearInference::Nearest);
+        assert_eq!(config.daemon.poll_interval, 300);
+        assert_eq!(
+            config.tracker.state_path(),
+            Some(PathBuf::from("/tmp/metime/tracker.state"))
+        );
+        assert_eq!(
+            config.sync.url.as_deref(),
+            Some("http://localhost:5232/calendars/metime/")
+        );
+        assert_eq!(config.sync.conflict, ConflictPolicy::Local);
+        assert_eq!(
+            config.daemon.notifier,
+            NotifierConfig::Command {
+                program: "notify-send".to_owned(),
+                args: vec!["metime".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn fixed_offset_zone() {
+        assert_eq!(
+            "+02:00".parse::<DisplayZone>(),
+            Ok(DisplayZone::Fixed(FixedOffset::east_opt(7200).unwrap()))
+        );
+        assert_eq!(
+            "utc".parse::<DisplayZone>(),
+            Ok(DisplayZone::Fixed(FixedOffset::east_opt(0).unwrap()))
+        );
+    }
+
+    #[test]
+    fn working_hours_contain_spans_of_a_single_day() {
+        let hours: WorkingHours = "09:00-17:00".parse().unwrap();
+        let paris = DisplayZone::Named(chrono_tz::Europe::Paris);
+        let at = |hour| Utc.with_ymd_and_hms(2024, 6, 10, hour, 0, 0).unwrap();
+        let span = |start, hours| TimeSpan::Interval {
+            start: at(start),
+            duration: TimeDelta::hours(hours),
+        };
+        // Paris is two hours ahead of UTC in June
+        assert!(hours.contain(span(7, 8), &paris));
+        assert!(hours.contain(TimeSpan::Instant(at(15)), &paris));
+        assert!(!hours.contain(span(6, 1), &paris));
+        assert!(!hours.contain(span(14, 2), &paris));
+        assert!(!hours.contain(span(7, 24), &paris));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert!(toml::from_str::<Config>("[display]\nzone = \"Mars/Olympus\"").is_err());
+        assert!(toml::from_str::<Config>("[schedule]\nworking_hours = \"17:00-09:00\"").is_err());
+        assert!(toml::from_str::<Config>("[storage]\nbackend = \"sqlite\"").is_err());
+        assert!(toml::from_str::<Config>("colour = true").is_err());
+        assert!(toml::from_str::<Config>("[daemon.notifier]\nkind = \"log\"").is_err());
+    }
+}
@@ -1,11 +1,38 @@
-use clap::Parser;
+use std::{path::PathBuf, sync::Arc};
+
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use clap::{Parser, Subcommand, ValueEnum};
 use clap_repl::{
     reedline::{DefaultPrompt, DefaultPromptSegment},
     ClapEditor,
 };
-use metime_core::MemoryRepo;
+use complete::ReplCompleter;
+use config::{Config, DisplayZone, OutputFormat};
+use metime_core::{
+    Alarm, AlarmTrigger, BodyId, CalDavClient, Calendar, CompiledFilter, ConflictPolicy, EventBody,
+    EventInstance, EventStatus, Filter, IndexingRepo, InstanceId, MemoryRepo, Outcome, Overlap,
+    Period, PlanComparison, Repository, ResolveIdError, SyncReport, SyncState, Task, TaskId,
+    TimeSpan, TimeSpanParseError, TimeSpanParseErrorKind, TimeSpanParser, Tracker,
+};
+use report::ReportFormat;
+
+mod complete;
+mod config;
+mod report;
+mod syncing;
+mod tracking;
 
-mod parse;
+/// The repository of the REPL, which keeps the search index up to date.
+type Repo = IndexingRepo<MemoryRepo>;
+
+/// Command-line arguments given when launching the REPL.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Path to the config file. Defaults to `$XDG_CONFIG_HOME/metime/config.toml`.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+}
 
 #[derive(Parser, Debug)]
 enum Command {
@@ -17,21 +44,265 @@ enum Command {
         time_span: String,
         #[arg(long, default_value = "")]
         desc: String,
+        #[arg(long, default_value = "")]
+        location: String,
+        /// A tag for grouping related events; may be given more than once.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long, default_value_t = EventStatus::Confirmed)]
+        status: EventStatus,
+        /// Set an alarm this many minutes before the event starts; may be
+        /// given more than once.
+        #[arg(long = "remind", value_name = "MINUTES")]
+        reminders: Vec<u32>,
+        /// The calendar to add the event to. Times without an offset are
+        /// read in the calendar's zone.
+        #[arg(long)]
+        calendar: Option<String>,
+    },
+    Show {
+        /// Only show events matching this filter, e.g.
+        /// `tag:work after:today before:+7d -status:cancelled`.
+        #[arg(long)]
+        filter: Option<String>,
+        /// Only show events in this calendar; may be given more than once.
+        #[arg(long = "calendar")]
+        calendars: Vec<String>,
+    },
+    /// Print the details of one event instance.
+    ShowEvent {
+        /// The ID of the instance, or enough of its start to be unambiguous.
+        id: String,
+    },
+    /// List the events whose title or description contain all of the given
+    /// words, or words starting with them.
+    Search {
+        #[arg(required = true)]
+        words: Vec<String>,
+        /// Only show events that also match this filter.
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Record how an event instance actually went.
+    Record {
+        /// The ID of the instance, or enough of its start to be unambiguous.
+        id: String,
+        /// When the event actually happened.
+        #[arg(long)]
+        actual: Option<String>,
+        /// One of `done`, `skipped` or `partial`.
+        #[arg(long)]
+        outcome: Option<Outcome>,
+    },
+    /// Compare the time planned for events with the time they actually took.
+    Review {
+        /// How many days to look back, including today.
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+        #[arg(long, value_enum, default_value_t = ReviewGrouping::Day)]
+        by: ReviewGrouping,
+    },
+    /// Show how time was spent, by tag, by period or by event.
+    Report {
+        #[arg(long, value_enum, default_value_t = ReportGrouping::Tag)]
+        by: ReportGrouping,
+        /// How many days to look back, including today.
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// List the alarms that go off in the coming hours.
+    Alarms {
+        #[arg(long, default_value_t = 24)]
+        hours: u32,
+    },
+    /// Mark an alarm as dealt with, so that it does not go off again.
+    AcknowledgeAlarm {
+        /// The ID of the instance, or enough of its start to be unambiguous.
+        id: String,
+        /// The start of the alarm's ID, as listed by `alarms`. May be left
+        /// out if the event has a single alarm.
+        alarm: Option<String>,
+    },
+    /// Put an alarm off, so that it goes off again later.
+    SnoozeAlarm {
+        /// The ID of the instance, or enough of its start to be unambiguous.
+        id: String,
+        /// The start of the alarm's ID, as listed by `alarms`. May be left
+        /// out if the event has a single alarm.
+        alarm: Option<String>,
+        /// How many minutes from now the alarm goes off again.
+        #[arg(long, default_value_t = 10)]
+        minutes: u32,
+    },
+    /// Add, list, complete and schedule tasks.
+    Task {
+        #[command(subcommand)]
+        command: TaskCommand,
+    },
+    /// Add and list calendars, like work, personal or holidays.
+    Calendar {
+        #[command(subcommand)]
+        command: CalendarCommand,
+    },
+    /// Record the time spent on something as it happens.
+    Track {
+        #[command(subcommand)]
+        command: TrackCommand,
+    },
+    /// Write everything in the repository to a JSON file, or to standard
+    /// output if no file is given.
+    Dump {
+        path: Option<PathBuf>,
+    },
+    /// Add everything in a file written by `dump` to the repository.
+    Restore {
+        path: PathBuf,
+    },
+    /// Exchange changes with a CalDAV calendar.
+    Sync {
+        /// The URL of the calendar. Defaults to `sync.url` from the config.
+        url: Option<String>,
+        /// Which side wins when an event was changed on both: `local` or
+        /// `remote`. Defaults to `sync.conflict` from the config.
+        #[arg(long)]
+        conflict: Option<ConflictPolicy>,
+    },
+    /// Print the configuration currently in effect.
+    Config,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone)]
+enum ReviewGrouping {
+    Day,
+    Category,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone)]
+enum ReportGrouping {
+    Tag,
+    Day,
+    Week,
+    Month,
+    /// Each event, counting all instances of a recurring event together.
+    Event,
+}
+
+#[derive(Subcommand, Debug)]
+enum TrackCommand {
+    /// Start a timer, which records an event when it is stopped.
+    Start {
+        title: String,
+        #[arg(long, default_value = "")]
+        desc: String,
+        /// A tag for grouping related events; may be given more than once.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Stop the timer that is running, if any, instead of refusing to
+        /// start another one.
+        #[arg(long)]
+        switch: bool,
+    },
+    /// Stop the running timer and record the time it ran as an event.
+    Stop,
+    /// Show the running timer.
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+enum CalendarCommand {
+    /// Add an empty calendar.
+    Add {
+        name: String,
+        /// The colour to show the calendar's events in, like `#3465a4`.
+        #[arg(long, default_value = "")]
+        colour: String,
+        /// The zone that times for new events in the calendar are read in,
+        /// like `Europe/Berlin`. Defaults to the local zone.
+        #[arg(long)]
+        zone: Option<chrono_tz::Tz>,
+    },
+    /// List the calendars and how many events each has.
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum TaskCommand {
+    /// Add a task to the task list.
+    Add {
+        title: String,
+        /// When the task must be done by.
+        #[arg(long)]
+        due: Option<String>,
+        /// How many minutes the task is expected to take.
+        #[arg(long, value_name = "MINUTES")]
+        estimate: Option<u32>,
+        /// From 1 (highest) to 9 (lowest).
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=9))]
+        priority: Option<u8>,
+        #[arg(long, default_value = "")]
+        desc: String,
+        /// A tag for grouping related tasks; may be given more than once.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// List the tasks that are not done yet, by due date and then priority.
+    List {
+        /// Also list the tasks that are done.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Mark a task as done.
+    Done {
+        /// The ID of the task, or enough of its start to be unambiguous.
+        id: String,
+    },
+    /// Set aside time on the timeline for working on a task.
+    Schedule {
+        /// The ID of the task, or enough of its start to be unambiguous.
+        id: String,
+        #[arg(short, long)]
+        time_span: String,
     },
-    Show,
 }
 
 fn main() {
+    let args = Args::parse();
+    let config = match config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
     // initialize app state
-    let mut repo = MemoryRepo::new();
+    let repo = Arc::new(IndexingRepo::new(config.storage.open_repo()));
+    let tracker_path = config.tracker.state_path();
+    let mut tracker = match tracker_path.as_deref().map(tracking::load) {
+        Some(Ok(tracker)) => tracker,
+        Some(Err(e)) => {
+            eprintln!("could not read the running timer: {e}");
+            std::process::exit(1);
+        }
+        None => Tracker::new(),
+    };
+    // what the last sync left behind, along with the URL it was for
+    let sync_dir = config.sync.state_dir();
+    let mut sync_state: Option<(String, SyncState)> = None;
 
     // initialize REPL
     let prompt = DefaultPrompt {
-        left_prompt: DefaultPromptSegment::Basic("metime".to_owned()),
+        left_prompt: DefaultPromptSegment::Basic(config.prompt.clone()),
         ..Default::default()
     };
     let rl = ClapEditor::<Command>::builder()
         .with_prompt(Box::new(prompt))
+        .with_editor_hook({
+            let repo = Arc::clone(&repo);
+            |editor| editor.with_completer(Box::new(ReplCompleter::new(repo)))
+        })
         .build();
     rl.repl(|command| {
         println!("{:?}", command);
@@ -44,19 +315,1011 @@ fn main() {
                 time_span,
                 title,
                 desc,
+                location,
+                tags,
+                status,
+                reminders,
+                calendar,
             } => {
-                let Some(time_span) = parse::parse_lenient_time_span(&time_span) else {
-                    println!("Failed to parse date/time: {}", time_span);
-                    return;
+                let zone = match &calendar {
+                    Some(name) => match repo.get_calendar_list().unwrap().get(name) {
+                        Some(calendar) => calendar.default_zone,
+                        None => {
+                            println!("There is no calendar named {name:?}");
+                            return;
+                        }
+                    },
+                    None => None,
                 };
+                let parsed = match zone {
+                    Some(zone) => parse_time_span(&time_span, zone, &config),
+                    None => parse_time_span(&time_span, Local, &config),
+                };
+                let mut time_span = match parsed {
+                    Ok(time_span) => time_span,
+                    Err(e) => {
+                        show_parse_error(&time_span, &e);
+                        return;
+                    }
+                };
+                if let (TimeSpan::Instant(start), Some(duration)) =
+                    (&time_span, config.schedule.default_event_duration)
+                {
+                    time_span = TimeSpan::Interval {
+                        start: *start,
+                        duration,
+                    };
+                }
 
                 println!("Creating event at: {}", time_span);
 
-                let _ = metime_core::add_event(&mut repo, time_span, title, desc);
+                let (instance_id, _, _, mut body) =
+                    metime_core::add_event(&*repo, time_span, title, desc);
+                if let Some(name) = &calendar {
+                    metime_core::move_to_calendar(&*repo, instance_id, name).unwrap();
+                }
+                body.location = location;
+                body.categories = tags;
+                body.status = status;
+                body.alarms = reminders
+                    .into_iter()
+                    .map(|minutes| {
+                        let trigger = AlarmTrigger::Start(-TimeDelta::minutes(minutes.into()));
+                        Alarm::new(trigger, String::new())
+                    })
+                    .collect();
+            }
+            Command::Show {
+                filter: None,
+                calendars,
+            } if calendars.is_empty() && config.display.output_format == OutputFormat::Debug => {
+                println!("{:#?}", repo.inner());
+            }
+            Command::Show { filter, calendars } => {
+                let mut ids = match filter {
+                    Some(filter) => match compile_filter(&filter, &config) {
+                        Ok(filter) => metime_core::filter_events(&*repo, &filter),
+                        Err(e) => {
+                            println!("{e}");
+                            return;
+                        }
+                    },
+                    None => {
+                        let mut ids = Vec::new();
+                        for_each_event(&repo, |id, _, _| ids.push(id));
+                        ids
+                    }
+                };
+                if !calendars.is_empty() {
+                    let names: Vec<_> = calendars.iter().map(String::as_str).collect();
+                    match metime_core::events_in_calendars(&*repo, &names) {
+                        Ok(visible) => ids.retain(|id| visible.contains(id)),
+                        Err(e) => {
+                            println!("{e}");
+                            return;
+                        }
+                    }
+                }
+                if ids.is_empty() {
+                    println!("No events.");
+                }
+                for id in ids {
+                    show_event_line(&repo, id, &config);
+                }
             }
-            Command::Show => {
-                println!("{:#?}", &repo);
+            Command::ShowEvent { id } => {
+                let id = match resolve_instance_id(&repo, &id) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                };
+                let calendar = metime_core::calendar_of(&*repo, id);
+                let instance = repo.get_event_instance(id).unwrap();
+                let body = repo.get_event_body(instance.body).unwrap();
+                match config.display.output_format {
+                    OutputFormat::Text => {
+                        show_event(id, &instance, &body, calendar.as_deref(), &config)
+                    }
+                    OutputFormat::Debug => println!("{:#?}\n{:#?}", &*instance, &*body),
+                }
+            }
+            Command::Search { words, filter } => {
+                let filter = match filter.map(|filter| compile_filter(&filter, &config)) {
+                    Some(Ok(filter)) => Some(filter),
+                    Some(Err(e)) => {
+                        println!("{e}");
+                        return;
+                    }
+                    None => None,
+                };
+                let mut found = metime_core::search_events(&*repo, &repo.index(), &words.join(" "));
+                if let Some(filter) = filter {
+                    found.retain(|&id| {
+                        let instance = repo.get_event_instance(id).unwrap();
+                        let body = repo.get_event_body(instance.body).unwrap();
+                        filter.matches(&instance, &body)
+                    });
+                }
+                if found.is_empty() {
+                    println!("No matching events.");
+                }
+                for id in found {
+                    show_event_line(&repo, id, &config);
+                }
+            }
+            Command::Record {
+                id,
+                actual,
+                outcome,
+            } => {
+                let id = match resolve_instance_id(&repo, &id) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                };
+                let actual = actual
+                    .map(|actual| parse_time_span(&actual, Local, &config).map_err(|e| (actual, e)))
+                    .transpose();
+                let actual = match actual {
+                    Ok(actual) => actual,
+                    Err((input, e)) => {
+                        show_parse_error(&input, &e);
+                        return;
+                    }
+                };
+                if actual.is_none() && outcome.is_none() {
+                    println!("Nothing to record; give --actual or --outcome.");
+                    return;
+                }
+                let mut instance = repo.get_event_instance(id).unwrap();
+                if actual.is_some() {
+                    instance.actual = actual;
+                }
+                if outcome.is_some() {
+                    instance.outcome = outcome;
+                }
+                drop(instance);
+                show_event_line(&repo, id, &config);
+            }
+            Command::Review { days, by } => {
+                let now = Utc::now();
+                let rows = match config.display.zone {
+                    DisplayZone::Local => review(&repo, now.with_timezone(&Local), days, by),
+                    DisplayZone::Fixed(offset) => {
+                        review(&repo, now.with_timezone(&offset), days, by)
+                    }
+                    DisplayZone::Named(tz) => review(&repo, now.with_timezone(&tz), days, by),
+                };
+                if rows.is_empty() {
+                    println!("No events in the last {days} days.");
+                }
+                for (label, comparison) in rows {
+                    show_comparison(&label, &comparison);
+                }
+            }
+            Command::Report { by, days, format } => {
+                let now = Utc::now();
+                let result = match config.display.zone {
+                    DisplayZone::Local => {
+                        usage_report(&repo, now.with_timezone(&Local), days, by, format, &config)
+                    }
+                    DisplayZone::Fixed(offset) => {
+                        usage_report(&repo, now.with_timezone(&offset), days, by, format, &config)
+                    }
+                    DisplayZone::Named(tz) => {
+                        usage_report(&repo, now.with_timezone(&tz), days, by, format, &config)
+                    }
+                };
+                if let Err(e) = result {
+                    println!("could not write the report: {e}");
+                }
+            }
+            Command::Alarms { hours } => {
+                let now = Utc::now();
+                let due =
+                    metime_core::alarms_due(&*repo, now, now + TimeDelta::hours(hours.into()));
+                if due.is_empty() {
+                    println!("No alarms in the next {hours} hours.");
+                }
+                for alarm in due {
+                    println!(
+                        "{}  {} {}  {}",
+                        config.display.zone.format(alarm.time, "%a %Y-%m-%d %H:%M"),
+                        alarm.instance.short(),
+                        short_alarm_id(&alarm.alarm),
+                        alarm.message
+                    );
+                }
+            }
+            Command::AcknowledgeAlarm { id, alarm } => {
+                let (id, uid) = match resolve_alarm(&repo, &id, alarm.as_deref()) {
+                    Ok(found) => found,
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                };
+                let mut instance = repo.get_event_instance(id).unwrap();
+                instance.acknowledge_alarm(&uid, Utc::now());
+                println!("Acknowledged alarm {}.", short_alarm_id(&uid));
+            }
+            Command::SnoozeAlarm { id, alarm, minutes } => {
+                let (id, uid) = match resolve_alarm(&repo, &id, alarm.as_deref()) {
+                    Ok(found) => found,
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                };
+                let until = Utc::now() + TimeDelta::minutes(minutes.into());
+                let mut instance = repo.get_event_instance(id).unwrap();
+                instance.snooze_alarm(&uid, until);
+                println!(
+                    "Snoozed alarm {} until {}.",
+                    short_alarm_id(&uid),
+                    config.display.zone.format(until, "%a %Y-%m-%d %H:%M")
+                );
+            }
+            Command::Task { command } => run_task_command(&repo, command, &config),
+            Command::Calendar { command } => run_calendar_command(&repo, command),
+            Command::Track { command } => {
+                let stopped = match command {
+                    TrackCommand::Start {
+                        title,
+                        desc,
+                        tags,
+                        switch,
+                    } => {
+                        let body = EventBody {
+                            summary: title,
+                            description: desc,
+                            location: String::new(),
+                            categories: tags,
+                            status: EventStatus::default(),
+                            alarms: Vec::new(),
+                        };
+                        let overlap = if switch {
+                            Overlap::AutoClose
+                        } else {
+                            Overlap::Refuse
+                        };
+                        tracker.start(&*repo, body, Utc::now(), overlap)
+                    }
+                    TrackCommand::Stop => tracker.stop(&*repo, Utc::now()).map(Some),
+                    TrackCommand::Status => Ok(None),
+                };
+                match stopped {
+                    Ok(Some(id)) => {
+                        print!("Recorded ");
+                        show_event_line(&repo, id, &config);
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                }
+                if let Some(path) = &tracker_path {
+                    if let Err(e) = tracking::save(path, &tracker) {
+                        println!("could not save the running timer: {e}");
+                    }
+                }
+                show_timer(&tracker, &config);
+            }
+            Command::Dump { path } => {
+                let result = match &path {
+                    Some(path) => std::fs::File::create(path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|file| {
+                            metime_core::export_repo(&*repo, std::io::BufWriter::new(file))
+                                .map_err(|e| e.to_string())
+                        }),
+                    None => metime_core::export_repo(&*repo, std::io::stdout().lock())
+                        .map(|()| println!())
+                        .map_err(|e| e.to_string()),
+                };
+                match (result, path) {
+                    (Err(e), _) => println!("Failed to dump the repository: {e}"),
+                    (Ok(()), Some(path)) => println!("Dumped the repository to {}", path.display()),
+                    (Ok(()), None) => {}
+                }
+            }
+            Command::Restore { path } => {
+                let result = std::fs::File::open(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|file| {
+                        metime_core::import_repo(&*repo, std::io::BufReader::new(file))
+                            .map_err(|e| e.to_string())
+                    });
+                if let Err(e) = result {
+                    println!("Failed to restore {}: {e}", path.display());
+                    return;
+                }
+                println!("Restored {}", path.display());
+            }
+            Command::Sync { url, conflict } => {
+                let Some(url) = url.or_else(|| config.sync.url.clone()) else {
+                    println!("No calendar to sync with; give a URL or set sync.url");
+                    return;
+                };
+                let client = match CalDavClient::new(&url) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        println!("{e}");
+                        return;
+                    }
+                };
+                if sync_state.as_ref().is_none_or(|(synced, _)| *synced != url) {
+                    let state = match sync_dir.as_deref().map(|dir| syncing::load(dir, &url)) {
+                        Some(Ok(state)) => state,
+                        Some(Err(e)) => {
+                            println!("could not read the state of the last sync: {e}");
+                            return;
+                        }
+                        None => SyncState::new(),
+                    };
+                    sync_state = Some((url.clone(), state));
+                }
+                let (_, state) = sync_state.as_mut().unwrap();
+                let policy = conflict.unwrap_or(config.sync.conflict);
+                let result = metime_core::sync_caldav(&*repo, &client, state, policy);
+                // even a failed sync may have changed some resources
+                if let Some(dir) = &sync_dir {
+                    if let Err(e) = syncing::save(dir, &url, state) {
+                        println!("could not save the state of the sync: {e}");
+                    }
+                }
+                match result {
+                    Ok(report) => {
+                        show_sync_report(&report);
+                    }
+                    Err(e) => println!("Failed to sync with {url}: {e}"),
+                }
             }
+            Command::Config => show_config(&config),
         }
     })
 }
+
+fn run_calendar_command(repo: &Repo, command: CalendarCommand) {
+    match command {
+        CalendarCommand::Add { name, colour, zone } => {
+            let calendar = Calendar {
+                colour,
+                default_zone: zone,
+                ..Calendar::new(name.clone())
+            };
+            match metime_core::add_calendar(repo, calendar) {
+                Ok(()) => println!("Added calendar {name}"),
+                Err(e) => println!("{e}"),
+            }
+        }
+        CalendarCommand::List => {
+            let calendar_list = repo.get_calendar_list().unwrap();
+            if calendar_list.calendars.is_empty() {
+                println!("No calendars.");
+            }
+            for calendar in &calendar_list.calendars {
+                let zone = match calendar.default_zone {
+                    Some(zone) => zone.name().to_owned(),
+                    None => "local".to_owned(),
+                };
+                println!(
+                    "{}  {:>3} events  {zone}",
+                    paint(&format!("{:<16}", calendar.name), &calendar.colour),
+                    calendar.events.len()
+                );
+            }
+        }
+    }
+}
+
+fn run_task_command(repo: &Repo, command: TaskCommand, config: &Config) {
+    match command {
+        TaskCommand::Add {
+            title,
+            due,
+            estimate,
+            priority,
+            desc,
+            tags,
+        } => {
+            let due = due
+                .map(|due| parse_time_span(&due, Local, config).map_err(|e| (due, e)))
+                .transpose();
+            let due = match due {
+                Ok(due) => due,
+                Err((input, e)) => {
+                    show_parse_error(&input, &e);
+                    return;
+                }
+            };
+            let task = Task {
+                description: desc,
+                categories: tags,
+                due,
+                estimated_duration: estimate.map(|minutes| TimeDelta::minutes(minutes.into())),
+                priority,
+                ..Task::new(title)
+            };
+            let (id, _) = metime_core::add_task(repo, task);
+            println!("Added task {}", id.short());
+        }
+        TaskCommand::List { all } => {
+            let mut tasks = Vec::new();
+            for_each_task(repo, |id, task| {
+                if all || !task.is_completed() {
+                    let due = task.due.map(|due| due.earliest());
+                    tasks.push((due.is_none(), due, task.priority.unwrap_or(u8::MAX), id));
+                }
+            });
+            tasks.sort();
+            if tasks.is_empty() {
+                println!("No tasks.");
+            }
+            for (.., id) in tasks {
+                show_task_line(repo, id, config);
+            }
+        }
+        TaskCommand::Done { id } => {
+            let id = match resolve_task_id(repo, &id) {
+                Ok(id) => id,
+                Err(e) => {
+                    println!("{e}");
+                    return;
+                }
+            };
+            repo.get_task(id).unwrap().complete(Utc::now());
+            show_task_line(repo, id, config);
+        }
+        TaskCommand::Schedule { id, time_span } => {
+            let id = match resolve_task_id(repo, &id) {
+                Ok(id) => id,
+                Err(e) => {
+                    println!("{e}");
+                    return;
+                }
+            };
+            let mut time_span = match parse_time_span(&time_span, Local, config) {
+                Ok(time_span) => time_span,
+                Err(e) => {
+                    show_parse_error(&time_span, &e);
+                    return;
+                }
+            };
+            // a bare start time lasts as long as the task is expected to take
+            let estimate = repo.get_task(id).unwrap().estimated_duration;
+            if let (TimeSpan::Instant(start), Some(duration)) = (
+                &time_span,
+                estimate.or(config.schedule.default_event_duration),
+            ) {
+                time_span = TimeSpan::Interval {
+                    start: *start,
+                    duration,
+                };
+            }
+            match metime_core::schedule_task(repo, id, time_span) {
+                Ok(instance_id) => {
+                    show_event_line(repo, instance_id, config);
+                    let hours = config.schedule.working_hours;
+                    if !hours.contain(time_span, &config.display.zone) {
+                        println!("Note: this is outside the working hours ({hours}).");
+                    }
+                }
+                Err(e) => println!("{e}"),
+            }
+        }
+    }
+}
+
+/// Calls `f` with every event instance on the timeline, in order of time,
+/// along with its ID and body.
+fn for_each_event(repo: &Repo, mut f: impl FnMut(InstanceId, &EventInstance<BodyId>, &EventBody)) {
+    let Some(timeline) = repo.get_timeline() else {
+        return;
+    };
+    for &instance_id in timeline.events.values() {
+        let Ok(instance) = repo.get_event_instance(instance_id) else {
+            continue;
+        };
+        let Ok(body) = repo.get_event_body(instance.body) else {
+            continue;
+        };
+        f(instance_id, &instance, &body);
+    }
+}
+
+/// Calls `f` with every task on the task list, in the order they were added.
+fn for_each_task(repo: &Repo, mut f: impl FnMut(TaskId, &Task<InstanceId>)) {
+    let Some(task_list) = repo.get_task_list() else {
+        return;
+    };
+    for &task_id in &task_list.tasks {
+        let Ok(task) = repo.get_task(task_id) else {
+            continue;
+        };
+        f(task_id, &task);
+    }
+}
+
+/// Finds the instance whose ID starts with `prefix`, which must match exactly
+/// one instance.
+fn resolve_instance_id(repo: &Repo, prefix: &str) -> Result<InstanceId, ResolveIdError> {
+    let mut ids = Vec::new();
+    for_each_event(repo, |id, _, _| ids.push(id));
+    InstanceId::resolve(ids, prefix)
+}
+
+/// Finds an alarm of the instance whose ID starts with `id`, by the start of
+/// its `uid`, or the only alarm of the instance if no `prefix` is given.
+/// Returns the instance and the `uid` of the alarm.
+fn resolve_alarm(
+    repo: &Repo,
+    id: &str,
+    prefix: Option<&str>,
+) -> Result<(InstanceId, String), ResolveIdError> {
+    let id = resolve_instance_id(repo, id)?;
+    let instance = repo.get_event_instance(id).unwrap();
+    let body = repo.get_event_body(instance.body).unwrap();
+    let prefix = prefix.unwrap_or_default().to_lowercase();
+    let mut matches: Vec<_> = instance
+        .all_alarms(&body)
+        .map(|alarm| &alarm.uid)
+        .filter(|uid| uid.to_lowercase().starts_with(&prefix))
+        .collect();
+    match matches.len() {
+        0 => Err(ResolveIdError::NoMatch {
+            what: "alarm",
+            prefix,
+        }),
+        1 => Ok((id, matches.remove(0).clone())),
+        count => Err(ResolveIdError::Ambiguous {
+            what: "alarm",
+            prefix,
+            count,
+        }),
+    }
+}
+
+/// The start of the `uid` of an alarm, enough to tell it apart from the other
+/// alarms of its event.
+fn short_alarm_id(uid: &str) -> &str {
+    uid.get(..8).unwrap_or(uid)
+}
+
+/// Finds the task whose ID starts with `prefix`, which must match exactly one
+/// task.
+fn resolve_task_id(repo: &Repo, prefix: &str) -> Result<TaskId, ResolveIdError> {
+    let mut ids = Vec::new();
+    for_each_task(repo, |id, _| ids.push(id));
+    TaskId::resolve(ids, prefix)
+}
+
+/// Parses a filter, resolving its dates and times in the display zone the way
+/// time spans typed by the user are.
+fn compile_filter(filter: &str, config: &Config) -> Result<CompiledFilter, String> {
+    let filter: Filter = filter.parse().map_err(|e| format!("{e}"))?;
+    let now = Utc::now();
+    let disambiguation = config.schedule.disambiguation;
+    match config.display.zone {
+        DisplayZone::Local => filter.compile(&now.with_timezone(&Local), disambiguation),
+        DisplayZone::Fixed(offset) => filter.compile(&now.with_timezone(&offset), disambiguation),
+        DisplayZone::Named(tz) => filter.compile(&now.with_timezone(&tz), disambiguation),
+    }
+    .map_err(|e| format!("{e}"))
+}
+
+/// Parses a time span typed by the user, reading times without an offset in
+/// `zone`.
+fn parse_time_span<Tz: TimeZone>(
+    input: &str,
+    zone: Tz,
+    config: &Config,
+) -> Result<TimeSpan, TimeSpanParseError> {
+    TimeSpanParser::new(Utc::now().with_timezone(&zone))
+        .with_disambiguation(config.schedule.disambiguation)
+        .with_year_inference(config.schedule.year_inference)
+        .parse(input)
+}
+
+/// Prints why a time span couldn't be parsed, with a caret under the part of
+/// the input that is wrong.
+fn show_parse_error(input: &str, error: &TimeSpanParseError) {
+    let column = |offset: usize| input.get(..offset).map_or(0, |text| text.chars().count());
+    let (start, end) = (column(error.position.start), column(error.position.end));
+    println!("Failed to parse date/time:");
+    println!("  {input}");
+    println!(
+        "  {}{} {}",
+        " ".repeat(start),
+        "^".repeat((end - start).max(1)),
+        error.kind
+    );
+    if let TimeSpanParseErrorKind::AmbiguousTime {
+        local,
+        earliest,
+        latest,
+    } = error.kind
+    {
+        // the same wall clock time with each of the offsets it had
+        let with_offset = |time: DateTime<Utc>| {
+            let offset = (local - time.naive_utc()).num_seconds() as i32;
+            FixedOffset::east_opt(offset)
+                .map(|offset| time.with_timezone(&offset).format("%Y-%m-%dT%H:%M%:z"))
+                .map_or_else(|| time.to_string(), |time| time.to_string())
+        };
+        println!(
+            "  it could be {} or {}",
+            with_offset(earliest),
+            with_offset(latest)
+        );
+    }
+    if let Some(help) = error.help() {
+        println!("  help: {help}");
+    }
+}
+
+fn format_time_span(time_span: &TimeSpan, config: &Config) -> String {
+    let zone = &config.display.zone;
+    let start = zone.format(time_span.earliest(), "%a %Y-%m-%d %H:%M");
+    match time_span {
+        TimeSpan::Instant(_) => start,
+        TimeSpan::Interval { .. } => {
+            format!("{start}-{}", zone.format(time_span.latest(), "%H:%M"))
+        }
+    }
+}
+
+/// Prints a one-line summary of an event instance.
+fn show_event_line(repo: &Repo, id: InstanceId, config: &Config) {
+    let instance = repo.get_event_instance(id).unwrap();
+    let body = repo.get_event_body(instance.body).unwrap();
+    if config.display.output_format == OutputFormat::Debug {
+        println!("{id}: {:?} {:?}", &*instance, &*body);
+        return;
+    }
+    let mut line = format!(
+        "{}  {:<26}  {}",
+        id.short(),
+        format_time_span(&instance.time_span, config),
+        body.summary
+    );
+    if !body.categories.is_empty() {
+        line += &format!(" [{}]", body.categories.join(", "));
+    }
+    if body.status != EventStatus::Confirmed {
+        line += &format!(" ({})", body.status);
+    }
+    if let Some(outcome) = instance.outcome {
+        line += &format!(" <{outcome}>");
+    }
+    if let Some(name) = metime_core::calendar_of(repo, id) {
+        let calendar_list = repo.get_calendar_list().unwrap();
+        let colour = calendar_list
+            .get(&name)
+            .map_or("", |calendar| &calendar.colour);
+        line += &format!(" {}", paint(&format!("@{name}"), colour));
+    }
+    println!("{line}");
+}
+
+/// Shows `text` in a colour like `#3465a4` when writing to a terminal, and
+/// as it is otherwise.
+fn paint(text: &str, colour: &str) -> String {
+    use std::io::IsTerminal;
+
+    let rgb = colour
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6)
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+    match rgb {
+        Some(rgb) if std::io::stdout().is_terminal() => format!(
+            "\x1b[38;2;{};{};{}m{text}\x1b[0m",
+            rgb >> 16,
+            (rgb >> 8) & 0xff,
+            rgb & 0xff
+        ),
+        _ => text.to_owned(),
+    }
+}
+
+/// Compares planned and actual time over the `days` days up to and including
+/// today, in the time zone of `now`.
+fn review<Tz: TimeZone>(
+    repo: &Repo,
+    now: DateTime<Tz>,
+    days: u32,
+    by: ReviewGrouping,
+) -> Vec<(String, PlanComparison)> {
+    let (from, to) = last_days(&now, days);
+    match by {
+        ReviewGrouping::Day => metime_core::compare_by_day(repo, &from, &to)
+            .into_iter()
+            .map(|(day, comparison)| (day.format("%a %Y-%m-%d").to_string(), comparison))
+            .collect(),
+        ReviewGrouping::Category => {
+            metime_core::compare_by_category(repo, from.to_utc(), to.to_utc())
+                .into_iter()
+                .map(|(category, comparison)| {
+                    (
+                        category.unwrap_or_else(|| "(untagged)".to_owned()),
+                        comparison,
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// Reports how time was used over the `days` days up to and including today,
+/// in the time zone of `now`.
+fn usage_report<Tz: TimeZone>(
+    repo: &Repo,
+    now: DateTime<Tz>,
+    days: u32,
+    by: ReportGrouping,
+    format: ReportFormat,
+    config: &Config,
+) -> std::io::Result<()> {
+    let (from, to) = last_days(&now, days);
+    let (from_utc, to_utc) = (from.to_utc(), to.to_utc());
+    // groups of tags and events are listed from the biggest down, and
+    // periods in order of time
+    let by_duration = |rows: &mut Vec<report::Row>| {
+        rows.sort_by(|a, b| {
+            b.minutes
+                .cmp(&a.minutes)
+                .then_with(|| a.group.cmp(&b.group))
+        })
+    };
+    let mut out = std::io::stdout().lock();
+    let mut write_periods = |period| {
+        let usage = metime_core::usage_by_period(repo, &from, &to, period);
+        let rows: Vec<_> = usage
+            .groups
+            .iter()
+            .map(|(start, &duration)| {
+                let group = match period {
+                    Period::Day => start.format("%a %Y-%m-%d").to_string(),
+                    Period::Week(_) => start.format("week of %Y-%m-%d").to_string(),
+                    Period::Month => start.format("%Y-%m").to_string(),
+                };
+                report::Row::new(&usage, group, duration)
+            })
+            .collect();
+        report::write(&mut out, format, &usage, &rows, from_utc, to_utc)
+    };
+    match by {
+        ReportGrouping::Day => write_periods(Period::Day),
+        ReportGrouping::Week => write_periods(Period::Week(config.display.week_start)),
+        ReportGrouping::Month => write_periods(Period::Month),
+        ReportGrouping::Tag => {
+            let usage = metime_core::usage_by_category(repo, from_utc, to_utc);
+            let mut rows: Vec<_> = usage
+                .groups
+                .iter()
+                .map(|(tag, &duration)| {
+                    let group = tag.clone().unwrap_or_else(|| "(untagged)".to_owned());
+                    report::Row::new(&usage, group, duration)
+                })
+                .collect();
+            by_duration(&mut rows);
+            report::write(&mut out, format, &usage, &rows, from_utc, to_utc)
+        }
+        ReportGrouping::Event => {
+            let usage = metime_core::usage_by_body(repo, from_utc, to_utc);
+            let mut rows: Vec<_> = usage
+                .groups
+                .iter()
+                .map(|(&body_id, &duration)| {
+                    let summary = repo.get_event_body(body_id).unwrap().summary.clone();
+                    report::Row::new(&usage, summary, duration)
+                })
+                .collect();
+            by_duration(&mut rows);
+            report::write(&mut out, format, &usage, &rows, from_utc, to_utc)
+        }
+    }
+}
+
+/// The window of the `days` days up to and including the day of `now`.
+fn last_days<Tz: TimeZone>(now: &DateTime<Tz>, days: u32) -> (DateTime<Tz>, DateTime<Tz>) {
+    let zone = now.timezone();
+    let today = now.date_naive();
+    let first = today - chrono::Days::new(days.saturating_sub(1).into());
+    let start_of_day =
+        |date| metime_core::start_of_day(&zone, date).expect("every day has an hour that exists");
+    (
+        start_of_day(first),
+        start_of_day(today + chrono::Days::new(1)),
+    )
+}
+
+fn format_duration(duration: TimeDelta) -> String {
+    let sign = if duration < TimeDelta::zero() {
+        "-"
+    } else {
+        ""
+    };
+    let minutes = duration.num_minutes().abs();
+    format!("{sign}{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+fn show_comparison(label: &str, comparison: &PlanComparison) {
+    let mut line = format!(
+        "{label:<16}  {:>3} events  planned {:>7}  actual {:>7}  over {:>7}",
+        comparison.instances,
+        format_duration(comparison.planned),
+        format_duration(comparison.actual),
+        format_duration(comparison.overrun()),
+    );
+    let counts: Vec<_> = [
+        (comparison.done, "done"),
+        (comparison.partial, "partial"),
+        (comparison.skipped, "skipped"),
+        (comparison.unrecorded, "unrecorded"),
+    ]
+    .into_iter()
+    .filter(|&(count, _)| count > 0)
+    .map(|(count, what)| format!("{count} {what}"))
+    .collect();
+    if !counts.is_empty() {
+        line += &format!("  ({})", counts.join(", "));
+    }
+    println!("{line}");
+}
+
+fn show_timer(tracker: &Tracker, config: &Config) {
+    let Some(running) = tracker.current() else {
+        println!("No timer is running.");
+        return;
+    };
+    let elapsed = Utc::now() - running.start;
+    let mut line = format!(
+        "Tracking {} since {} ({}h{:02}m)",
+        running.body.summary,
+        config
+            .display
+            .zone
+            .format(running.start, "%a %Y-%m-%d %H:%M"),
+        elapsed.num_hours(),
+        elapsed.num_minutes() % 60
+    );
+    if !running.body.categories.is_empty() {
+        line += &format!(" [{}]", running.body.categories.join(", "));
+    }
+    println!("{line}");
+}
+
+/// Prints a one-line summary of a task.
+fn show_task_line(repo: &Repo, id: TaskId, config: &Config) {
+    let task = repo.get_task(id).unwrap();
+    if config.display.output_format == OutputFormat::Debug {
+        println!("{id}: {:?}", &*task);
+        return;
+    }
+    let mark = if task.is_completed() {
+        "[x]".to_owned()
+    } else if task.percent_complete > 0 {
+        format!("[{}%]", task.percent_complete)
+    } else {
+        "[ ]".to_owned()
+    };
+    let due = match &task.due {
+        Some(due) => format_time_span(due, config),
+        None => String::new(),
+    };
+    let mut line = format!("{}  {mark:<5} {due:<26}  {}", id.short(), task.summary);
+    if let Some(priority) = task.priority {
+        line += &format!(" !{priority}");
+    }
+    if !task.categories.is_empty() {
+        line += &format!(" [{}]", task.categories.join(", "));
+    }
+    if task.scheduled.is_some() {
+        line += " (scheduled)";
+    }
+    println!("{line}");
+}
+
+fn show_event(
+    id: InstanceId,
+    instance: &EventInstance<BodyId>,
+    body: &EventBody,
+    calendar: Option<&str>,
+    config: &Config,
+) {
+    println!("id:          {id}");
+    println!(
+        "time:        {}",
+        format_time_span(&instance.time_span, config)
+    );
+    println!("title:       {}", body.summary);
+    println!("status:      {}", body.status);
+    if let Some(actual) = &instance.actual {
+        println!("actual:      {}", format_time_span(actual, config));
+    }
+    if let Some(outcome) = instance.outcome {
+        println!("outcome:     {outcome}");
+    }
+    if !body.description.is_empty() {
+        println!("description: {}", body.description);
+    }
+    if !body.location.is_empty() {
+        println!("location:    {}", body.location);
+    }
+    if !body.categories.is_empty() {
+        println!("tags:        {}", body.categories.join(", "));
+    }
+    if let Some(calendar) = calendar {
+        println!("calendar:    {calendar}");
+    }
+}
+
+fn show_sync_report(report: &SyncReport) {
+    println!(
+        "Pulled {}, pushed {}, deleted {} here and {} there, {} conflicts",
+        report.pulled,
+        report.pushed,
+        report.deleted_locally,
+        report.deleted_remotely,
+        report.conflicts
+    );
+    for skipped in &report.skipped {
+        println!("  skipped {skipped}");
+    }
+}
+
+fn show_config(config: &Config) {
+    let Config {
+        prompt,
+        storage,
+        display,
+        schedule,
+        daemon,
+        tracker,
+        sync,
+    } = config;
+    println!("prompt = {prompt:?}");
+    println!("storage.backend = {}", storage.backend);
+    match &storage.path {
+        Some(path) => println!("storage.path = {}", path.display()),
+        None => println!("storage.path = (none)"),
+    }
+    println!("display.zone = {}", display.zone);
+    println!("display.week_start = {}", display.week_start);
+    println!("display.output_format = {}", display.output_format);
+    println!("schedule.working_hours = {}", schedule.working_hours);
+    match schedule.default_event_duration {
+        Some(duration) => println!(
+            "schedule.default_event_duration = {}",
+            duration.num_minutes()
+        ),
+        None => println!("schedule.default_event_duration = (none)"),
+    }
+    println!("schedule.disambiguation = {}", schedule.disambiguation);
+    println!("schedule.year_inference = {}", schedule.year_inference);
+    println!("daemon.notifier = {}", daemon.notifier);
+    match daemon.state_path() {
+        Some(path) => println!("daemon.state_path = {}", path.display()),
+        None => println!("daemon.state_path = (none)"),
+    }
+    println!("daemon.poll_interval = {}", daemon.poll_interval);
+    match tracker.state_path() {
+        Some(path) => println!("tracker.state_path = {}", path.display()),
+        None => println!("tracker.state_path = (none)"),
+    }
+    match &sync.url {
+        Some(url) => println!("sync.url = {url}"),
+        None => println!("sync.url = (none)"),
+    }
+    println!("sync.conflict = {}", sync.conflict);
+    match sync.state_dir() {
+        Some(dir) => println!("sync.state_dir = {}", dir.display()),
+        None => println!("sync.state_dir = (none)"),
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::{self, Write};
+
+use chrono::prelude::*;
+use chrono::TimeDelta;
+use clap::ValueEnum;
+use metime_core::UsageReport;
+use serde::Serialize;
+
+/// How the output of the `report` command is written.
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// An aligned table for reading.
+    Text,
+    Csv,
+    Json,
+}
+
+/// One group of a usage report, ready for output.
+#[derive(Debug, Serialize)]
+pub struct Row {
+    pub group: String,
+    pub minutes: i64,
+    pub percent: f64,
+}
+
+impl Row {
+    pub fn new<K>(report: &UsageReport<K>, group: String, duration: TimeDelta) -> Self {
+        Self {
+            group,
+            minutes: duration.num_minutes(),
+            // one decimal is plenty, and keeps CSV and JSON output tidy
+            percent: (report.percentage(duration) * 10.0).round() / 10.0,
+        }
+    }
+}
+
+/// Writes a usage report over `[from, to)` whose groups have been turned into
+/// `rows`.
+pub fn write<K>(
+    out: &mut impl Write,
+    format: ReportFormat,
+    report: &UsageReport<K>,
+    rows: &[Row],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Text => {
+            let width = rows.iter().map(|row| row.group.chars().count()).max();
+            let width = width.unwrap_or(0).max("covered".len());
+            for row in rows {
+                writeln!(
+                    out,
+                    "{:<width$}  {:>7}  {:>5.1}%",
+                    row.group,
+                    format_minutes(row.minutes),
+                    row.percent
+                )?;
+            }
+            writeln!(
+                out,
+                "{:<width$}  {:>7}",
+                "total",
+                format_minutes(report.total.num_minutes())
+            )?;
+            writeln!(
+                out,
+                "{:<width$}  {:>7}",
+                "covered",
+                format_minutes(report.covered.num_minutes())
+            )
+        }
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()
+        }
+        ReportFormat::Json => {
+            let json = serde_json::json!({
+                "from": from.to_rfc3339(),
+                "to": to.to_rfc3339(),
+                "total_minutes": report.total.num_minutes(),
+                "covered_minutes": report.covered.num_minutes(),
+                "groups": rows,
+            });
+            serde_json::to_writer_pretty(&mut *out, &json)?;
+            writeln!(out)
+        }
+    }
+}
+
+fn format_minutes(minutes: i64) -> String {
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn writes_every_format() {
+        let report = UsageReport {
+            groups: BTreeMap::from([("work", TimeDelta::minutes(90))]),
+            total: TimeDelta::minutes(120),
+            covered: TimeDelta::minutes(100),
+        };
+        let rows = [Row::new(&report, "work".to_owned(), TimeDelta::minutes(90))];
+        let from = Utc.with_ymd_and_hms(2024, 6, 10, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 6, 11, 0, 0, 0).unwrap();
+        let output = |format| {
+            let mut out = Vec::new();
+            write(&mut out, format, &report, &rows, from, to).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            output(ReportFormat::Text),
+            "work       1h30m   75.0%\ntotal      2h00m\ncovered    1h40m\n"
+        );
+        assert_eq!(
+            output(ReportFormat::Csv),
+            "group,minutes,percent\nwork,90,75.0\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&output(ReportFormat::Json)).unwrap();
+        assert_eq!(json["total_minutes"], 120);
+        assert_eq!(json["groups"][0]["group"], "work");
+        assert_eq!(json["groups"][0]["percent"], 75.0);
+    }
+}
@@ -0,0 +1,36 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use metime_core::SyncState;
+use uuid::Uuid;
+
+/// Loads the state of syncing with the calendar at `url` from `dir`, or a
+/// fresh state if it has never been synced.
+pub fn load(dir: &Path, url: &str) -> io::Result<SyncState> {
+    let contents = match fs::read_to_string(state_path(dir, url)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(SyncState::new()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Writes the state of syncing with the calendar at `url` to `dir`,
+/// replacing the previous one atomically.
+pub fn save(dir: &Path, url: &str, state: &SyncState) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = state_path(dir, url);
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, serde_json::to_string(state)?)?;
+    fs::rename(temp, path)
+}
+
+/// Each calendar gets a file named after its URL, which is hashed so that
+/// any URL makes a valid file name.
+fn state_path(dir: &Path, url: &str) -> PathBuf {
+    let name = Uuid::new_v5(&Uuid::NAMESPACE_URL, url.as_bytes());
+    dir.join(format!("{name}.json"))
+}
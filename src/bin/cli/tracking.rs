@@ -0,0 +1,78 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use metime_core::{RunningTimer, Tracker};
+
+/// Loads the tracker from the file at `path`, with no timer running if the
+/// file does not exist. The file holds the running timer as JSON.
+pub fn load(path: &Path) -> io::Result<Tracker> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Tracker::new()),
+        Err(e) => return Err(e),
+    };
+    let running: RunningTimer =
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Tracker::resume(running))
+}
+
+/// Writes the tracker to the file at `path`, replacing the file atomically,
+/// or removes the file if no timer is running.
+pub fn save(path: &Path, tracker: &Tracker) -> io::Result<()> {
+    let Some(running) = tracker.current() else {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, serde_json::to_string_pretty(running)?)?;
+    fs::rename(temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+    use metime_core::{EventBody, EventStatus};
+
+    use super::*;
+
+    #[test]
+    fn running_timer_round_trips() {
+        let path = std::env::temp_dir().join(format!("metime-tracker-{}", std::process::id()));
+        let mut tracker = Tracker::resume(RunningTimer {
+            start: Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap(),
+            body: EventBody {
+                summary: "Write the report".to_owned(),
+                description: "with\nline breaks".to_owned(),
+                location: String::new(),
+                categories: vec!["work".to_owned(), "q2".to_owned()],
+                status: EventStatus::default(),
+                alarms: Vec::new(),
+            },
+        });
+        save(&path, &tracker).unwrap();
+        let loaded = load(&path).unwrap();
+        let running = loaded.current().unwrap();
+        assert_eq!(
+            running.start,
+            Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap()
+        );
+        assert_eq!(running.body, tracker.current().unwrap().body);
+
+        tracker = Tracker::new();
+        save(&path, &tracker).unwrap();
+        assert!(!path.exists());
+        assert!(load(&path).unwrap().current().is_none());
+
+        fs::write(&path, "start 2024-06-10T09:00:00+00:00\n").unwrap();
+        assert_eq!(load(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use metime_core::MemoryRepo;
+
+use crate::config::StorageConfig;
+
+/// The file written by the CLI's `dump` command that the daemon delivers the
+/// alarms of, loaded into a repository that is kept up to date with it.
+pub struct DataFile {
+    path: PathBuf,
+    storage: StorageConfig,
+    /// When the file was last changed as of the last load or save.
+    modified: Option<SystemTime>,
+    repo: MemoryRepo,
+}
+
+impl DataFile {
+    pub fn open(path: PathBuf, storage: StorageConfig) -> Result<Self, String> {
+        let mut data = DataFile {
+            repo: storage.open_repo(),
+            path,
+            storage,
+            modified: None,
+        };
+        data.reload()?;
+        Ok(data)
+    }
+
+    pub fn repo(&self) -> &MemoryRepo {
+        &self.repo
+    }
+
+    /// Loads the file again if something else has changed it since it was
+    /// last loaded or saved. A missing file holds no events.
+    pub fn reload(&mut self) -> Result<(), String> {
+        let modified = self.modified_time()?;
+        if self.modified.is_some() && modified == self.modified {
+            return Ok(());
+        }
+        let repo = self.storage.open_repo();
+        if modified.is_some() {
+            let file = File::open(&self.path).map_err(|e| self.error(e))?;
+            metime_core::import_repo(&repo, io::BufReader::new(file)).map_err(|e| self.error(e))?;
+        }
+        self.repo = repo;
+        self.modified = modified;
+        Ok(())
+    }
+
+    /// Writes the repository back to the file, replacing it only once the
+    /// dump is complete.
+    pub fn save(&mut self) -> Result<(), String> {
+        let tmp = self.path.with_extension("tmp");
+        let file = File::create(&tmp).map_err(|e| self.error(e))?;
+        metime_core::export_repo(&self.repo, io::BufWriter::new(file))
+            .map_err(|e| self.error(e))?;
+        fs::rename(&tmp, &self.path).map_err(|e| self.error(e))?;
+        self.modified = self.modified_time()?;
+        Ok(())
+    }
+
+    fn modified_time(&self) -> Result<Option<SystemTime>, String> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.modified().map(Some).map_err(|e| self.error(e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    fn error(&self, e: impl std::fmt::Display) -> String {
+        format!("{}: {e}", self.path.display())
+    }
+}
@@ -0,0 +1,271 @@
+use std::{path::PathBuf, thread};
+
+use chrono::{prelude::*, TimeDelta};
+use clap::Parser;
+use data::DataFile;
+use metime_core::Repository;
+use notify::Notifier;
+use state::DaemonState;
+
+// the config file is shared with the CLI, which is what reads most of it
+#[allow(dead_code)]
+#[path = "../cli/config.rs"]
+mod config;
+mod data;
+mod notify;
+mod state;
+
+/// Delivers the alarms of events in the repository as they go off.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Path to the config file. Defaults to `$XDG_CONFIG_HOME/metime/config.toml`.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// A file written by the CLI's `dump` command, whose alarms are
+    /// delivered. It is reloaded whenever it changes, and delivered alarms
+    /// are saved back to it as acknowledged.
+    #[arg(long)]
+    data: PathBuf,
+    /// Deliver the alarms that have gone off since the last run, then exit
+    /// instead of waiting for more.
+    #[arg(long)]
+    once: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+    let config = match config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let mut notifier = notify::from_config(&config.daemon.notifier, config.display.zone);
+    let mut state = match DaemonState::load(config.daemon.state_path()) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("could not read the daemon state: {e}");
+            std::process::exit(1);
+        }
+    };
+    let mut data = match DataFile::open(args.data, config.storage) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("could not read {e}");
+            std::process::exit(1);
+        }
+    };
+    let poll_interval = TimeDelta::seconds(config.daemon.poll_interval.max(1).into());
+
+    loop {
+        let now = Utc::now();
+        if let Err(e) = deliver(&mut data, &mut state, &mut *notifier, now) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        if args.once {
+            break;
+        }
+        let wake = metime_core::next_alarm(data.repo(), now)
+            .map(|alarm| alarm.time)
+            .filter(|&time| time < now + poll_interval)
+            .unwrap_or(now + poll_interval);
+        thread::sleep((wake - Utc::now()).to_std().unwrap_or_default());
+    }
+}
+
+/// Delivers the alarms that have gone off since the last round, up to `now`.
+fn deliver(
+    data: &mut DataFile,
+    state: &mut DaemonState,
+    notifier: &mut dyn Notifier,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    // pick up changes made by other programs
+    data.reload().map_err(|e| format!("could not read {e}"))?;
+
+    // alarms that went off while the daemon was not running are delivered
+    // late rather than not at all
+    let from = state.checked_until.unwrap_or(now);
+    let due = metime_core::alarms_due(data.repo(), from, now);
+    let mut retry_from = None;
+    if !due.is_empty() {
+        // acknowledge the alarms before delivering them, so that they are not
+        // delivered twice if the daemon dies in between
+        let mut previous = Vec::new();
+        for alarm in &due {
+            if let Ok(mut instance) = data.repo().get_event_instance(alarm.instance) {
+                previous.push(instance.alarm_states.get(&alarm.alarm).copied());
+                instance.acknowledge_alarm(&alarm.alarm, now);
+            } else {
+                previous.push(None);
+            }
+        }
+        data.save().map_err(|e| format!("could not save {e}"))?;
+        // alarms that could not be delivered go back to how they were, to be
+        // tried again next round
+        for (alarm, previous) in due.iter().zip(previous) {
+            let Err(e) = notifier.notify(alarm) else {
+                continue;
+            };
+            eprintln!("could not deliver alarm \"{}\": {e}", alarm.message);
+            if let Ok(mut instance) = data.repo().get_event_instance(alarm.instance) {
+                match previous {
+                    Some(previous) => instance.alarm_states.insert(alarm.alarm.clone(), previous),
+                    None => instance.alarm_states.remove(&alarm.alarm),
+                };
+            }
+            // the alarms are in order of time, so the first to fail is the
+            // earliest
+            retry_from.get_or_insert(alarm.time);
+        }
+        if retry_from.is_some() {
+            data.save().map_err(|e| format!("could not save {e}"))?;
+        }
+    }
+    state.checked_until = Some(retry_from.unwrap_or(now));
+    state
+        .save()
+        .map_err(|e| format!("could not save the daemon state: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io};
+
+    use metime_core::{Alarm, AlarmState, AlarmTrigger, DueAlarm, InstanceId, TimeSpan};
+
+    use super::*;
+    use crate::config::StorageConfig;
+
+    struct Recorder(Vec<String>);
+
+    impl Notifier for Recorder {
+        fn notify(&mut self, alarm: &DueAlarm<InstanceId>) -> io::Result<()> {
+            self.0.push(alarm.message.clone());
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl Notifier for Failing {
+        fn notify(&mut self, _: &DueAlarm<InstanceId>) -> io::Result<()> {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "notify-send not found",
+            ))
+        }
+    }
+
+    #[test]
+    fn delivers_alarms_once_and_saves_them_as_acknowledged() {
+        let dir = std::env::temp_dir().join(format!("metimed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.json");
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let repo = metime_core::MemoryRepo::new();
+        let (_, _, _, mut body) = metime_core::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Standup".to_owned(),
+            String::new(),
+        );
+        body.alarms.push(Alarm {
+            uid: "standup".to_owned(),
+            trigger: AlarmTrigger::Start(-TimeDelta::minutes(10)),
+            description: String::new(),
+        });
+        drop(body);
+        let file = fs::File::create(&path).unwrap();
+        metime_core::export_repo(&repo, file).unwrap();
+
+        let mut data = DataFile::open(path.clone(), StorageConfig::default()).unwrap();
+        let mut state = DaemonState::default();
+        let mut recorder = Recorder(Vec::new());
+        deliver(
+            &mut data,
+            &mut state,
+            &mut recorder,
+            start - TimeDelta::hours(1),
+        )
+        .unwrap();
+        assert!(recorder.0.is_empty());
+        deliver(&mut data, &mut state, &mut recorder, start).unwrap();
+        assert_eq!(recorder.0, ["Standup"]);
+
+        // a restart that forgot how far it had got still doesn't repeat it
+        let mut data = DataFile::open(path.clone(), StorageConfig::default()).unwrap();
+        let mut state = DaemonState::default();
+        state.checked_until = Some(start - TimeDelta::hours(1));
+        deliver(&mut data, &mut state, &mut recorder, start).unwrap();
+        assert_eq!(recorder.0.len(), 1);
+        let id = *data
+            .repo()
+            .get_timeline()
+            .unwrap()
+            .events
+            .values()
+            .next()
+            .unwrap();
+        assert_eq!(
+            data.repo().get_event_instance(id).unwrap().alarm_states["standup"],
+            AlarmState::Acknowledged(start)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_alarms_that_could_not_be_delivered() {
+        let dir = std::env::temp_dir().join(format!("metimed-failing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.json");
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let repo = metime_core::MemoryRepo::new();
+        let (_, _, mut instance, _) = metime_core::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Standup".to_owned(),
+            String::new(),
+        );
+        instance.alarms.push(Alarm {
+            uid: "standup".to_owned(),
+            trigger: AlarmTrigger::Start(-TimeDelta::minutes(10)),
+            description: String::new(),
+        });
+        let snoozed = start - TimeDelta::minutes(5);
+        instance.snooze_alarm("standup", snoozed);
+        drop(instance);
+        let file = fs::File::create(&path).unwrap();
+        metime_core::export_repo(&repo, file).unwrap();
+
+        let mut data = DataFile::open(path.clone(), StorageConfig::default()).unwrap();
+        let mut state = DaemonState::default();
+        state.checked_until = Some(start - TimeDelta::hours(1));
+        deliver(&mut data, &mut state, &mut Failing, start).unwrap();
+        assert_eq!(state.checked_until, Some(snoozed));
+        let data = DataFile::open(path.clone(), StorageConfig::default()).unwrap();
+        let id = *data
+            .repo()
+            .get_timeline()
+            .unwrap()
+            .events
+            .values()
+            .next()
+            .unwrap();
+        assert_eq!(
+            data.repo().get_event_instance(id).unwrap().alarm_states["standup"],
+            AlarmState::Snoozed(snoozed)
+        );
+
+        // once notifying works again, the alarm is delivered after all
+        let mut data = data;
+        let mut recorder = Recorder(Vec::new());
+        let later = start + TimeDelta::minutes(1);
+        deliver(&mut data, &mut state, &mut recorder, later).unwrap();
+        assert_eq!(recorder.0, ["Standup"]);
+        assert_eq!(state.checked_until, Some(later));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    process,
+};
+
+use metime_core::{DueAlarm, InstanceId};
+
+use crate::config::{DisplayZone, NotifierConfig};
+
+/// Something that delivers alarms to the user.
+pub trait Notifier {
+    fn notify(&mut self, alarm: &DueAlarm<InstanceId>) -> io::Result<()>;
+}
+
+/// Creates the notifier described by the config.
+pub fn from_config(config: &NotifierConfig, zone: DisplayZone) -> Box<dyn Notifier> {
+    match config {
+        NotifierConfig::Stdout => Box::new(StdoutNotifier { zone }),
+        NotifierConfig::Command { program, args } => Box::new(CommandNotifier {
+            program: program.clone(),
+            args: args.clone(),
+        }),
+        NotifierConfig::Log { path } => Box::new(LogNotifier { path: path.clone() }),
+    }
+}
+
+pub struct StdoutNotifier {
+    zone: DisplayZone,
+}
+
+impl Notifier for StdoutNotifier {
+    fn notify(&mut self, alarm: &DueAlarm<InstanceId>) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        writeln!(
+            stdout,
+            "{}  {}",
+            self.zone.format(alarm.time, "%a %Y-%m-%d %H:%M"),
+            alarm.message
+        )?;
+        stdout.flush()
+    }
+}
+
+/// Runs a program with the alarm message as its last argument. The time of
+/// the alarm and the ID of its event instance are passed in the environment
+/// as `METIME_ALARM_TIME` (RFC 3339) and `METIME_INSTANCE_ID`.
+pub struct CommandNotifier {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&mut self, alarm: &DueAlarm<InstanceId>) -> io::Result<()> {
+        let status = process::Command::new(&self.program)
+            .args(&self.args)
+            .arg(&alarm.message)
+            .env("METIME_ALARM_TIME", alarm.time.to_rfc3339())
+            .env("METIME_INSTANCE_ID", alarm.instance.to_string())
+            .status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "{} exited with {status}",
+                self.program
+            )))
+        }
+    }
+}
+
+/// Appends a line per alarm to a file.
+pub struct LogNotifier {
+    path: PathBuf,
+}
+
+impl Notifier for LogNotifier {
+    fn notify(&mut self, alarm: &DueAlarm<InstanceId>) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(
+            file,
+            "{}\t{}\t{}",
+            alarm.time.to_rfc3339(),
+            alarm.instance,
+            alarm.message.replace('\n', " ")
+        )
+    }
+}
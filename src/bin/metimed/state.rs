@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How far the daemon has got, saved to a file as JSON so that alarms that
+/// go off while it is not running are delivered late rather than not at all.
+/// Which alarms have been delivered is kept in the data file instead, as
+/// their acknowledgements.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DaemonState {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Every alarm going off before this time has been delivered.
+    pub checked_until: Option<DateTime<Utc>>,
+}
+
+impl DaemonState {
+    /// Loads the state from `path`, starting afresh if the file does not
+    /// exist. Without a path, nothing is remembered across restarts.
+    pub fn load(path: Option<PathBuf>) -> io::Result<Self> {
+        let Some(file) = &path else {
+            return Ok(DaemonState::default());
+        };
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(DaemonState {
+                    path,
+                    ..Default::default()
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let state: DaemonState = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        Ok(DaemonState { path, ..state })
+    }
+
+    /// Writes the state to its file, replacing the file atomically.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, serde_json::to_string(self)?)?;
+        fs::rename(temp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_round_trips() {
+        let path = std::env::temp_dir().join(format!("metimed-state-{}", std::process::id()));
+        let mut state = DaemonState::load(Some(path.clone())).unwrap();
+        assert_eq!(state.checked_until, None);
+        let time = Utc.with_ymd_and_hms(2024, 6, 10, 11, 45, 0).unwrap();
+        state.checked_until = Some(time);
+        state.save().unwrap();
+        assert_eq!(
+            DaemonState::load(Some(path.clone())).unwrap().checked_until,
+            Some(time)
+        );
+
+        fs::write(&path, "checked-until 2024-06-10T12:00:00+00:00\n").unwrap();
+        assert_eq!(
+            DaemonState::load(Some(path.clone())).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use derive_more::derive::{Display, Error, From};
+
+use crate::{
+    domain::Calendar,
+    repository::{RepoRetrievalError, Repository},
+};
+
+#[derive(Debug, Display, Error, From, PartialEq, Eq)]
+pub enum CalendarError {
+    Retrieval(RepoRetrievalError),
+    #[display("there is no calendar named {_0:?}")]
+    #[from(ignore)]
+    UnknownCalendar(#[error(not(source))] String),
+    #[display("there is already a calendar named {_0:?}")]
+    #[from(ignore)]
+    DuplicateName(#[error(not(source))] String),
+}
+
+/// Adds a calendar to the repository. Its name must not be taken.
+pub fn add_calendar<R: Repository>(
+    repo: &R,
+    calendar: Calendar<R::EventInstanceId>,
+) -> Result<(), CalendarError> {
+    let mut calendar_list = repo
+        .get_calendar_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    if calendar_list.get(&calendar.name).is_some() {
+        return Err(CalendarError::DuplicateName(calendar.name));
+    }
+    calendar_list.calendars.push(calendar);
+    Ok(())
+}
+
+/// Moves an event instance into the named calendar, out of whichever calendar
+/// it was in before.
+pub fn move_to_calendar<R: Repository>(
+    repo: &R,
+    instance_id: R::EventInstanceId,
+    name: &str,
+) -> Result<(), CalendarError>
+where
+    R::EventInstanceId: PartialEq,
+{
+    let mut calendar_list = repo
+        .get_calendar_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    if calendar_list.get(name).is_none() {
+        return Err(CalendarError::UnknownCalendar(name.to_owned()));
+    }
+    for calendar in &mut calendar_list.calendars {
+        calendar.events.retain(|&id| id != instance_id);
+    }
+    calendar_list
+        .get_mut(name)
+        .expect("the calendar was just found")
+        .events
+        .push(instance_id);
+    Ok(())
+}
+
+/// The name of the calendar that an event instance belongs to, if any.
+pub fn calendar_of<R: Repository>(repo: &R, instance_id: R::EventInstanceId) -> Option<String>
+where
+    R::EventInstanceId: PartialEq,
+{
+    let calendar_list = repo.get_calendar_list()?;
+    calendar_list
+        .calendars
+        .iter()
+        .find(|calendar| calendar.events.contains(&instance_id))
+        .map(|calendar| calendar.name.clone())
+}
+
+/// Lists the event instances on the timeline that belong to any of the named
+/// calendars, in order of time.
+pub fn events_in_calendars<R: Repository>(
+    repo: &R,
+    names: &[&str],
+) -> Result<Vec<R::EventInstanceId>, CalendarError>
+where
+    R::EventInstanceId: PartialEq,
+{
+    let calendar_list = repo
+        .get_calendar_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    let mut selected = Vec::new();
+    for &name in names {
+        let calendar = calendar_list
+            .get(name)
+            .ok_or_else(|| CalendarError::UnknownCalendar(name.to_owned()))?;
+        selected.push(calendar);
+    }
+    let timeline = repo
+        .get_timeline()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    Ok(timeline
+        .events
+        .values()
+        .copied()
+        .filter(|id| selected.iter().any(|calendar| calendar.events.contains(id)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use super::*;
+    use crate::{domain::TimeSpan, InstanceId, MemoryRepo};
+
+    fn add(repo: &MemoryRepo, hour: u32, title: &str) -> InstanceId {
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, hour, 0, 0).unwrap();
+        let (id, ..) = crate::add_event(
+            repo,
+            TimeSpan::Instant(start),
+            title.to_owned(),
+            String::new(),
+        );
+        id
+    }
+
+    #[test]
+    fn unions_selected_calendars_in_time_order() {
+        let repo = MemoryRepo::new();
+        for name in ["work", "personal", "holidays"] {
+            add_calendar(&repo, Calendar::new(name.to_owned())).unwrap();
+        }
+        let gym = add(&repo, 18, "Gym");
+        let standup = add(&repo, 9, "Standup");
+        let review = add(&repo, 14, "Review");
+        let unfiled = add(&repo, 12, "Lunch");
+        move_to_calendar(&repo, gym, "personal").unwrap();
+        move_to_calendar(&repo, standup, "work").unwrap();
+        move_to_calendar(&repo, review, "personal").unwrap();
+        // moving an instance takes it out of its old calendar
+        move_to_calendar(&repo, review, "work").unwrap();
+
+        assert_eq!(
+            events_in_calendars(&repo, &["work"]),
+            Ok(vec![standup, review])
+        );
+        assert_eq!(
+            events_in_calendars(&repo, &["personal", "work"]),
+            Ok(vec![standup, review, gym])
+        );
+        assert_eq!(events_in_calendars(&repo, &["holidays"]), Ok(vec![]));
+        assert_eq!(calendar_of(&repo, review).as_deref(), Some("work"));
+        assert_eq!(calendar_of(&repo, unfiled), None);
+    }
+
+    #[test]
+    fn rejects_unknown_and_duplicate_names() {
+        let repo = MemoryRepo::new();
+        add_calendar(&repo, Calendar::new("work".to_owned())).unwrap();
+        assert_eq!(
+            add_calendar(&repo, Calendar::new("work".to_owned())),
+            Err(CalendarError::DuplicateName("work".to_owned()))
+        );
+        let id = add(&repo, 9, "Standup");
+        assert_eq!(
+            move_to_calendar(&repo, id, "play"),
+            Err(CalendarError::UnknownCalendar("play".to_owned()))
+        );
+        assert_eq!(
+            events_in_calendars(&repo, &["work", "play"]),
+            Err(CalendarError::UnknownCalendar("play".to_owned()))
+        );
+    }
+}
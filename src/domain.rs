@@ -1,10 +1,23 @@
 use std::collections::BTreeMap;
 
 use chrono::{prelude::*, TimeDelta};
-use derive_more::derive::Display;
+use chrono_tz::Tz;
+use derive_more::derive::{Display, FromStr};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+mod local_time;
+mod parse;
+#[cfg(feature = "serde")]
+mod serialization;
+
+pub use local_time::{start_of_day, Disambiguation, LocalTimeError, ParseDisambiguationError};
+pub use parse::{TimeSpanParseError, TimeSpanParseErrorKind, TimeSpanParser, YearInference};
 
 /// Holds IDs to all event instances, allowing lookup by time.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Timeline<EventInstanceId> {
     pub events: BTreeMap<DateTime<Utc>, EventInstanceId>,
 }
@@ -23,21 +36,168 @@ impl<EventInstanceId> Timeline<EventInstanceId> {
     }
 }
 
-/// A single event instance.
+/// A named group of event instances, like "work" or "holidays", that can be
+/// shown or hidden together. Every instance is still on the [`Timeline`];
+/// calendars only say which instances belong together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Calendar<EventInstanceId> {
+    pub name: String,
+    /// The colour to show the calendar's events in, like `#3465a4`, or empty
+    /// for no colour in particular.
+    pub colour: String,
+    /// The zone that times without an offset are read in when adding events
+    /// to the calendar, or `None` for the local zone.
+    pub default_zone: Option<Tz>,
+    /// The instances that belong to the calendar. An instance belongs to at
+    /// most one calendar.
+    pub events: Vec<EventInstanceId>,
+}
+
+impl<EventInstanceId> Calendar<EventInstanceId> {
+    /// Creates an empty calendar with no colour, in the local zone.
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            colour: String::new(),
+            default_zone: None,
+            events: Vec::new(),
+        }
+    }
+}
+
+/// Holds all calendars, in the order they were added.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CalendarList<EventInstanceId> {
+    pub calendars: Vec<Calendar<EventInstanceId>>,
+}
+
+impl<EventInstanceId> Default for CalendarList<EventInstanceId> {
+    fn default() -> Self {
+        Self {
+            calendars: Vec::new(),
+        }
+    }
+}
+
+impl<EventInstanceId> CalendarList<EventInstanceId> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Calendar<EventInstanceId>> {
+        self.calendars.iter().find(|calendar| calendar.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Calendar<EventInstanceId>> {
+        self.calendars
+            .iter_mut()
+            .find(|calendar| calendar.name == name)
+    }
+}
+
+/// Holds IDs to all tasks, in the order they were added.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TaskList<TaskId> {
+    pub tasks: Vec<TaskId>,
+}
+
+impl<TaskId> Default for TaskList<TaskId> {
+    fn default() -> Self {
+        Self { tasks: Vec::new() }
+    }
+}
+
+impl<TaskId> TaskList<TaskId> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// A single event instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EventInstance<EventBodyId> {
+    /// When the event is planned to happen. This is what places the instance
+    /// on the timeline.
     pub time_span: TimeSpan,
     pub body: EventBodyId,
+    /// When the event actually happened, once that is known.
+    pub actual: Option<TimeSpan>,
+    /// How the event turned out, once it is over.
+    pub outcome: Option<Outcome>,
+    /// Alarms for this instance only, in addition to those of its body.
+    pub alarms: Vec<Alarm>,
+    /// Which of this instance's alarms, including the ones inherited from its
+    /// body, have been acknowledged or snoozed, by the `uid` of the alarm.
+    /// Alarms not in the map are pending.
+    pub alarm_states: BTreeMap<String, AlarmState>,
+    /// The iCalendar `UID` of the instance, once it has been synced with a
+    /// CalDAV server. It stays the same across dumps and restores, unlike
+    /// the ID the repository gives the instance.
+    pub uid: Option<String>,
+}
+
+impl<EventBodyId> EventInstance<EventBodyId> {
+    /// Returns every alarm that applies to this instance, given its body:
+    /// those of the body, then its own.
+    pub fn all_alarms<'a>(&'a self, body: &'a EventBody) -> impl Iterator<Item = &'a Alarm> + 'a {
+        body.alarms.iter().chain(&self.alarms)
+    }
+
+    /// Marks the alarm with the given `uid` as dealt with, so that it is no
+    /// longer due.
+    pub fn acknowledge_alarm(&mut self, uid: &str, at: DateTime<Utc>) {
+        self.alarm_states
+            .insert(uid.to_owned(), AlarmState::Acknowledged(at));
+    }
+
+    /// Postpones the alarm with the given `uid` so that it is due again at
+    /// `until`.
+    pub fn snooze_alarm(&mut self, uid: &str, until: DateTime<Utc>) {
+        self.alarm_states
+            .insert(uid.to_owned(), AlarmState::Snoozed(until));
+    }
+
+    /// How long the event actually took, if that is known. A skipped event
+    /// took no time, and an event that was done without recording when is
+    /// assumed to have gone as planned.
+    pub fn actual_duration(&self) -> Option<TimeDelta> {
+        match (&self.actual, self.outcome) {
+            (Some(actual), _) => Some(actual.duration()),
+            (None, Some(Outcome::Skipped)) => Some(TimeDelta::zero()),
+            (None, Some(Outcome::Done)) => Some(self.time_span.duration()),
+            (None, Some(Outcome::Partial) | None) => None,
+        }
+    }
+}
+
+/// How an event turned out compared to what was planned.
+#[derive(Debug, Display, FromStr, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Outcome {
+    #[display("done")]
+    Done,
+    #[display("skipped")]
+    Skipped,
+    /// Some, but not all, of what was planned happened.
+    #[display("partial")]
+    Partial,
 }
 
 /// A set of continuous points in time describing the times at which an event is
 /// occuring. If the span is not instantaneous, the start endpoint is considered
 /// included and the end endpoint is considered excluded (half-open interval).
-#[derive(Debug, Display, PartialEq, Eq)]
+///
+/// Time spans are displayed in UTC in the form that [`TimeSpanParser`] reads,
+/// like `2024-06-10T09:00:00Z/2024-06-10T10:30:00Z`, so that they can be parsed
+/// back.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TimeSpan {
-    #[display("[{}]", _0.format("%c"))]
     Instant(DateTime<Utc>),
-    #[display("[{} -- {}m]", start.format("%c"), duration.num_minutes())]
     Interval {
         start: DateTime<Utc>,
         duration: TimeDelta,
@@ -63,11 +223,168 @@ impl TimeSpan {
             TimeSpan::Interval { start, duration } => *start + *duration,
         }
     }
+
+    /// Returns how long the time span lasts, which is zero for an instant.
+    pub fn duration(&self) -> TimeDelta {
+        match self {
+            TimeSpan::Instant(_) => TimeDelta::zero(),
+            TimeSpan::Interval { duration, .. } => *duration,
+        }
+    }
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        match self {
+            TimeSpan::Instant(time) => f.write_str(&format(*time)),
+            TimeSpan::Interval { start, .. } => {
+                write!(f, "{}/{}", format(*start), format(self.latest()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EventBody {
     pub summary: String,
     pub description: String,
-    // TODO add location, categories, etc.
+    /// Where the event takes place, like iCalendar's `LOCATION`.
+    pub location: String,
+    /// Free-form tags used to group related events, like iCalendar's
+    /// `CATEGORIES`.
+    pub categories: Vec<String>,
+    pub status: EventStatus,
+    /// Alarms that apply to every instance of this body.
+    pub alarms: Vec<Alarm>,
+    // TODO add attendees, etc.
+}
+
+/// Whether an event is expected to happen, like iCalendar's `STATUS`.
+#[derive(Debug, Default, Display, FromStr, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EventStatus {
+    #[display("tentative")]
+    Tentative,
+    #[default]
+    #[display("confirmed")]
+    Confirmed,
+    #[display("cancelled")]
+    Cancelled,
+}
+
+/// A reminder that goes off at some time relative to an event, like
+/// iCalendar's `VALARM`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Alarm {
+    /// Identifies the alarm among those of an event, like the `UID` of a
+    /// `VALARM` (RFC 9074), so that what was done about it does not depend on
+    /// where it is in the list.
+    pub uid: String,
+    pub trigger: AlarmTrigger,
+    /// The message to show when the alarm goes off. If empty, the summary of
+    /// the event is shown instead.
+    pub description: String,
+}
+
+impl Alarm {
+    /// Creates an alarm with a new, random `uid`.
+    pub fn new(trigger: AlarmTrigger, description: String) -> Self {
+        Self {
+            uid: Uuid::new_v4().to_string(),
+            trigger,
+            description,
+        }
+    }
+}
+
+/// When an alarm goes off.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AlarmTrigger {
+    /// At an offset from the start of the event; negative offsets are before
+    /// the start.
+    Start(#[cfg_attr(feature = "serde", serde(with = "serialization::duration"))] TimeDelta),
+    /// At an offset from the (excluded) end of the event.
+    End(#[cfg_attr(feature = "serde", serde(with = "serialization::duration"))] TimeDelta),
+    /// At a fixed time, regardless of when the event is.
+    Absolute(DateTime<Utc>),
+}
+
+impl AlarmTrigger {
+    /// Returns when the alarm goes off for an event occuring at `time_span`.
+    pub fn time(&self, time_span: &TimeSpan) -> DateTime<Utc> {
+        match *self {
+            AlarmTrigger::Start(offset) => time_span.earliest() + offset,
+            AlarmTrigger::End(offset) => time_span.latest() + offset,
+            AlarmTrigger::Absolute(time) => time,
+        }
+    }
+}
+
+/// What has been done about an alarm which has gone off.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AlarmState {
+    /// The alarm was acknowledged at the given time and will not go off again.
+    Acknowledged(DateTime<Utc>),
+    /// The alarm will go off again at the given time.
+    Snoozed(DateTime<Utc>),
+}
+
+/// Something to be done, like iCalendar's `VTODO`. Unlike an event, a task
+/// does not occupy any time until it is scheduled onto the timeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Task<EventInstanceId> {
+    pub summary: String,
+    pub description: String,
+    pub categories: Vec<String>,
+    /// When the task must be done by: either a deadline, or a span of time in
+    /// which it should be done.
+    pub due: Option<TimeSpan>,
+    /// How long the task is expected to take.
+    #[cfg_attr(feature = "serde", serde(with = "serialization::option_duration"))]
+    pub estimated_duration: Option<TimeDelta>,
+    /// From 1 (highest) to 9 (lowest), as in iCalendar, or `None` if the
+    /// task has no particular priority.
+    pub priority: Option<u8>,
+    /// How much of the task is done, from 0 to 100.
+    pub percent_complete: u8,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// The event instance that sets aside time for working on this task, if
+    /// the task has been scheduled.
+    pub scheduled: Option<EventInstanceId>,
+}
+
+impl<EventInstanceId> Task<EventInstanceId> {
+    /// Creates a task that is not started, with no deadline and no priority.
+    pub fn new(summary: String) -> Self {
+        Self {
+            summary,
+            description: String::new(),
+            categories: Vec::new(),
+            due: None,
+            estimated_duration: None,
+            priority: None,
+            percent_complete: 0,
+            completed_at: None,
+            scheduled: None,
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.completed_at.is_some()
+    }
+
+    /// Marks the task as fully done at the given time.
+    pub fn complete(&mut self, at: DateTime<Utc>) {
+        self.percent_complete = 100;
+        self.completed_at = Some(at);
+    }
 }
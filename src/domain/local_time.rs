@@ -0,0 +1,238 @@
+//! Turning wall clock times into instants, which is ambiguous when the clocks
+//! go back and impossible when they skip ahead.
+
+use std::str::FromStr;
+
+use chrono::{prelude::*, LocalResult, TimeDelta};
+use derive_more::derive::{Display, Error};
+
+use super::TimeSpan;
+
+/// How to read a wall clock time that happens twice, because the clocks went
+/// back, or not at all, because they skipped ahead.
+#[derive(Debug, Default, Display, Copy, Clone, PartialEq, Eq)]
+pub enum Disambiguation {
+    /// Take the earlier of the two instants, and reject times that don't
+    /// exist.
+    #[default]
+    #[display("earliest")]
+    Earliest,
+    /// Take the later of the two instants, and reject times that don't exist.
+    #[display("latest")]
+    Latest,
+    /// Reject times that happen twice or not at all.
+    #[display("reject")]
+    Reject,
+    /// Take the earlier of the two instants, and move times that don't exist
+    /// forward by the length of the gap, so 02:30 on a day when the clocks go
+    /// from 02:00 to 03:00 becomes 03:30.
+    #[display("shift-forward")]
+    ShiftForward,
+}
+
+#[derive(Debug, Display, Error, Clone, PartialEq, Eq)]
+#[display("{_0:?} is not one of earliest, latest, reject or shift-forward")]
+pub struct ParseDisambiguationError(#[error(not(source))] String);
+
+#[derive(Debug, Display, Error, Clone, PartialEq, Eq)]
+pub enum LocalTimeError {
+    #[display("{local} happens twice in the time zone, at {earliest} and at {latest}")]
+    Ambiguous {
+        local: NaiveDateTime,
+        earliest: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    },
+    #[display("{_0} does not exist in the time zone")]
+    Nonexistent(#[error(not(source))] NaiveDateTime),
+    #[display("the time span ends before it starts")]
+    EndBeforeStart,
+}
+
+impl Disambiguation {
+    /// Finds the instant at which the clocks in `zone` read `local`.
+    pub fn resolve<Tz: TimeZone>(
+        self,
+        zone: &Tz,
+        local: NaiveDateTime,
+    ) -> Result<DateTime<Utc>, LocalTimeError> {
+        match zone.from_local_datetime(&local) {
+            LocalResult::Single(time) => Ok(time.to_utc()),
+            LocalResult::Ambiguous(first, second) => {
+                // not every zone gives the two in order
+                let (earliest, latest) = if first <= second {
+                    (first.to_utc(), second.to_utc())
+                } else {
+                    (second.to_utc(), first.to_utc())
+                };
+                match self {
+                    Disambiguation::Earliest | Disambiguation::ShiftForward => Ok(earliest),
+                    Disambiguation::Latest => Ok(latest),
+                    Disambiguation::Reject => Err(LocalTimeError::Ambiguous {
+                        local,
+                        earliest,
+                        latest,
+                    }),
+                }
+            }
+            LocalResult::None => match self {
+                Disambiguation::ShiftForward => {
+                    shift_forward(zone, local).ok_or(LocalTimeError::Nonexistent(local))
+                }
+                _ => Err(LocalTimeError::Nonexistent(local)),
+            },
+        }
+    }
+}
+
+/// The first moment of a day in `zone`, which is midnight unless the clocks
+/// skip it. Only `None` if no hour of the day exists in the zone.
+pub fn start_of_day<Tz: TimeZone>(zone: &Tz, date: NaiveDate) -> Option<DateTime<Tz>> {
+    (0..24).find_map(|hour| {
+        zone.from_local_datetime(&date.and_hms_opt(hour, 0, 0)?)
+            .earliest()
+    })
+}
+
+/// Reads a wall clock time that was skipped with the offset from before the
+/// clocks skipped ahead.
+fn shift_forward<Tz: TimeZone>(zone: &Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    // the clocks don't change twice in a day, so the offset a day earlier is
+    // the one from before the gap
+    let day_before = local.checked_sub_signed(TimeDelta::days(1))?;
+    let offset = zone
+        .from_local_datetime(&day_before)
+        .earliest()?
+        .offset()
+        .fix();
+    Some(local.checked_sub_offset(offset)?.and_utc())
+}
+
+impl FromStr for Disambiguation {
+    type Err = ParseDisambiguationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Disambiguation::Earliest,
+            Disambiguation::Latest,
+            Disambiguation::Reject,
+            Disambiguation::ShiftForward,
+        ]
+        .into_iter()
+        .find(|policy| policy.to_string() == s)
+        .ok_or_else(|| ParseDisambiguationError(s.to_owned()))
+    }
+}
+
+impl TimeSpan {
+    /// The instant at which the clocks in `zone` read `at`.
+    pub fn local_instant<Tz: TimeZone>(
+        zone: &Tz,
+        at: NaiveDateTime,
+        policy: Disambiguation,
+    ) -> Result<Self, LocalTimeError> {
+        policy.resolve(zone, at).map(TimeSpan::Instant)
+    }
+
+    /// The interval from when the clocks in `zone` read `start` until they
+    /// read `end`, which lasts longer or shorter than the difference between
+    /// the two if the clocks change in between.
+    pub fn local_interval<Tz: TimeZone>(
+        zone: &Tz,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        policy: Disambiguation,
+    ) -> Result<Self, LocalTimeError> {
+        let start = policy.resolve(zone, start)?;
+        let end = policy.resolve(zone, end)?;
+        if end < start {
+            return Err(LocalTimeError::EndBeforeStart);
+        }
+        Ok(TimeSpan::Interval {
+            start,
+            duration: end - start,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn local(m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn utc(m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        local(m, d, h, min).and_utc()
+    }
+
+    #[test]
+    fn resolves_repeated_times_by_policy() {
+        // the clocks go back from 03:00 to 02:00 on October 27th
+        let twice = local(10, 27, 2, 30);
+        let resolve = |policy: Disambiguation| policy.resolve(&Berlin, twice);
+        assert_eq!(resolve(Disambiguation::Earliest), Ok(utc(10, 27, 0, 30)));
+        assert_eq!(
+            resolve(Disambiguation::ShiftForward),
+            Ok(utc(10, 27, 0, 30))
+        );
+        assert_eq!(resolve(Disambiguation::Latest), Ok(utc(10, 27, 1, 30)));
+        assert_eq!(
+            resolve(Disambiguation::Reject),
+            Err(LocalTimeError::Ambiguous {
+                local: twice,
+                earliest: utc(10, 27, 0, 30),
+                latest: utc(10, 27, 1, 30),
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_skipped_times_by_policy() {
+        // the clocks go forward from 02:00 to 03:00 on March 31st
+        let skipped = local(3, 31, 2, 30);
+        let resolve = |policy: Disambiguation| policy.resolve(&Berlin, skipped);
+        for policy in [
+            Disambiguation::Earliest,
+            Disambiguation::Latest,
+            Disambiguation::Reject,
+        ] {
+            assert_eq!(resolve(policy), Err(LocalTimeError::Nonexistent(skipped)));
+        }
+        // 03:30 in summer time
+        assert_eq!(resolve(Disambiguation::ShiftForward), Ok(utc(3, 31, 1, 30)));
+    }
+
+    #[test]
+    fn local_intervals_last_as_long_as_they_really_do() {
+        let span = TimeSpan::local_interval(
+            &Berlin,
+            local(3, 31, 1, 0),
+            local(3, 31, 4, 0),
+            Disambiguation::Reject,
+        );
+        assert_eq!(span.map(|span| span.duration()), Ok(TimeDelta::hours(2)));
+        assert_eq!("shift-forward".parse(), Ok(Disambiguation::ShiftForward));
+    }
+
+    #[test]
+    fn days_start_at_the_first_hour_that_exists() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        assert_eq!(
+            start_of_day(&Berlin, date(3, 31)).map(|start| start.to_utc()),
+            Some(utc(3, 30, 23, 0))
+        );
+        // the clocks in São Paulo skipped midnight
+        let sao_paulo = chrono_tz::America::Sao_Paulo;
+        let skipped = NaiveDate::from_ymd_opt(2018, 11, 4).unwrap();
+        assert_eq!(
+            start_of_day(&sao_paulo, skipped).map(|start| start.naive_local()),
+            skipped.and_hms_opt(1, 0, 0)
+        );
+    }
+}
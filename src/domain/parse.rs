@@ -0,0 +1,860 @@
+//! Parsing time spans from the forms people type, like `2024-06-10T09:00/10:30`.
+//!
+//! A time span is one of:
+//!
+//! - an instant: `2024-06-10T09:00`, with optional seconds and fractions of a
+//!   second, and an optional offset (`Z`, `+02:00`);
+//! - an interval between two instants: `2024-06-10T09:00/2024-06-10T10:30`,
+//!   where the end may be just a time (`2024-06-10T09:00/10:30`) on the same
+//!   day and with the same offset as the start;
+//! - a whole day, `2024-06-10`, or several: `2024-06-10/3` for three days
+//!   from the 10th, or `2024-06-10/2024-06-12` for the 10th to the 12th.
+//!
+//! The year may be left out of any date (`06-10T09:00`), in which case the
+//! parser's [`YearInference`] picks one, and times without an offset are in the
+//! parser's zone, read with its [`Disambiguation`] policy if
+//! the clocks change around them.
+
+use std::ops::Range;
+
+use chrono::{prelude::*, Days};
+use derive_more::derive::{Display, Error, FromStr};
+use peg::error::ExpectedSet;
+
+use super::{start_of_day, Disambiguation, LocalTimeError, TimeSpan};
+
+/// Parses time spans relative to a reference time, whose zone is used for
+/// times without an offset and which dates without a year are placed near.
+#[derive(Debug, Clone)]
+pub struct TimeSpanParser<Tz: TimeZone> {
+    now: DateTime<Tz>,
+    disambiguation: Disambiguation,
+    year_inference: YearInference,
+}
+
+/// How to pick the year of a date written without one, relative to a
+/// reference day.
+///
+/// This only applies to the start of an interval. An end without a year is
+/// always its first occurrence on or after the start, so `12-30/01-02` spans
+/// the new year.
+#[derive(Debug, Default, Display, FromStr, Copy, Clone, PartialEq, Eq)]
+pub enum YearInference {
+    /// The year of the reference day, even if the date has passed.
+    #[display("current")]
+    Current,
+    /// The first occurrence on or after the reference day.
+    #[default]
+    #[display("next")]
+    Next,
+    /// The occurrence closest to the reference day, before or after it.
+    #[display("nearest")]
+    Nearest,
+}
+
+impl YearInference {
+    /// Picks the year of the date `month`-`day`, or returns `None` if no year
+    /// has it.
+    pub fn infer(self, month: u32, day: u32, reference: NaiveDate) -> Option<NaiveDate> {
+        let in_year = |year| NaiveDate::from_ymd_opt(year, month, day);
+        // leap days can be eight years apart, like in 2096 and 2104
+        let years = reference.year() - 8..=reference.year() + 8;
+        match self {
+            YearInference::Current => in_year(reference.year()),
+            YearInference::Next => years.filter_map(in_year).find(|&date| date >= reference),
+            YearInference::Nearest => years
+                .filter_map(in_year)
+                .min_by_key(|&date| (date - reference).num_days().abs()),
+        }
+    }
+}
+
+/// Why a time span could not be parsed, and where in the input the problem is.
+#[derive(Debug, Display, Error, Clone, PartialEq, Eq)]
+#[display("{kind} at position {}", position.start)]
+pub struct TimeSpanParseError {
+    /// The byte range of the input that the error is about. For syntax
+    /// errors, this is the character at which parsing stopped, which is
+    /// empty at the end of the input.
+    pub position: Range<usize>,
+    pub kind: TimeSpanParseErrorKind,
+}
+
+#[derive(Debug, Display, Clone, PartialEq, Eq)]
+pub enum TimeSpanParseErrorKind {
+    /// The input doesn't have the shape of a time span.
+    #[display("expected {expected}, found {}", found.map_or("the end".to_owned(), |c| format!("{c:?}")))]
+    Syntax {
+        expected: ExpectedSet,
+        /// The character at which parsing stopped, unless it reached the end.
+        found: Option<char>,
+    },
+    #[display("there is no month {month}")]
+    InvalidMonth { month: u32, day: u32 },
+    #[display("there is no day {day} in {} {year}", month_name(*month))]
+    InvalidDay { year: i32, month: u32, day: u32 },
+    #[display("{hour:02}:{min:02}:{sec:02} is not a time of day")]
+    InvalidTime { hour: u32, min: u32, sec: u32 },
+    #[display("{} is not a valid offset", format_offset(*_0))]
+    InvalidOffset(i32),
+    #[display("{_0} does not exist in the time zone")]
+    NonexistentTime(NaiveDateTime),
+    /// The clocks read the time twice, and the parser was told to reject such
+    /// times.
+    #[display("{local} happens twice in the time zone")]
+    AmbiguousTime {
+        local: NaiveDateTime,
+        earliest: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    },
+    #[display("the time span ends before it starts")]
+    EndBeforeStart,
+    #[display("the time span ends too far in the future")]
+    TooLong,
+}
+
+impl TimeSpanParseError {
+    /// A hint at how to fix common mistakes, to show along with the error.
+    pub fn help(&self) -> Option<&'static str> {
+        match &self.kind {
+            TimeSpanParseErrorKind::Syntax {
+                expected,
+                found: Some(' '),
+            } if expected.tokens().any(|token| token == "\"T\"") => {
+                Some("put a T between the date and the time, like 2024-06-10T09:00")
+            }
+            TimeSpanParseErrorKind::AmbiguousTime { .. } => {
+                Some("add an offset to say which one you mean")
+            }
+            TimeSpanParseErrorKind::InvalidMonth { day: 1..=12, .. } => {
+                Some("write the month before the day, like 2024-06-10 for June 10th")
+            }
+            _ => None,
+        }
+    }
+}
+
+fn month_name(month: u32) -> &'static str {
+    u8::try_from(month)
+        .ok()
+        .and_then(|month| Month::try_from(month).ok())
+        .map_or("?", |month| month.name())
+}
+
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.unsigned_abs() / 60;
+    format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// The byte range of a lexed item in the input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Span(usize, usize);
+
+impl Span {
+    fn error(self, kind: TimeSpanParseErrorKind) -> TimeSpanParseError {
+        TimeSpanParseError {
+            position: self.0..self.1,
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LexedTimeSpan {
+    // TODO add a start-duration variant of intervals
+    Instant(LexedInstant),
+    InstantIntervalStartEnd {
+        start: LexedInstant,
+        end: LexedInstant,
+    },
+    DateIntervalStartDuration {
+        start: LexedDate,
+        /// The duration of the event in days.
+        duration_days: Option<u32>,
+    },
+    DateIntervalStartEnd {
+        start: LexedDate,
+        end: LexedDate,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct LexedInstant {
+    date: LexedDate,
+    time: LexedTime,
+    offset: LexedOffset,
+    span: Span,
+    offset_span: Span,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct LexedDate {
+    year: Option<i32>,
+    month: u32,
+    day: u32,
+    span: Span,
+    month_span: Span,
+    day_span: Span,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct LexedTime {
+    hour: u32,
+    min: u32,
+    sec: u32,
+    nano: u32,
+    span: Span,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LexedOffset {
+    Utc,
+    /// The time zone offset in seconds; positive values are in the Eastern
+    /// hemisphere.
+    FixedOffset(i32),
+    LocalTime,
+}
+
+peg::parser! {
+    grammar time_span_parser() for str {
+        use std::ops::RangeInclusive;
+
+        rule decimal_int(digits: RangeInclusive<usize>) -> u32 =
+            num:$(['0'..='9']*<{*digits.start()},{*digits.end()}>) {? num.parse().or(Err("smaller number")) }
+
+        rule utc_offset() = "Z"
+
+        rule sign() -> i32 = "+" { 1 } / "-" { -1 }
+
+        /// Parses +HH:MM or -HH:MM as a signed number of seconds.
+        rule fixed_offset() -> i32 = s:sign() hours:decimal_int(1..=2) ":" mins:decimal_int(2..=2) {
+            s * (hours as i32 * 3600 + mins as i32 * 60)
+        }
+
+        rule offset() -> LexedOffset = utc_offset() { LexedOffset::Utc } / o:fixed_offset() { LexedOffset::FixedOffset(o) } / { LexedOffset::LocalTime }
+
+        /// Parses the digits after a decimal point as nanoseconds.
+        rule fraction() -> u32 = "." f:$(['0'..='9']*<1,9>) {
+            format!("{f:0<9}").parse().unwrap()
+        }
+
+        rule seconds() -> (u32, u32) = ":" s:decimal_int(2..=2) n:fraction()? { (s, n.unwrap_or(0)) }
+
+        rule time() -> LexedTime = start:position!() h:decimal_int(1..=2) ":" m:decimal_int(2..=2) s:seconds()? end:position!() {
+            let (sec, nano) = s.unwrap_or((0, 0));
+            LexedTime { hour: h, min: m, sec, nano, span: Span(start, end) }
+        }
+
+        rule date() -> LexedDate = start:position!() y:(y:decimal_int(4..=4) "-" { y })?
+            month_start:position!() m:decimal_int(1..=2) month_end:position!() "-"
+            day_start:position!() d:decimal_int(1..=2) end:position!()
+        {
+            LexedDate {
+                year: y.map(|y| y as i32),
+                month: m,
+                day: d,
+                span: Span(start, end),
+                month_span: Span(month_start, month_end),
+                day_span: Span(day_start, end),
+            }
+        }
+
+        rule instant() -> LexedInstant = start:position!() d:date() "T" t:time() offset_start:position!() o:offset() end:position!() {
+            LexedInstant { date: d, time: t, offset: o, span: Span(start, end), offset_span: Span(offset_start, end) }
+        }
+
+        pub rule time_span() -> LexedTimeSpan = (start:instant() "/" end:instant() {
+            LexedTimeSpan::InstantIntervalStartEnd { start, end }
+        }) / (start:instant() "/" end_time:time() {
+            LexedTimeSpan::InstantIntervalStartEnd { start, end: LexedInstant { time: end_time, span: end_time.span, ..start } }
+        }) / (start:instant() {
+            LexedTimeSpan::Instant(start)
+        }) / (start:date() "/" end:date() {
+            LexedTimeSpan::DateIntervalStartEnd { start, end }
+        }) / (start:date() "/" duration:decimal_int(1..=usize::MAX) {
+            LexedTimeSpan::DateIntervalStartDuration { start, duration_days: Some(duration) }
+        }) / (start:date() {
+            LexedTimeSpan::DateIntervalStartDuration { start, duration_days: None }
+        })
+    }
+}
+
+impl<Tz: TimeZone> TimeSpanParser<Tz> {
+    /// Creates a parser that reads times without an offset in the zone of
+    /// `now`, and dates without a year as their next occurrence from the day
+    /// of `now`.
+    pub fn new(now: DateTime<Tz>) -> Self {
+        Self {
+            now,
+            disambiguation: Disambiguation::default(),
+            year_inference: YearInference::default(),
+        }
+    }
+
+    /// Sets how the year of a date written without one is picked.
+    pub fn with_year_inference(mut self, year_inference: YearInference) -> Self {
+        self.year_inference = year_inference;
+        self
+    }
+
+    /// Sets how times without an offset are read when the clocks change
+    /// around them. Whole days always start at the first moment of the day.
+    pub fn with_disambiguation(mut self, disambiguation: Disambiguation) -> Self {
+        self.disambiguation = disambiguation;
+        self
+    }
+
+    pub fn parse(&self, input: &str) -> Result<TimeSpan, TimeSpanParseError> {
+        let lexed = time_span_parser::time_span(input).map_err(|e| {
+            let offset = e.location.offset;
+            let found = input[offset..].chars().next();
+            TimeSpanParseError {
+                position: offset..offset + found.map_or(0, char::len_utf8),
+                kind: TimeSpanParseErrorKind::Syntax {
+                    expected: e.expected,
+                    found,
+                },
+            }
+        })?;
+        match lexed {
+            LexedTimeSpan::Instant(instant) => {
+                let date = self.date(instant.date, None)?;
+                Ok(TimeSpan::Instant(self.instant(instant, date)?))
+            }
+            LexedTimeSpan::InstantIntervalStartEnd { start, end } => {
+                let start_date = self.date(start.date, None)?;
+                let end_date = self.date(end.date, Some(start_date))?;
+                interval(
+                    self.instant(start, start_date)?,
+                    self.instant(end, end_date)?,
+                    end.span,
+                )
+            }
+            LexedTimeSpan::DateIntervalStartDuration {
+                start,
+                duration_days,
+            } => {
+                let start_date = self.date(start, None)?;
+                let end = start_date
+                    .checked_add_days(Days::new(duration_days.unwrap_or(1).into()))
+                    .ok_or(Span(0, input.len()).error(TimeSpanParseErrorKind::TooLong))?;
+                interval(
+                    self.start_of_day(start_date, start.span)?,
+                    self.start_of_day(end, start.span)?,
+                    Span(0, input.len()),
+                )
+            }
+            LexedTimeSpan::DateIntervalStartEnd { start, end } => {
+                // the end date is included, so the span lasts until the day
+                // after it starts
+                let start_date = self.date(start, None)?;
+                let end_date = self.date(end, Some(start_date))?;
+                let after_end = end_date
+                    .checked_add_days(Days::new(1))
+                    .ok_or(end.span.error(TimeSpanParseErrorKind::TooLong))?;
+                interval(
+                    self.start_of_day(start_date, start.span)?,
+                    self.start_of_day(after_end, end.span)?,
+                    end.span,
+                )
+            }
+        }
+    }
+
+    /// Resolves a date, picking a missing year with the parser's inference,
+    /// or as the first occurrence on or after `start` for the end of an
+    /// interval.
+    fn date(
+        &self,
+        date: LexedDate,
+        start: Option<NaiveDate>,
+    ) -> Result<NaiveDate, TimeSpanParseError> {
+        let LexedDate {
+            year, month, day, ..
+        } = date;
+        let resolved = match (year, start) {
+            (Some(year), _) => NaiveDate::from_ymd_opt(year, month, day),
+            (None, Some(start)) => YearInference::Next.infer(month, day, start),
+            (None, None) => self.year_inference.infer(month, day, self.now.date_naive()),
+        };
+        resolved.ok_or_else(|| {
+            let year = year.unwrap_or_else(|| self.now.year());
+            if (1..=12).contains(&month) {
+                date.day_span
+                    .error(TimeSpanParseErrorKind::InvalidDay { year, month, day })
+            } else {
+                date.month_span
+                    .error(TimeSpanParseErrorKind::InvalidMonth { month, day })
+            }
+        })
+    }
+
+    /// Resolves an instant on an already resolved date.
+    fn instant(
+        &self,
+        instant: LexedInstant,
+        date: NaiveDate,
+    ) -> Result<DateTime<Utc>, TimeSpanParseError> {
+        let LexedInstant { time, offset, .. } = instant;
+        let LexedTime {
+            hour,
+            min,
+            sec,
+            nano,
+            ..
+        } = time;
+
+        let naive = date.and_hms_nano_opt(hour, min, sec, nano).ok_or(
+            time.span
+                .error(TimeSpanParseErrorKind::InvalidTime { hour, min, sec }),
+        )?;
+        let resolved = match offset {
+            LexedOffset::Utc => Ok(naive.and_utc()),
+            LexedOffset::FixedOffset(seconds) => {
+                let offset = FixedOffset::east_opt(seconds).ok_or(
+                    instant
+                        .offset_span
+                        .error(TimeSpanParseErrorKind::InvalidOffset(seconds)),
+                )?;
+                self.disambiguation.resolve(&offset, naive)
+            }
+            LexedOffset::LocalTime => self.disambiguation.resolve(&self.now.timezone(), naive),
+        };
+        resolved.map_err(|e| {
+            instant.span.error(match e {
+                LocalTimeError::Ambiguous {
+                    local,
+                    earliest,
+                    latest,
+                } => TimeSpanParseErrorKind::AmbiguousTime {
+                    local,
+                    earliest,
+                    latest,
+                },
+                LocalTimeError::Nonexistent(local) => {
+                    TimeSpanParseErrorKind::NonexistentTime(local)
+                }
+                LocalTimeError::EndBeforeStart => TimeSpanParseErrorKind::EndBeforeStart,
+            })
+        })
+    }
+
+    /// The first moment of a day, which is midnight unless the clocks skip it.
+    fn start_of_day(
+        &self,
+        date: NaiveDate,
+        span: Span,
+    ) -> Result<DateTime<Utc>, TimeSpanParseError> {
+        start_of_day(&self.now.timezone(), date)
+            .map(|dt| dt.to_utc())
+            .ok_or(span.error(TimeSpanParseErrorKind::NonexistentTime(
+                date.and_time(NaiveTime::MIN),
+            )))
+    }
+}
+
+/// Makes an interval, or an error pointing at `end_span` if it would end
+/// before it starts.
+fn interval(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    end_span: Span,
+) -> Result<TimeSpan, TimeSpanParseError> {
+    if end < start {
+        return Err(end_span.error(TimeSpanParseErrorKind::EndBeforeStart));
+    }
+    Ok(TimeSpan::Interval {
+        start,
+        duration: end - start,
+    })
+}
+
+/// Parses a time span relative to the current time in the local zone. See
+/// [`TimeSpanParser`] to parse relative to another time or in another zone.
+impl std::str::FromStr for TimeSpan {
+    type Err = TimeSpanParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TimeSpanParser::new(Local::now()).parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    // TODO fuzzing test
+
+    fn parse(input: &str) -> Option<TimeSpan> {
+        input.parse().ok()
+    }
+
+    #[test]
+    fn parse_date_and_time_with_utc_offset() {
+        let input = "2023-10-05T14:30:00Z";
+        let expected = Utc.with_ymd_and_hms(2023, 10, 5, 14, 30, 0).unwrap();
+        assert_eq!(parse(input), Some(TimeSpan::Instant(expected)));
+    }
+
+    #[test]
+    fn parse_date_and_time_in_a_given_zone() {
+        let input = "2023-10-05T14:30";
+        let expected = Utc.with_ymd_and_hms(2023, 10, 5, 12, 30, 0).unwrap();
+        let parser = TimeSpanParser::new(Utc::now().with_timezone(&chrono_tz::Europe::Berlin));
+        assert_eq!(parser.parse(input).ok(), Some(TimeSpan::Instant(expected)));
+        // an explicit offset wins over the zone
+        assert_eq!(
+            parser.parse("2023-10-05T12:30Z").ok(),
+            Some(TimeSpan::Instant(expected))
+        );
+    }
+
+    #[test]
+    fn parse_date_and_time_with_positive_offset() {
+        let input = "2023-10-05T14:30:00+02:00";
+        let expected = Utc.with_ymd_and_hms(2023, 10, 5, 12, 30, 0).unwrap();
+        assert_eq!(parse(input), Some(TimeSpan::Instant(expected)));
+    }
+
+    #[test]
+    fn parse_date_and_time_with_negative_offset() {
+        let input = "2023-10-05T14:30:00-02:00";
+        let expected = Utc.with_ymd_and_hms(2023, 10, 5, 16, 30, 0).unwrap();
+        assert_eq!(parse(input), Some(TimeSpan::Instant(expected)));
+    }
+
+    #[test]
+    fn parse_date_and_time_without_offset() {
+        let input = "2023-10-05T14:30:00";
+        let expected = Local
+            .with_ymd_and_hms(2023, 10, 5, 14, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse(input), Some(TimeSpan::Instant(expected)));
+    }
+
+    #[test]
+    fn parse_date_and_time_without_seconds() {
+        let input = "2023-10-05T14:30";
+        let expected = Local
+            .with_ymd_and_hms(2023, 10, 5, 14, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(parse(input), Some(TimeSpan::Instant(expected)));
+    }
+
+    #[test]
+    fn parse_date_and_time_without_seconds_with_offset() {
+        let input = "2023-10-05T14:30+02:00";
+        let expected = Utc.with_ymd_and_hms(2023, 10, 5, 12, 30, 0).unwrap();
+        assert_eq!(parse(input), Some(TimeSpan::Instant(expected)));
+    }
+
+    #[test]
+    fn parse_date_and_time_without_years() {
+        let input = "10-05T14:30:00";
+        let now = Local.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let expected = Local
+            .with_ymd_and_hms(now.year(), 10, 5, 14, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            TimeSpanParser::new(now).parse(input).ok(),
+            Some(TimeSpan::Instant(expected))
+        );
+    }
+
+    #[test]
+    fn parse_instant_interval_with_utc_offset() {
+        let input = "2023-10-05T14:30:00Z/2023-10-05T16:30:00Z";
+        let start = Utc.with_ymd_and_hms(2023, 10, 5, 14, 30, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 10, 5, 16, 30, 0).unwrap();
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_instant_interval_with_positive_offset() {
+        let input = "2023-10-05T14:30:00+02:00/2023-10-05T16:30:00+02:00";
+        let start = Utc.with_ymd_and_hms(2023, 10, 5, 12, 30, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 10, 5, 14, 30, 0).unwrap();
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_instant_interval_with_negative_offset() {
+        let input = "2023-10-05T14:30:00-02:00/2023-10-05T16:30:00-02:00";
+        let start = Utc.with_ymd_and_hms(2023, 10, 5, 16, 30, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 10, 5, 18, 30, 0).unwrap();
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_instant_interval_without_offset() {
+        let input = "2023-10-05T14:30:00/2023-10-05T16:30:00";
+        let start = Local
+            .with_ymd_and_hms(2023, 10, 5, 14, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let end = Local
+            .with_ymd_and_hms(2023, 10, 5, 16, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_instant_interval_without_seconds() {
+        let input = "2023-10-05T14:30/2023-10-05T16:30";
+        let start = Local
+            .with_ymd_and_hms(2023, 10, 5, 14, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let end = Local
+            .with_ymd_and_hms(2023, 10, 5, 16, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_instant_interval_without_seconds_with_offset() {
+        let input = "2023-10-05T14:30+02:00/2023-10-05T16:30+02:00";
+        let start = Utc.with_ymd_and_hms(2023, 10, 5, 12, 30, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 10, 5, 14, 30, 0).unwrap();
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_instant_interval_omitting_end_date_and_offset() {
+        let input = "2023-10-05T14:30:00/16:30:00";
+        let start = Local
+            .with_ymd_and_hms(2023, 10, 5, 14, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let end = Local
+            .with_ymd_and_hms(2023, 10, 5, 16, 30, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        let duration = end - start;
+        assert_eq!(parse(input), Some(TimeSpan::Interval { start, duration }));
+    }
+
+    #[test]
+    fn parse_whole_days() {
+        let parser = TimeSpanParser::new(Utc::now());
+        let day = |d| Utc.with_ymd_and_hms(2023, 10, d, 0, 0, 0).unwrap();
+        let days = |from, to| TimeSpan::Interval {
+            start: day(from),
+            duration: day(to) - day(from),
+        };
+        assert_eq!(parser.parse("2023-10-05").unwrap(), days(5, 6));
+        assert_eq!(parser.parse("2023-10-05/3").unwrap(), days(5, 8));
+        assert_eq!(parser.parse("2023-10-05/2023-10-07").unwrap(), days(5, 8));
+    }
+
+    #[test]
+    fn rejects_impossible_spans() {
+        let parser = TimeSpanParser::new(Utc::now());
+        let error = |input| parser.parse(input).unwrap_err();
+        let at = |position, kind| TimeSpanParseError { position, kind };
+        assert_eq!(
+            error("2023-13-05"),
+            at(
+                5..7,
+                TimeSpanParseErrorKind::InvalidMonth { month: 13, day: 5 }
+            )
+        );
+        assert_eq!(
+            error("2023-02-29T09:00"),
+            at(
+                8..10,
+                TimeSpanParseErrorKind::InvalidDay {
+                    year: 2023,
+                    month: 2,
+                    day: 29
+                }
+            )
+        );
+        assert_eq!(
+            error("2023-10-05T25:00"),
+            at(
+                11..16,
+                TimeSpanParseErrorKind::InvalidTime {
+                    hour: 25,
+                    min: 0,
+                    sec: 0
+                }
+            )
+        );
+        assert_eq!(
+            error("2023-10-05T14:30+24:00"),
+            at(16..22, TimeSpanParseErrorKind::InvalidOffset(24 * 3600))
+        );
+        assert_eq!(
+            error("2023-10-05T14:30/13:30"),
+            at(17..22, TimeSpanParseErrorKind::EndBeforeStart)
+        );
+        assert_eq!(
+            error("2023-10-05/2023-10-03"),
+            at(11..21, TimeSpanParseErrorKind::EndBeforeStart)
+        );
+    }
+
+    #[test]
+    fn reports_what_was_expected() {
+        let parser = TimeSpanParser::new(Utc::now());
+        let error = parser.parse("2023-10-05 14:30").unwrap_err();
+        assert_eq!(error.position, 10..11);
+        let TimeSpanParseErrorKind::Syntax { expected, found } = &error.kind else {
+            panic!("{error:?} is not a syntax error");
+        };
+        assert_eq!(*found, Some(' '));
+        assert!(expected.tokens().any(|token| token == "\"T\""));
+        assert!(error.help().is_some());
+
+        // errors at the end point just past the input
+        let error = parser.parse("2023-10-05T14:").unwrap_err();
+        assert_eq!(error.position, 14..14);
+        assert_eq!(
+            error.to_string(),
+            "expected ['0'..='9'], found the end at position 14"
+        );
+    }
+
+    #[test]
+    fn applies_the_disambiguation_policy() {
+        // the clocks go back from 03:00 to 02:00 on October 27th
+        let now = Utc::now().with_timezone(&chrono_tz::Europe::Berlin);
+        let parse = |policy| {
+            TimeSpanParser::new(now)
+                .with_disambiguation(policy)
+                .parse("2024-10-27T02:30/03:30")
+        };
+        let at = |h| Utc.with_ymd_and_hms(2024, 10, 27, h, 30, 0).unwrap();
+        assert_eq!(
+            parse(Disambiguation::Earliest).unwrap(),
+            TimeSpan::Interval {
+                start: at(0),
+                duration: TimeDelta::hours(2)
+            }
+        );
+        assert_eq!(
+            parse(Disambiguation::Latest).unwrap(),
+            TimeSpan::Interval {
+                start: at(1),
+                duration: TimeDelta::hours(1)
+            }
+        );
+        let error = parse(Disambiguation::Reject).unwrap_err();
+        assert_eq!(error.position, 0..16);
+        assert_eq!(
+            error.kind,
+            TimeSpanParseErrorKind::AmbiguousTime {
+                local: NaiveDate::from_ymd_opt(2024, 10, 27)
+                    .unwrap()
+                    .and_hms_opt(2, 30, 0)
+                    .unwrap(),
+                earliest: at(0),
+                latest: at(1),
+            }
+        );
+        // an offset settles it
+        assert!(TimeSpanParser::new(now)
+            .with_disambiguation(Disambiguation::Reject)
+            .parse("2024-10-27T02:30+01:00")
+            .is_ok());
+    }
+
+    #[test]
+    fn infers_years_relative_to_the_reference() {
+        let now = Utc.with_ymd_and_hms(2023, 12, 28, 12, 0, 0).unwrap();
+        let day = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+        let parse = |inference, input| {
+            TimeSpanParser::new(now)
+                .with_year_inference(inference)
+                .parse(input)
+                .map(|span| span.earliest())
+        };
+        assert_eq!(parse(YearInference::Current, "01-05"), Ok(day(2023, 1, 5)));
+        assert_eq!(parse(YearInference::Next, "01-05"), Ok(day(2024, 1, 5)));
+        assert_eq!(parse(YearInference::Next, "12-28"), Ok(day(2023, 12, 28)));
+        assert_eq!(parse(YearInference::Nearest, "01-05"), Ok(day(2024, 1, 5)));
+        assert_eq!(
+            parse(YearInference::Nearest, "11-15"),
+            Ok(day(2023, 11, 15))
+        );
+        // the next leap day
+        assert_eq!(parse(YearInference::Next, "02-29"), Ok(day(2024, 2, 29)));
+        assert_eq!(
+            parse(YearInference::Current, "02-29").unwrap_err().kind,
+            TimeSpanParseErrorKind::InvalidDay {
+                year: 2023,
+                month: 2,
+                day: 29
+            }
+        );
+    }
+
+    #[test]
+    fn intervals_without_years_cross_the_new_year() {
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let parser = TimeSpanParser::new(now).with_year_inference(YearInference::Current);
+        let day = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+        assert_eq!(
+            parser.parse("12-30/01-02").unwrap(),
+            TimeSpan::Interval {
+                start: day(2023, 12, 30),
+                duration: day(2024, 1, 3) - day(2023, 12, 30),
+            }
+        );
+        assert_eq!(
+            parser.parse("2023-12-31T22:00Z/01-01T02:00Z").unwrap(),
+            TimeSpan::Interval {
+                start: day(2023, 12, 31) + TimeDelta::hours(22),
+                duration: TimeDelta::hours(4),
+            }
+        );
+        // an end time alone stays on the start's day
+        assert_eq!(
+            parser.parse("12-31T22:00Z/23:00").unwrap().duration(),
+            TimeDelta::hours(1)
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let start = Utc.with_ymd_and_hms(2023, 10, 5, 14, 30, 0).unwrap();
+        let spans = [
+            TimeSpan::Instant(start),
+            TimeSpan::Instant(start + TimeDelta::nanoseconds(1_500)),
+            TimeSpan::Interval {
+                start,
+                duration: TimeDelta::minutes(90),
+            },
+            TimeSpan::Interval {
+                start: start + TimeDelta::milliseconds(250),
+                duration: TimeDelta::days(3) + TimeDelta::seconds(1),
+            },
+            TimeSpan::Interval {
+                start,
+                duration: TimeDelta::zero(),
+            },
+        ];
+        for span in spans {
+            assert_eq!(span.to_string().parse::<TimeSpan>().unwrap(), span);
+        }
+        assert_eq!(
+            spans[2].to_string(),
+            "2023-10-05T14:30:00Z/2023-10-05T16:00:00Z"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Serde support for the domain types, enabled by the `serde` feature.
+//!
+//! Times are RFC 3339 strings in UTC, and lengths of time are ISO 8601
+//! durations in whole seconds, like `-PT15M`. A [`TimeSpan`] is an object
+//! tagged by its kind:
+//!
+//! ```json
+//! {"type": "instant", "at": "2024-06-10T09:00:00Z"}
+//! {"type": "interval", "start": "2024-06-10T09:00:00Z", "end": "2024-06-10T10:30:00Z"}
+//! ```
+//!
+//! An interval is stored by its endpoints rather than its duration so that it
+//! round-trips exactly, whatever its precision.
+
+use chrono::prelude::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::TimeSpan;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum TimeSpanRepr {
+    Instant { at: String },
+    Interval { start: String, end: String },
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+fn parse_time<E: de::Error>(value: &str) -> Result<DateTime<Utc>, E> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.to_utc())
+        .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &"an RFC 3339 time"))
+}
+
+impl Serialize for TimeSpan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TimeSpan::Instant(at) => TimeSpanRepr::Instant {
+                at: format_time(*at),
+            },
+            TimeSpan::Interval { .. } => TimeSpanRepr::Interval {
+                start: format_time(self.earliest()),
+                end: format_time(self.latest()),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeSpan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match TimeSpanRepr::deserialize(deserializer)? {
+            TimeSpanRepr::Instant { at } => Ok(TimeSpan::Instant(parse_time(&at)?)),
+            TimeSpanRepr::Interval { start, end } => {
+                let start = parse_time(&start)?;
+                let end = parse_time(&end)?;
+                if end < start {
+                    return Err(de::Error::custom("interval ends before it starts"));
+                }
+                Ok(TimeSpan::Interval {
+                    start,
+                    duration: end - start,
+                })
+            }
+        }
+    }
+}
+
+/// For `#[serde(with)]` on `TimeDelta` fields.
+pub(super) mod duration {
+    use chrono::TimeDelta;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::ical;
+
+    pub fn serialize<S: Serializer>(
+        duration: &TimeDelta,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&ical::format_duration(*duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TimeDelta, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        ical::parse_duration(&value).ok_or_else(|| {
+            de::Error::invalid_value(de::Unexpected::Str(&value), &"an ISO 8601 duration")
+        })
+    }
+}
+
+/// For `#[serde(with)]` on `Option<TimeDelta>` fields.
+pub(super) mod option_duration {
+    use chrono::TimeDelta;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<TimeDelta>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::duration::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<TimeDelta>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super::duration")] TimeDelta);
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(duration)| duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::domain::{Alarm, AlarmState, AlarmTrigger, EventInstance};
+
+    #[test]
+    fn time_span_representation_is_stable() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let instant = TimeSpan::Instant(start);
+        let interval = TimeSpan::Interval {
+            start,
+            duration: TimeDelta::minutes(90) + TimeDelta::nanoseconds(5),
+        };
+        assert_eq!(
+            serde_json::to_string(&instant).unwrap(),
+            r#"{"type":"instant","at":"2024-06-10T09:00:00Z"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&interval).unwrap(),
+            r#"{"type":"interval","start":"2024-06-10T09:00:00Z","end":"2024-06-10T10:30:00.000000005Z"}"#
+        );
+        for time_span in [instant, interval] {
+            let json = serde_json::to_string(&time_span).unwrap();
+            assert_eq!(serde_json::from_str::<TimeSpan>(&json).unwrap(), time_span);
+        }
+        assert!(serde_json::from_str::<TimeSpan>(
+            r#"{"type":"interval","start":"2024-06-10T09:00:00Z","end":"2024-06-10T08:00:00Z"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn instance_round_trips() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let instance = EventInstance {
+            time_span: TimeSpan::Instant(start),
+            body: 7,
+            actual: None,
+            outcome: None,
+            alarms: vec![Alarm {
+                uid: "reminder".to_owned(),
+                trigger: AlarmTrigger::Start(-TimeDelta::minutes(15)),
+                description: String::new(),
+            }],
+            alarm_states: [("reminder".to_owned(), AlarmState::Snoozed(start))].into(),
+            uid: None,
+        };
+        let json = serde_json::to_value(&instance).unwrap();
+        assert_eq!(json["alarms"][0]["trigger"]["start"], "-PT15M");
+        assert_eq!(
+            json["alarm_states"]["reminder"]["snoozed"],
+            "2024-06-10T09:00:00Z"
+        );
+        let back: EventInstance<u32> = serde_json::from_value(json).unwrap();
+        assert_eq!(back.alarms, instance.alarms);
+        assert_eq!(back.alarm_states, instance.alarm_states);
+    }
+}
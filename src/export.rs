@@ -0,0 +1,422 @@
+//! Dumping a whole repository to JSON and restoring it, for backups and for
+//! moving data between backends or machines.
+//!
+//! The document is self-describing and does not depend on the ID types of
+//! any backend: items refer to each other by their position in the document,
+//! and are given new IDs by the repository they are imported into.
+//!
+//! ```json
+//! {
+//!   "format": "metime",
+//!   "version": 3,
+//!   "bodies": [{"summary": "Standup", ...}],
+//!   "instances": [{"time_span": {...}, "body": 0, ...}],
+//!   "timeline": {"events": {"2024-06-10T09:00:00Z": 0}},
+//!   "tasks": [{"summary": "Write report", "scheduled": null, ...}],
+//!   "calendars": [{"name": "work", "events": [0], ...}]
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+use chrono::{DateTime, Utc};
+use derive_more::derive::{Display, Error, From};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    domain::{Calendar, EventBody, EventInstance, Task, Timeline},
+    repository::{RepoRetrievalError, Repository},
+};
+
+pub use migrate::SCHEMA_VERSION;
+
+mod migrate;
+
+const FORMAT: &str = "metime";
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    format: String,
+    version: u32,
+    bodies: Vec<EventBody>,
+    instances: Vec<EventInstance<usize>>,
+    timeline: Timeline<usize>,
+    tasks: Vec<Task<usize>>,
+    calendars: Vec<Calendar<usize>>,
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum ExportError {
+    #[display("could not read the repository: {_0}")]
+    Retrieval(RepoRetrievalError),
+    #[display("could not write the document: {_0}")]
+    Json(serde_json::Error),
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum ImportError {
+    #[display("could not read the repository: {_0}")]
+    Retrieval(RepoRetrievalError),
+    #[display("invalid document: {_0}")]
+    Json(serde_json::Error),
+    #[display("not a metime document")]
+    #[from(ignore)]
+    WrongFormat,
+    #[display("unsupported document version {_0}")]
+    #[from(ignore)]
+    UnsupportedVersion(#[error(not(source))] u64),
+    #[display("could not upgrade the document from version {from}: {reason}")]
+    #[from(ignore)]
+    Migration {
+        from: u64,
+        #[error(not(source))]
+        reason: String,
+    },
+    #[display("the document refers to {_0}, which it does not contain")]
+    #[from(ignore)]
+    DanglingReference(#[error(not(source))] String),
+    /// The timeline holds one event per start time, and the repository
+    /// already has one at a time that the document uses.
+    #[display("there is already an event starting at {_0}")]
+    #[from(ignore)]
+    TimelineCollision(#[error(not(source))] DateTime<Utc>),
+}
+
+/// Writes every event instance on the timeline, their bodies, every task on
+/// the task list and every calendar to `out` as a JSON document.
+pub fn export_repo<R: Repository>(repo: &R, out: impl Write) -> Result<(), ExportError>
+where
+    R::EventBodyId: Ord,
+    R::EventInstanceId: Ord,
+{
+    let mut body_indices = BTreeMap::new();
+    let mut bodies = Vec::new();
+    let mut instance_indices = BTreeMap::new();
+    let mut instances = Vec::new();
+    let mut timeline = Timeline::new();
+
+    let repo_timeline = repo
+        .get_timeline()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    for (&time, &instance_id) in &repo_timeline.events {
+        let instance = repo.get_event_instance(instance_id)?;
+        let body = match body_indices.get(&instance.body) {
+            Some(&index) => index,
+            None => {
+                bodies.push(repo.get_event_body(instance.body)?.clone());
+                body_indices.insert(instance.body, bodies.len() - 1);
+                bodies.len() - 1
+            }
+        };
+        instances.push(with_body(&instance, body));
+        instance_indices.insert(instance_id, instances.len() - 1);
+        timeline.events.insert(time, instances.len() - 1);
+    }
+
+    let mut tasks = Vec::new();
+    let task_list = repo
+        .get_task_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    for &task_id in &task_list.tasks {
+        let task = repo.get_task(task_id)?;
+        tasks.push(Task {
+            // instances that are not on the timeline are not exported
+            scheduled: task
+                .scheduled
+                .and_then(|id| instance_indices.get(&id).copied()),
+            ..unscheduled_copy(&task)
+        });
+    }
+
+    let mut calendars = Vec::new();
+    let calendar_list = repo
+        .get_calendar_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    for calendar in &calendar_list.calendars {
+        calendars.push(Calendar {
+            name: calendar.name.clone(),
+            colour: calendar.colour.clone(),
+            default_zone: calendar.default_zone,
+            events: calendar
+                .events
+                .iter()
+                .filter_map(|id| instance_indices.get(id).copied())
+                .collect(),
+        });
+    }
+
+    let document = Document {
+        format: FORMAT.to_owned(),
+        version: SCHEMA_VERSION,
+        bodies,
+        instances,
+        timeline,
+        tasks,
+        calendars,
+    };
+    serde_json::to_writer_pretty(out, &document)?;
+    Ok(())
+}
+
+/// Adds everything in a document written by [`export_repo`], of this or any
+/// earlier version, to `repo`, alongside what is already there. Nothing is
+/// added unless the whole document is valid, none of its events start at the
+/// same time as one already on the timeline, and the timeline, task list and
+/// calendar list can all be retrieved.
+pub fn import_repo<R: Repository>(repo: &R, input: impl Read) -> Result<(), ImportError> {
+    let mut document: Value = serde_json::from_reader(input)?;
+    if document.get("format").and_then(Value::as_str) != Some(FORMAT) {
+        return Err(ImportError::WrongFormat);
+    }
+    migrate::migrate(&mut document)?;
+    let document: Document = serde_json::from_value(document)?;
+    check_references(&document)?;
+    // everything the import adds to is retrieved up front, so that nothing is
+    // added if any of it is unavailable
+    let mut timeline = repo
+        .get_timeline()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    let mut task_list = repo
+        .get_task_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    let mut calendar_list = repo
+        .get_calendar_list()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    if let Some(&time) = document
+        .timeline
+        .events
+        .keys()
+        .find(|time| timeline.events.contains_key(time))
+    {
+        return Err(ImportError::TimelineCollision(time));
+    }
+
+    let body_ids: Vec<_> = document
+        .bodies
+        .into_iter()
+        .map(|body| repo.add_event_body(body).0)
+        .collect();
+    let instance_ids: Vec<_> = document
+        .instances
+        .iter()
+        .map(|instance| {
+            repo.add_event_instance(with_body(instance, body_ids[instance.body]))
+                .0
+        })
+        .collect();
+    for (time, index) in document.timeline.events {
+        timeline.events.insert(time, instance_ids[index]);
+    }
+    for task in document.tasks {
+        let task = Task {
+            scheduled: task.scheduled.map(|index| instance_ids[index]),
+            ..unscheduled_copy(&task)
+        };
+        task_list.tasks.push(repo.add_task(task).0);
+    }
+    for calendar in document.calendars {
+        // calendars that are already in the repository gain the imported
+        // events rather than being added again
+        let events = calendar.events.iter().map(|&index| instance_ids[index]);
+        match calendar_list.get_mut(&calendar.name) {
+            Some(existing) => existing.events.extend(events),
+            None => calendar_list.calendars.push(Calendar {
+                name: calendar.name,
+                colour: calendar.colour,
+                default_zone: calendar.default_zone,
+                events: events.collect(),
+            }),
+        }
+    }
+    Ok(())
+}
+
+fn check_references(document: &Document) -> Result<(), ImportError> {
+    let dangling =
+        |what: &str, index: usize| ImportError::DanglingReference(format!("{what} {index}"));
+    for instance in &document.instances {
+        if instance.body >= document.bodies.len() {
+            return Err(dangling("body", instance.body));
+        }
+    }
+    let instances = document.timeline.events.values();
+    let scheduled = document.tasks.iter().filter_map(|task| task.scheduled);
+    let in_calendars = document
+        .calendars
+        .iter()
+        .flat_map(|calendar| calendar.events.iter());
+    for index in instances.chain(in_calendars).copied().chain(scheduled) {
+        if index >= document.instances.len() {
+            return Err(dangling("instance", index));
+        }
+    }
+    Ok(())
+}
+
+/// Copies an instance, replacing the reference to its body.
+fn with_body<B, C>(instance: &EventInstance<B>, body: C) -> EventInstance<C> {
+    EventInstance {
+        time_span: instance.time_span,
+        body,
+        actual: instance.actual,
+        outcome: instance.outcome,
+        alarms: instance.alarms.clone(),
+        alarm_states: instance.alarm_states.clone(),
+        uid: instance.uid.clone(),
+    }
+}
+
+/// Copies a task, leaving it unscheduled so that the caller can fill in a
+/// reference to an instance of its own.
+fn unscheduled_copy<I, J>(task: &Task<I>) -> Task<J> {
+    Task {
+        summary: task.summary.clone(),
+        description: task.description.clone(),
+        categories: task.categories.clone(),
+        due: task.due,
+        estimated_duration: task.estimated_duration,
+        priority: task.priority,
+        percent_complete: task.percent_complete,
+        completed_at: task.completed_at,
+        scheduled: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use super::*;
+    use crate::{MemoryRepo, TimeSpan};
+
+    #[test]
+    fn repository_round_trips() {
+        let repo = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let (_, body_id, _, mut body) = crate::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Standup".to_owned(),
+            String::new(),
+        );
+        body.categories.push("work".to_owned());
+        drop(body);
+        // a second instance of the same body
+        let later = TimeSpan::Instant(start + chrono::TimeDelta::days(1));
+        let (later_id, _) = crate::add_event_instance(&repo, body_id, later);
+        let (task_id, _) = crate::add_task(&repo, Task::new("Write report".to_owned()));
+        repo.get_task(task_id).unwrap().scheduled = Some(later_id);
+        let work = Calendar {
+            colour: "#3465a4".to_owned(),
+            default_zone: Some(chrono_tz::Europe::Berlin),
+            ..Calendar::new("work".to_owned())
+        };
+        crate::add_calendar(&repo, work).unwrap();
+        crate::move_to_calendar(&repo, later_id, "work").unwrap();
+
+        let mut json = Vec::new();
+        export_repo(&repo, &mut json).unwrap();
+
+        let restored = MemoryRepo::new();
+        import_repo(&restored, json.as_slice()).unwrap();
+        let mut again = Vec::new();
+        export_repo(&restored, &mut again).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            String::from_utf8(again).unwrap()
+        );
+
+        let timeline = restored.get_timeline().unwrap();
+        let instances: Vec<_> = timeline
+            .events
+            .values()
+            .map(|&id| restored.get_event_instance(id).unwrap())
+            .collect();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].body, instances[1].body);
+        let task_id = restored.get_task_list().unwrap().tasks[0];
+        let scheduled = restored.get_task(task_id).unwrap().scheduled.unwrap();
+        assert_eq!(Some(&scheduled), timeline.events.values().nth(1));
+        let calendar_list = restored.get_calendar_list().unwrap();
+        assert_eq!(calendar_list.get("work").unwrap().events, [scheduled]);
+    }
+
+    #[test]
+    fn rejects_bad_documents() {
+        let repo = MemoryRepo::new();
+        let document = |format: &str, version: u32, body: usize| {
+            format!(
+                r#"{{"format": "{format}", "version": {version}, "bodies": [],
+                "instances": [{{"time_span": {{"type": "instant", "at": "2024-06-10T09:00:00Z"}},
+                "body": {body}, "actual": null, "outcome": null, "alarms": [],
+                "alarm_states": {{}}}}], "timeline": {{"events": {{}}}}, "tasks": [],
+                "calendars": []}}"#
+            )
+        };
+        let import = |json: String| import_repo(&repo, json.as_bytes()).unwrap_err();
+        assert!(matches!(
+            import(document("ical", 1, 0)),
+            ImportError::WrongFormat
+        ));
+        assert!(matches!(
+            import(document("metime", 99, 0)),
+            ImportError::UnsupportedVersion(99)
+        ));
+        assert!(matches!(
+            import(document("metime", 1, 0)),
+            ImportError::DanglingReference(_)
+        ));
+        assert!(repo.get_timeline().unwrap().events.is_empty());
+    }
+
+    #[test]
+    fn imports_alongside_existing_events() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let add = |repo: &MemoryRepo, start, summary: &str| {
+            crate::add_event(
+                repo,
+                TimeSpan::Instant(start),
+                summary.to_owned(),
+                String::new(),
+            )
+            .0
+        };
+        let source = MemoryRepo::new();
+        add(&source, start, "Standup");
+        let mut json = Vec::new();
+        export_repo(&source, &mut json).unwrap();
+
+        let repo = MemoryRepo::new();
+        let existing = add(&repo, start + chrono::TimeDelta::hours(1), "Review");
+        import_repo(&repo, json.as_slice()).unwrap();
+        let timeline = repo.get_timeline().unwrap();
+        assert_eq!(timeline.events.len(), 2);
+        assert_eq!(timeline.events.values().nth(1), Some(&existing));
+        let imported = *timeline.events.values().next().unwrap();
+        drop(timeline);
+        let body = repo.get_event_instance(imported).unwrap().body;
+        assert_eq!(repo.get_event_body(body).unwrap().summary, "Standup");
+
+        // importing again would put a second event at the same time
+        assert!(matches!(
+            import_repo(&repo, json.as_slice()),
+            Err(ImportError::TimelineCollision(time)) if time == start
+        ));
+        assert_eq!(repo.get_timeline().unwrap().events.len(), 2);
+
+        // nor can anything be imported while the calendars are borrowed
+        let target = MemoryRepo::new();
+        let calendar_list = target.get_calendar_list().unwrap();
+        assert!(matches!(
+            import_repo(&target, json.as_slice()),
+            Err(ImportError::Retrieval(RepoRetrievalError::AlreadyRetrieved))
+        ));
+        drop(calendar_list);
+        assert!(target.get_timeline().unwrap().events.is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+//! Upgrading documents written by older versions of [`export_repo`].
+//!
+//! Every change to the document format bumps [`SCHEMA_VERSION`] and adds a
+//! step to [`MIGRATIONS`] that rewrites a document of the previous version
+//! into the new one. Loading a document applies every step from its version
+//! up, so that only the latest format ever has to be deserialized.
+//!
+//! Each version also gets a fixture in `fixtures/`, the output of
+//! [`export_repo`] at the commit that introduced the version, which the tests
+//! load to check that old documents can still be read. Add one by checking
+//! out that commit (a `git worktree` keeps the current tree untouched) and
+//! exporting the same events as the other fixtures from a throwaway example
+//! program: a standup body with a five-minute alarm, an acknowledged instance
+//! of it on 2024-06-10 and a snoozed one on 2024-06-11 with an alarm of its
+//! own, and a task scheduled onto the second instance. Fields that a version
+//! adds take the values its migration gives them, such as the alarm UIDs of
+//! version 5, so that every fixture upgrades to the latest one.
+//!
+//! [`export_repo`]: super::export_repo
+
+use serde_json::{Map, Value};
+
+use super::ImportError;
+use crate::{domain::AlarmTrigger, ical};
+
+/// The version of the document written by [`export_repo`](super::export_repo).
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// Rewrites a document of one version into the next, or explains why the
+/// document cannot be upgraded.
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// `MIGRATIONS[i]` upgrades a document from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[Migration] = &[
+    // version 2 adds `location` to bodies
+    |document| {
+        for body in array(document, "bodies")? {
+            object(body, "body")?
+                .entry("location")
+                .or_insert(Value::String(String::new()));
+        }
+        Ok(())
+    },
+    // version 3 adds calendars
+    |document| {
+        document
+            .entry("calendars")
+            .or_insert(Value::Array(Vec::new()));
+        Ok(())
+    },
+    // version 4 adds `uid` to instances
+    |document| {
+        for instance in array(document, "instances")? {
+            object(instance, "instance")?
+                .entry("uid")
+                .or_insert(Value::Null);
+        }
+        Ok(())
+    },
+    // version 5 gives alarms a `uid`, by which instances keep their states
+    // instead of by where the alarms are
+    |document| {
+        let mut body_alarms = Vec::new();
+        for body in array(document, "bodies")? {
+            body_alarms.push(alarm_uids(object(body, "body")?)?);
+        }
+        for instance in array(document, "instances")? {
+            let instance = object(instance, "instance")?;
+            let own = alarm_uids(instance)?;
+            // a missing body is reported by the import, after migrating
+            let body = instance
+                .get("body")
+                .and_then(Value::as_u64)
+                .and_then(|body| body_alarms.get(body as usize))
+                .map_or(&[][..], Vec::as_slice);
+            let states = match instance.get_mut("alarm_states") {
+                Some(Value::Object(states)) => std::mem::take(states),
+                _ => return Err("`alarm_states` is not an object".to_owned()),
+            };
+            let mut rekeyed = Map::new();
+            for (source, state) in states {
+                let (kind, index) = source
+                    .split_once(':')
+                    .ok_or_else(|| format!("`{source}` is not an alarm source"))?;
+                let uids = match kind {
+                    "body" => body,
+                    "instance" => &own[..],
+                    _ => return Err(format!("`{source}` is not an alarm source")),
+                };
+                // states of alarms that no longer exist are dropped
+                let uid = index.parse().ok().and_then(|i: usize| uids.get(i));
+                if let Some(uid) = uid {
+                    rekeyed.insert(uid.clone(), state);
+                }
+            }
+            instance.insert("alarm_states".to_owned(), Value::Object(rekeyed));
+        }
+        Ok(())
+    },
+];
+
+/// Upgrades a document of any supported version to the current one.
+pub(super) fn migrate(document: &mut Value) -> Result<(), ImportError> {
+    let document = document.as_object_mut().ok_or(ImportError::WrongFormat)?;
+    let version = match document.get("version").map(Value::as_u64) {
+        Some(Some(version)) => version,
+        _ => return Err(ImportError::WrongFormat),
+    };
+    let supported = 1..=u64::from(SCHEMA_VERSION);
+    if !supported.contains(&version) {
+        return Err(ImportError::UnsupportedVersion(version));
+    }
+    for (from, migration) in (version..).zip(&MIGRATIONS[version as usize - 1..]) {
+        migration(document).map_err(|reason| ImportError::Migration { from, reason })?;
+        document.insert("version".to_owned(), Value::from(from + 1));
+    }
+    Ok(())
+}
+
+fn array<'a>(
+    document: &'a mut Map<String, Value>,
+    key: &str,
+) -> Result<&'a mut Vec<Value>, String> {
+    document
+        .get_mut(key)
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| format!("`{key}` is not a list"))
+}
+
+/// Gives each of the alarms of a body or an instance the `uid` it would get
+/// if it was read from iCalendar without one, and returns them in order.
+fn alarm_uids(owner: &mut Map<String, Value>) -> Result<Vec<String>, String> {
+    let mut uids = Vec::new();
+    for alarm in array(owner, "alarms")? {
+        let alarm = object(alarm, "alarm")?;
+        let trigger: AlarmTrigger = alarm
+            .get("trigger")
+            .cloned()
+            .and_then(|trigger| serde_json::from_value(trigger).ok())
+            .ok_or("an alarm has no trigger")?;
+        let description = alarm
+            .get("description")
+            .and_then(Value::as_str)
+            .ok_or("an alarm has no description")?;
+        let uid = ical::content_uid(&trigger, description);
+        alarm.insert("uid".to_owned(), Value::String(uid.clone()));
+        uids.push(uid);
+    }
+    Ok(uids)
+}
+
+fn object<'a>(value: &'a mut Value, what: &str) -> Result<&'a mut Map<String, Value>, String> {
+    value
+        .as_object_mut()
+        .ok_or_else(|| format!("a {what} is not an object"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{export_repo, import_repo, MemoryRepo, Repository};
+
+    /// A document of every version, oldest first, all holding the same data.
+    const FIXTURES: &[&str] = &[
+        include_str!("fixtures/v1.json"),
+        include_str!("fixtures/v2.json"),
+        include_str!("fixtures/v3.json"),
+        include_str!("fixtures/v4.json"),
+        include_str!("fixtures/v5.json"),
+    ];
+
+    #[test]
+    fn every_version_has_a_fixture() {
+        assert_eq!(FIXTURES.len(), SCHEMA_VERSION as usize);
+        for (version, fixture) in (1..).zip(FIXTURES) {
+            let document: Value = serde_json::from_str(fixture).unwrap();
+            assert_eq!(document["version"], version);
+        }
+    }
+
+    #[test]
+    fn fixtures_of_every_version_load() {
+        let latest = FIXTURES.last().unwrap();
+        for fixture in FIXTURES {
+            let repo = MemoryRepo::new();
+            import_repo(&repo, fixture.as_bytes()).unwrap();
+            assert_eq!(repo.get_task_list().unwrap().tasks.len(), 1);
+
+            // once loaded, old data is written out in the latest format
+            let mut json = Vec::new();
+            export_repo(&repo, &mut json).unwrap();
+            let exported: Value = serde_json::from_slice(&json).unwrap();
+            let expected: Value = serde_json::from_str(latest).unwrap();
+            assert_eq!(exported, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_broken_old_documents() {
+        let mut document = serde_json::json!({"format": "metime", "version": 1, "bodies": 3});
+        assert!(matches!(
+            migrate(&mut document),
+            Err(ImportError::Migration { from: 1, .. })
+        ));
+        let mut document = serde_json::json!({"format": "metime", "version": 0});
+        assert!(matches!(
+            migrate(&mut document),
+            Err(ImportError::UnsupportedVersion(0))
+        ));
+    }
+}
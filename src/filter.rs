@@ -0,0 +1,461 @@
+use std::{
+    ops::{Bound, RangeBounds},
+    str::FromStr,
+};
+
+use chrono::{prelude::*, Days, TimeDelta};
+use derive_more::derive::{Display, Error, From};
+
+use crate::{
+    domain::{start_of_day, Disambiguation, EventBody, EventInstance, EventStatus, LocalTimeError},
+    repository::Repository,
+    search::fold,
+};
+
+/// A parsed filter expression, such as
+/// `tag:work after:2024-01-01 before:+7d "standup" -status:cancelled`.
+///
+/// A filter is a whitespace-separated list of terms, all of which must hold
+/// for an event to match. Each term may be negated with a leading `-`. The
+/// terms are:
+///
+/// - `tag:TAG` — the event has the tag (case-insensitive).
+/// - `status:STATUS` — the event has the status (`tentative`, `confirmed`,
+///   `cancelled`).
+/// - `after:TIME` — the event starts at or after the time.
+/// - `before:TIME` — the event starts before the time.
+/// - `WORD` or `"SOME TEXT"` — the title or description contains the text,
+///   ignoring case and diacritics.
+///
+/// A `TIME` is a date (`2024-01-01`), a date and time (`2024-01-01T09:00`),
+/// `now`, `today`, `tomorrow`, `yesterday`, or an offset from now such as
+/// `+7d` or `-2w` (units are `m`, `h`, `d`, `w`). Dates refer to the first
+/// moment of the day, which is midnight unless the clocks skip it. Times are
+/// resolved against a reference time when the filter is compiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub terms: Vec<FilterTerm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterTerm {
+    pub negated: bool,
+    pub predicate: Predicate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    Tag(String),
+    Status(EventStatus),
+    After(TimeRef),
+    Before(TimeRef),
+    Text(String),
+}
+
+/// A point in time as written in a filter, before being resolved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeRef {
+    Now,
+    /// The start of the given number of days after today.
+    DaysFromToday(i64),
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    /// An offset from the reference time.
+    Offset(TimeDelta),
+}
+
+impl TimeRef {
+    /// Resolves this time relative to `now`. Dates and times without an
+    /// offset are interpreted in the time zone of `now`, times that the
+    /// clocks skip or repeat there as `disambiguation` says.
+    fn resolve<Tz: TimeZone>(
+        &self,
+        now: &DateTime<Tz>,
+        disambiguation: Disambiguation,
+    ) -> Result<DateTime<Utc>, FilterCompileError> {
+        let zone = now.timezone();
+        let nonexistent = || FilterCompileError::NonexistentTime(*self);
+        let start_of_day = |date: NaiveDate| {
+            start_of_day(&zone, date)
+                .map(|dt| dt.to_utc())
+                .ok_or_else(nonexistent)
+        };
+        match *self {
+            TimeRef::Now => Ok(now.to_utc()),
+            TimeRef::DaysFromToday(days) => {
+                let today = now.date_naive();
+                let date = if days >= 0 {
+                    today.checked_add_days(Days::new(days as u64))
+                } else {
+                    today.checked_sub_days(Days::new(days.unsigned_abs()))
+                };
+                start_of_day(date.ok_or_else(nonexistent)?)
+            }
+            TimeRef::Date(date) => start_of_day(date),
+            TimeRef::DateTime(naive) => disambiguation.resolve(&zone, naive).map_err(|e| match e {
+                LocalTimeError::Ambiguous { .. } => FilterCompileError::AmbiguousTime(*self),
+                _ => nonexistent(),
+            }),
+            TimeRef::Offset(delta) => now
+                .to_utc()
+                .checked_add_signed(delta)
+                .ok_or_else(nonexistent),
+        }
+    }
+}
+
+#[derive(Debug, Display, From, Error)]
+#[display("invalid filter: {_0}")]
+pub struct FilterParseError(peg::error::ParseError<peg::str::LineCol>);
+
+#[derive(Debug, Display, Error)]
+pub enum FilterCompileError {
+    #[display("the time {_0:?} does not exist")]
+    NonexistentTime(#[error(not(source))] TimeRef),
+    #[display("the time {_0:?} happens twice")]
+    AmbiguousTime(#[error(not(source))] TimeRef),
+}
+
+peg::parser! {
+    grammar filter_parser() for str {
+        rule _ = [' ' | '\t' | '\n' | '\r']*
+        rule __ = [' ' | '\t' | '\n' | '\r']+
+
+        rule digits() -> &'input str = $(['0'..='9']+)
+
+        rule quoted() -> String = "\"" s:$([^ '"']*) "\"" { s.to_owned() }
+
+        rule bare() -> String = s:$([^ ' ' | '\t' | '\n' | '\r' | '"' | ':' | '-'] [^ ' ' | '\t' | '\n' | '\r' | '"' | ':']*) {
+            s.to_owned()
+        }
+
+        rule value() -> String = quoted() / bare()
+
+        rule date() -> NaiveDate = d:$(digits() "-" digits() "-" digits()) {?
+            NaiveDate::parse_from_str(d, "%Y-%m-%d").or(Err("valid date"))
+        }
+
+        rule time() -> NaiveTime = t:$(digits() ":" digits() (":" digits())?) {?
+            NaiveTime::parse_from_str(t, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(t, "%H:%M"))
+                .or(Err("valid time"))
+        }
+
+        rule unit() -> TimeDelta =
+            "m" { TimeDelta::minutes(1) } / "h" { TimeDelta::hours(1) }
+            / "d" { TimeDelta::days(1) } / "w" { TimeDelta::weeks(1) }
+
+        rule offset() -> TimeDelta = sign:$("+" / "-") n:digits() u:unit() {?
+            let n: i32 = n.parse().or(Err("smaller offset"))?;
+            let n = if sign == "-" { -n } else { n };
+            u.checked_mul(n).ok_or("smaller offset")
+        }
+
+        rule time_ref() -> TimeRef =
+            d:date() "T" t:time() { TimeRef::DateTime(d.and_time(t)) }
+            / d:date() { TimeRef::Date(d) }
+            / o:offset() { TimeRef::Offset(o) }
+            / "now" { TimeRef::Now }
+            / "today" { TimeRef::DaysFromToday(0) }
+            / "tomorrow" { TimeRef::DaysFromToday(1) }
+            / "yesterday" { TimeRef::DaysFromToday(-1) }
+
+        rule status() -> EventStatus = s:$(['a'..='z' | 'A'..='Z']+) {?
+            s.parse().or(Err("tentative, confirmed or cancelled"))
+        }
+
+        rule predicate() -> Predicate =
+            "tag:" v:value() { Predicate::Tag(v) }
+            / "status:" s:status() { Predicate::Status(s) }
+            / "after:" t:time_ref() { Predicate::After(t) }
+            / "before:" t:time_ref() { Predicate::Before(t) }
+            / v:value() { Predicate::Text(v) }
+
+        rule term() -> FilterTerm = negated:"-"? predicate:predicate() {
+            FilterTerm { negated: negated.is_some(), predicate }
+        }
+
+        pub rule filter() -> Filter = _ terms:(term() ** __) _ { Filter { terms } }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(filter_parser::filter(s)?)
+    }
+}
+
+impl Filter {
+    /// Resolves the times in the filter relative to `now`, producing a plan
+    /// that can be run against a repository. Times without an offset that
+    /// the clocks skip or repeat are read as `disambiguation` says.
+    pub fn compile<Tz: TimeZone>(
+        &self,
+        now: &DateTime<Tz>,
+        disambiguation: Disambiguation,
+    ) -> Result<CompiledFilter, FilterCompileError> {
+        let mut start = Bound::Unbounded;
+        let mut end = Bound::Unbounded;
+        let mut residual = Vec::new();
+        for term in &self.terms {
+            let resolve = |time: &TimeRef| time.resolve(now, disambiguation);
+            let check = match (&term.predicate, term.negated) {
+                // non-negated time bounds narrow the range of the timeline
+                // that needs to be scanned at all
+                (Predicate::After(time), false) => {
+                    start = tighter_start(start, Bound::Included(resolve(time)?));
+                    continue;
+                }
+                (Predicate::Before(time), false) => {
+                    end = tighter_end(end, Bound::Excluded(resolve(time)?));
+                    continue;
+                }
+                (Predicate::After(time), true) => Check::StartsBefore(resolve(time)?),
+                (Predicate::Before(time), true) => Check::StartsAtOrAfter(resolve(time)?),
+                (Predicate::Tag(tag), negated) => Check::Tag(tag.to_lowercase(), negated),
+                (Predicate::Status(status), negated) => Check::Status(*status, negated),
+                (Predicate::Text(text), negated) => Check::Text(fold(text), negated),
+            };
+            residual.push(check);
+        }
+        Ok(CompiledFilter {
+            start,
+            end,
+            residual,
+        })
+    }
+}
+
+fn tighter_start(a: Bound<DateTime<Utc>>, b: Bound<DateTime<Utc>>) -> Bound<DateTime<Utc>> {
+    match (a, b) {
+        (Bound::Included(a), Bound::Included(b)) => Bound::Included(a.max(b)),
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        _ => unreachable!("start bounds are always inclusive"),
+    }
+}
+
+fn tighter_end(a: Bound<DateTime<Utc>>, b: Bound<DateTime<Utc>>) -> Bound<DateTime<Utc>> {
+    match (a, b) {
+        (Bound::Excluded(a), Bound::Excluded(b)) => Bound::Excluded(a.min(b)),
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        _ => unreachable!("end bounds are always exclusive"),
+    }
+}
+
+/// A filter whose times have been resolved, split into the range of start
+/// times to scan on the timeline and the checks to run on each event in it.
+#[derive(Debug, Clone)]
+pub struct CompiledFilter {
+    start: Bound<DateTime<Utc>>,
+    end: Bound<DateTime<Utc>>,
+    residual: Vec<Check>,
+}
+
+#[derive(Debug, Clone)]
+enum Check {
+    StartsBefore(DateTime<Utc>),
+    StartsAtOrAfter(DateTime<Utc>),
+    /// The tag, lowercased, and whether the check is negated.
+    Tag(String, bool),
+    Status(EventStatus, bool),
+    /// The folded text, and whether the check is negated.
+    Text(String, bool),
+}
+
+impl CompiledFilter {
+    /// Returns whether the event instance with the given body matches.
+    pub fn matches<EventBodyId>(
+        &self,
+        instance: &EventInstance<EventBodyId>,
+        body: &EventBody,
+    ) -> bool {
+        let start = instance.time_span.earliest();
+        (self.start, self.end).contains(&start)
+            && self.residual.iter().all(|check| match check {
+                Check::StartsBefore(time) => start < *time,
+                Check::StartsAtOrAfter(time) => start >= *time,
+                Check::Tag(tag, negated) => {
+                    body.categories.iter().any(|c| c.to_lowercase() == *tag) != *negated
+                }
+                Check::Status(status, negated) => (body.status == *status) != *negated,
+                Check::Text(text, negated) => {
+                    (fold(&body.summary).contains(text.as_str())
+                        || fold(&body.description).contains(text.as_str()))
+                        != *negated
+                }
+            })
+    }
+}
+
+/// Finds the event instances that match the filter, in order of time.
+pub fn filter_events<R: Repository>(repo: &R, filter: &CompiledFilter) -> Vec<R::EventInstanceId> {
+    let Some(timeline) = repo.get_timeline() else {
+        return Vec::new();
+    };
+    if let (Bound::Included(start), Bound::Excluded(end)) = (filter.start, filter.end) {
+        if start >= end {
+            return Vec::new();
+        }
+    }
+    timeline
+        .events
+        .range((filter.start, filter.end))
+        .map(|(_, &id)| id)
+        .filter(|&id| {
+            let Ok(instance) = repo.get_event_instance(id) else {
+                return false;
+            };
+            repo.get_event_body(instance.body)
+                .is_ok_and(|body| filter.matches(&instance, &body))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(negated: bool, predicate: Predicate) -> FilterTerm {
+        FilterTerm { negated, predicate }
+    }
+
+    #[test]
+    fn parse_example_filter() {
+        let filter: Filter = r#"tag:work after:2024-01-01 before:+7d "standup" -status:cancelled"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            filter.terms,
+            vec![
+                term(false, Predicate::Tag("work".to_owned())),
+                term(
+                    false,
+                    Predicate::After(TimeRef::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()))
+                ),
+                term(
+                    false,
+                    Predicate::Before(TimeRef::Offset(TimeDelta::days(7)))
+                ),
+                term(false, Predicate::Text("standup".to_owned())),
+                term(true, Predicate::Status(EventStatus::Cancelled)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_times_and_words() {
+        let filter: Filter = "  after:2024-03-05T09:30 before:tomorrow -dentist -2w  "
+            .parse()
+            .unwrap();
+        assert_eq!(
+            filter.terms,
+            vec![
+                term(
+                    false,
+                    Predicate::After(TimeRef::DateTime(
+                        NaiveDate::from_ymd_opt(2024, 3, 5)
+                            .unwrap()
+                            .and_hms_opt(9, 30, 0)
+                            .unwrap()
+                    ))
+                ),
+                term(false, Predicate::Before(TimeRef::DaysFromToday(1))),
+                term(true, Predicate::Text("dentist".to_owned())),
+                term(true, Predicate::Text("2w".to_owned())),
+            ]
+        );
+        assert_eq!("".parse::<Filter>().unwrap().terms, vec![]);
+    }
+
+    #[test]
+    fn reject_invalid_filters() {
+        assert!("after:2024-13-01".parse::<Filter>().is_err());
+        assert!("status:maybe".parse::<Filter>().is_err());
+        assert!("colour:red".parse::<Filter>().is_err());
+        assert!("\"unterminated".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn compiled_filter_matches_events() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 10, 12, 0, 0).unwrap();
+        let filter: Filter = "tag:WORK after:today before:+2d stand -status:cancelled"
+            .parse()
+            .unwrap();
+        let filter = filter.compile(&now, Disambiguation::Reject).unwrap();
+
+        let instance = |start| EventInstance {
+            time_span: crate::TimeSpan::Instant(start),
+            body: (),
+            actual: None,
+            outcome: None,
+            alarms: Vec::new(),
+            alarm_states: Default::default(),
+            uid: None,
+        };
+        let body = |summary: &str, status| EventBody {
+            summary: summary.to_owned(),
+            description: String::new(),
+            location: String::new(),
+            categories: vec!["work".to_owned()],
+            status,
+            alarms: Vec::new(),
+        };
+        let at = |d, h| Utc.with_ymd_and_hms(2024, 6, d, h, 0, 0).unwrap();
+
+        let standup = body("Daily Standup", EventStatus::Confirmed);
+        assert!(filter.matches(&instance(at(10, 0)), &standup));
+        assert!(filter.matches(&instance(at(12, 11)), &standup));
+        assert!(!filter.matches(&instance(at(9, 23)), &standup));
+        assert!(!filter.matches(&instance(at(12, 12)), &standup));
+        assert!(!filter.matches(
+            &instance(at(10, 9)),
+            &body("Daily Standup", EventStatus::Cancelled)
+        ));
+        assert!(!filter.matches(&instance(at(10, 9)), &body("Retro", EventStatus::Confirmed)));
+    }
+
+    #[test]
+    fn times_are_resolved_like_time_spans() {
+        let instance = |start| EventInstance {
+            time_span: crate::TimeSpan::Instant(start),
+            body: (),
+            actual: None,
+            outcome: None,
+            alarms: Vec::new(),
+            alarm_states: Default::default(),
+            uid: None,
+        };
+        let body = EventBody {
+            summary: String::new(),
+            description: String::new(),
+            location: String::new(),
+            categories: Vec::new(),
+            status: EventStatus::Confirmed,
+            alarms: Vec::new(),
+        };
+
+        // the clocks in São Paulo skipped from midnight to 1:00 that day
+        let zone = chrono_tz::America::Sao_Paulo;
+        let now = zone.with_ymd_and_hms(2018, 11, 5, 12, 0, 0).unwrap();
+        let filter: Filter = "after:2018-11-04".parse().unwrap();
+        let filter = filter.compile(&now, Disambiguation::Reject).unwrap();
+        let at = |h, m| Utc.with_ymd_and_hms(2018, 11, 4, h, m, 0).unwrap();
+        assert!(!filter.matches(&instance(at(2, 59)), &body));
+        assert!(filter.matches(&instance(at(3, 0)), &body));
+
+        // and in Berlin 2:30 happened twice
+        let zone = chrono_tz::Europe::Berlin;
+        let now = zone.with_ymd_and_hms(2024, 10, 28, 12, 0, 0).unwrap();
+        let filter: Filter = "before:2024-10-27T02:30".parse().unwrap();
+        assert!(matches!(
+            filter.compile(&now, Disambiguation::Reject),
+            Err(FilterCompileError::AmbiguousTime(_))
+        ));
+        let latest = filter.compile(&now, Disambiguation::Latest).unwrap();
+        let at = |h, m| Utc.with_ymd_and_hms(2024, 10, 27, h, m, 0).unwrap();
+        assert!(latest.matches(&instance(at(1, 0)), &body));
+        assert!(!latest.matches(&instance(at(1, 30)), &body));
+    }
+}
@@ -0,0 +1,854 @@
+//! Conversion of domain types to and from iCalendar (RFC 5545) text.
+
+use std::collections::BTreeMap;
+
+use chrono::{prelude::*, TimeDelta};
+use derive_more::derive::{Display, Error, From};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        Alarm, AlarmState, AlarmTrigger, Disambiguation, EventBody, EventInstance, EventStatus,
+        TimeSpan,
+    },
+    repository::{RepoRetrievalError, Repository},
+};
+
+#[derive(Debug, Display, Error, PartialEq, Eq)]
+pub enum IcalError {
+    #[display("malformed content line: {_0}")]
+    MalformedLine(#[error(not(source))] String),
+    #[display("expected {_0}")]
+    MissingProperty(#[error(not(source))] &'static str),
+    #[display("invalid value for {property}: {value}")]
+    InvalidValue { property: String, value: String },
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum StoreEventError {
+    /// The timeline holds one event per start time, and another instance
+    /// already starts when the event does.
+    #[display("another event already starts at {_0}")]
+    #[from(ignore)]
+    TimelineCollision(#[error(not(source))] DateTime<Utc>),
+    #[display("could not read the repository: {_0}")]
+    Retrieval(RepoRetrievalError),
+}
+
+/// A single `NAME;PARAM=VALUE:value` line, after unfolding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ContentLine {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl ContentLine {
+    pub fn new(name: &str, value: impl Into<String>) -> Self {
+        Self {
+            name: name.to_owned(),
+            params: Vec::new(),
+            value: value.into(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        self.params.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Returns the value of a parameter, compared case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn invalid(&self) -> IcalError {
+        IcalError::InvalidValue {
+            property: self.name.clone(),
+            value: self.value.clone(),
+        }
+    }
+
+    /// Parses an unfolded line.
+    pub fn parse(line: &str) -> Result<Self, IcalError> {
+        let malformed = || IcalError::MalformedLine(line.to_owned());
+
+        // the value starts at the first colon that is not in a quoted
+        // parameter value
+        let mut in_quotes = false;
+        let colon = line
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quotes = !in_quotes;
+                }
+                c == ':' && !in_quotes
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(malformed)?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+        let mut parts = head.split(';');
+        let name = parts
+            .next()
+            .filter(|name| !name.is_empty())
+            .ok_or_else(malformed)?;
+        let params = parts
+            .map(|param| {
+                let (name, value) = param.split_once('=').ok_or_else(malformed)?;
+                Ok((name.to_owned(), value.trim_matches('"').to_owned()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ContentLine {
+            name: name.to_ascii_uppercase(),
+            params,
+            value: value.to_owned(),
+        })
+    }
+
+    /// Writes the line folded to at most 75 octets per line, terminated by
+    /// CRLF.
+    pub fn write(&self, out: &mut String) {
+        let mut line = self.name.clone();
+        for (name, value) in &self.params {
+            line.push(';');
+            line.push_str(name);
+            line.push('=');
+            if value.contains([':', ';', ',']) {
+                line.push('"');
+                line.push_str(value);
+                line.push('"');
+            } else {
+                line.push_str(value);
+            }
+        }
+        line.push(':');
+        line.push_str(&self.value);
+
+        let mut width = 0;
+        for c in line.chars() {
+            if width + c.len_utf8() > 75 {
+                out.push_str("\r\n ");
+                width = 1;
+            }
+            out.push(c);
+            width += c.len_utf8();
+        }
+        out.push_str("\r\n");
+    }
+}
+
+/// Splits iCalendar text into unfolded content lines.
+pub(crate) fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in text.split('\n') {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        match (raw.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if raw.is_empty() => {}
+            _ => lines.push(raw.to_owned()),
+        }
+    }
+    lines
+}
+
+pub(crate) fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n' | 'N')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some(escaped)) => {
+                unescaped.push(escaped);
+                chars.next();
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Formats a UTC time as a `DATE-TIME` value, e.g. `20240610T120000Z`.
+pub(crate) fn format_date_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Parses a `DATE-TIME` value in UTC form.
+pub(crate) fn parse_date_time(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+/// Formats a signed duration as a `DURATION` value, e.g. `-PT15M`.
+pub(crate) fn format_duration(duration: TimeDelta) -> String {
+    let sign = if duration < TimeDelta::zero() {
+        "-"
+    } else {
+        ""
+    };
+    let total = duration.num_seconds().unsigned_abs();
+    if total == 0 {
+        return "PT0S".to_owned();
+    }
+    const WEEK: u64 = 7 * 24 * 3600;
+    if total.is_multiple_of(WEEK) {
+        return format!("{sign}P{}W", total / WEEK);
+    }
+
+    let (days, rest) = (total / 86400, total % 86400);
+    let mut out = format!("{sign}P");
+    if days > 0 {
+        out += &format!("{days}D");
+    }
+    if rest > 0 {
+        // hours, minutes and seconds must be contiguous, so only leading and
+        // trailing zero fields can be left out
+        let fields = [(rest / 3600, 'H'), (rest / 60 % 60, 'M'), (rest % 60, 'S')];
+        let first = fields.iter().position(|&(n, _)| n > 0).unwrap();
+        let last = fields.iter().rposition(|&(n, _)| n > 0).unwrap();
+        out.push('T');
+        for (n, unit) in &fields[first..=last] {
+            out += &format!("{n}{unit}");
+        }
+    }
+    out
+}
+
+/// Parses a `DURATION` value.
+pub(crate) fn parse_duration(value: &str) -> Option<TimeDelta> {
+    let (negative, rest) = match value.as_bytes().first()? {
+        b'-' => (true, &value[1..]),
+        b'+' => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let mut rest = rest.strip_prefix('P')?;
+    let mut seconds: i64 = 0;
+    let mut in_time = false;
+    let mut any = false;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('T') {
+            in_time = true;
+            rest = after;
+            continue;
+        }
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let n: i64 = rest[..digits].parse().ok()?;
+        let unit = rest[digits..].chars().next()?;
+        let unit_seconds = match (in_time, unit) {
+            (false, 'W') => 7 * 86400,
+            (false, 'D') => 86400,
+            (true, 'H') => 3600,
+            (true, 'M') => 60,
+            (true, 'S') => 1,
+            _ => return None,
+        };
+        seconds = seconds.checked_add(n.checked_mul(unit_seconds)?)?;
+        any = true;
+        rest = &rest[digits + unit.len_utf8()..];
+    }
+    if !any {
+        return None;
+    }
+    let duration = TimeDelta::try_seconds(seconds)?;
+    Some(if negative { -duration } else { duration })
+}
+
+/// Writes an alarm as a `VALARM` component. If the alarm has been
+/// acknowledged, the time of acknowledgement is written as the
+/// `ACKNOWLEDGED` property of RFC 9074.
+pub fn write_valarm(alarm: &Alarm, state: Option<&AlarmState>) -> String {
+    let mut out = String::new();
+    for line in valarm_lines(alarm, state) {
+        line.write(&mut out);
+    }
+    out
+}
+
+pub(crate) fn valarm_lines(alarm: &Alarm, state: Option<&AlarmState>) -> Vec<ContentLine> {
+    let mut lines = vec![
+        ContentLine::new("BEGIN", "VALARM"),
+        ContentLine::new("UID", escape_text(&alarm.uid)),
+        ContentLine::new("ACTION", "DISPLAY"),
+        trigger_line(&alarm.trigger),
+        ContentLine::new("DESCRIPTION", escape_text(&alarm.description)),
+    ];
+    if let Some(AlarmState::Acknowledged(at)) = state {
+        lines.push(ContentLine::new("ACKNOWLEDGED", format_date_time(*at)));
+    }
+    lines.push(ContentLine::new("END", "VALARM"));
+    lines
+}
+
+fn trigger_line(trigger: &AlarmTrigger) -> ContentLine {
+    match *trigger {
+        AlarmTrigger::Start(offset) => ContentLine::new("TRIGGER", format_duration(offset)),
+        AlarmTrigger::End(offset) => {
+            ContentLine::new("TRIGGER", format_duration(offset)).with_param("RELATED", "END")
+        }
+        AlarmTrigger::Absolute(time) => {
+            ContentLine::new("TRIGGER", format_date_time(time)).with_param("VALUE", "DATE-TIME")
+        }
+    }
+}
+
+/// The `uid` of an alarm that came without one, made from what the alarm
+/// does, so that reading the same alarm again gives the same `uid`.
+pub(crate) fn content_uid(trigger: &AlarmTrigger, description: &str) -> String {
+    let mut content = String::new();
+    trigger_line(trigger).write(&mut content);
+    content.push_str(description);
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, content.as_bytes()).to_string()
+}
+
+/// Reads a `VALARM` component, along with its acknowledgement state. An
+/// alarm without a `UID` is given one made from its trigger and description.
+pub fn parse_valarm(text: &str) -> Result<(Alarm, Option<AlarmState>), IcalError> {
+    let lines = unfold(text)
+        .iter()
+        .map(|line| ContentLine::parse(line))
+        .collect::<Result<Vec<_>, _>>()?;
+    parse_valarm_lines(&lines)
+}
+
+/// Reads the properties of a `VALARM` component, given the lines from its
+/// `BEGIN` to its `END`.
+pub(crate) fn parse_valarm_lines(
+    lines: &[ContentLine],
+) -> Result<(Alarm, Option<AlarmState>), IcalError> {
+    match (lines.first(), lines.last()) {
+        (Some(begin), Some(end))
+            if begin.name == "BEGIN"
+                && begin.value.eq_ignore_ascii_case("VALARM")
+                && end.name == "END"
+                && end.value.eq_ignore_ascii_case("VALARM") => {}
+        _ => return Err(IcalError::MissingProperty("BEGIN:VALARM ... END:VALARM")),
+    }
+
+    let mut uid = None;
+    let mut trigger = None;
+    let mut description = String::new();
+    let mut state = None;
+    for line in &lines[1..lines.len() - 1] {
+        match line.name.as_str() {
+            "UID" => uid = Some(unescape_text(&line.value)),
+            "TRIGGER" => {
+                let is_absolute = line
+                    .param("VALUE")
+                    .is_some_and(|value| value.eq_ignore_ascii_case("DATE-TIME"));
+                trigger = Some(if is_absolute {
+                    AlarmTrigger::Absolute(
+                        parse_date_time(&line.value).ok_or_else(|| line.invalid())?,
+                    )
+                } else {
+                    let offset = parse_duration(&line.value).ok_or_else(|| line.invalid())?;
+                    match line.param("RELATED") {
+                        Some(related) if related.eq_ignore_ascii_case("END") => {
+                            AlarmTrigger::End(offset)
+                        }
+                        _ => AlarmTrigger::Start(offset),
+                    }
+                });
+            }
+            "DESCRIPTION" => description = unescape_text(&line.value),
+            "ACKNOWLEDGED" => {
+                let at = parse_date_time(&line.value).ok_or_else(|| line.invalid())?;
+                state = Some(AlarmState::Acknowledged(at));
+            }
+            _ => {}
+        }
+    }
+    let trigger = trigger.ok_or(IcalError::MissingProperty("TRIGGER"))?;
+    let uid = uid.unwrap_or_else(|| content_uid(&trigger, &description));
+    Ok((
+        Alarm {
+            uid,
+            trigger,
+            description,
+        },
+        state,
+    ))
+}
+
+/// An event as it is exchanged with other calendar software: one `VEVENT`,
+/// holding the body of the event and the time of one instance of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IcalEvent {
+    /// The `UID` of the event, which identifies it across calendars.
+    pub uid: String,
+    pub body: EventBody,
+    pub time_span: TimeSpan,
+    /// The state of each of the body's alarms that has been acknowledged, by
+    /// the `uid` of the alarm.
+    pub alarm_states: BTreeMap<String, AlarmState>,
+}
+
+impl IcalEvent {
+    /// Gathers an event instance and its body into a single event. The
+    /// alarms of the instance are added to those of the body, since
+    /// iCalendar makes no such distinction.
+    pub fn from_instance<B>(uid: String, instance: &EventInstance<B>, body: &EventBody) -> Self {
+        let mut merged = body.clone();
+        merged.alarms = instance.all_alarms(body).cloned().collect();
+        let alarm_states = instance
+            .alarm_states
+            .iter()
+            .filter(|(uid, _)| merged.alarms.iter().any(|alarm| alarm.uid == **uid))
+            .map(|(uid, state)| (uid.clone(), *state))
+            .collect();
+        Self {
+            uid,
+            body: merged,
+            time_span: instance.time_span,
+            alarm_states,
+        }
+    }
+}
+
+/// Makes an instance, new or existing, match an event from another calendar,
+/// and puts it on the timeline. Returns the ID of the instance.
+///
+/// The event stands for one instance, so an existing instance whose body is
+/// shared with other instances on the timeline gets a body of its own
+/// instead of changing theirs. Its own alarms that the event still has stay
+/// its own rather than becoming alarms of the body.
+pub fn store_event<R: Repository>(
+    repo: &R,
+    id: Option<R::EventInstanceId>,
+    event: &IcalEvent,
+) -> Result<R::EventInstanceId, StoreEventError>
+where
+    R::EventInstanceId: PartialEq,
+    R::EventBodyId: PartialEq,
+{
+    let start = event.time_span.earliest();
+    let mut timeline = repo
+        .get_timeline()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+    if timeline
+        .events
+        .get(&start)
+        .is_some_and(|&other| Some(other) != id)
+    {
+        return Err(StoreEventError::TimelineCollision(start));
+    }
+    let Some(id) = id else {
+        drop(timeline);
+        let (body_id, _) = repo.add_event_body(event.body.clone());
+        let (id, mut instance) = crate::add_event_instance(repo, body_id, event.time_span);
+        instance.alarm_states = event.alarm_states.clone();
+        instance.uid = Some(event.uid.clone());
+        return Ok(id);
+    };
+    let mut instance = repo.get_event_instance(id)?;
+    let mut shared = false;
+    for &other in timeline.events.values() {
+        if other == id {
+            continue;
+        }
+        match repo.get_event_instance(other) {
+            Ok(other) => shared |= other.body == instance.body,
+            Err(RepoRetrievalError::IdNotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let (body, alarms) = split_alarms(&instance.alarms, event);
+    if shared {
+        instance.body = repo.add_event_body(body).0;
+    } else {
+        *repo.get_event_body(instance.body)? = body;
+    }
+    timeline.events.retain(|_, &mut other| other != id);
+    timeline.events.insert(start, id);
+    instance.time_span = event.time_span;
+    instance.alarms = alarms;
+    instance.alarm_states = event.alarm_states.clone();
+    instance.uid = Some(event.uid.clone());
+    Ok(id)
+}
+
+/// Splits the alarms of an event, which all come as alarms of the body, back
+/// into those of the body and those of an instance that had `own` as its
+/// alarms: any that are still there stay with the instance.
+fn split_alarms(own: &[Alarm], event: &IcalEvent) -> (EventBody, Vec<Alarm>) {
+    let mut body = event.body.clone();
+    let (alarms, shared) = std::mem::take(&mut body.alarms)
+        .into_iter()
+        .partition(|alarm| own.iter().any(|own| own.uid == alarm.uid));
+    body.alarms = shared;
+    (body, alarms)
+}
+
+/// Writes an event as a `VCALENDAR` object holding a single `VEVENT`, with
+/// times in UTC. `stamp` is the `DTSTAMP` of the event, the time it was last
+/// changed.
+pub fn write_vcalendar(event: &IcalEvent, stamp: DateTime<Utc>) -> String {
+    let body = &event.body;
+    let mut lines = vec![
+        ContentLine::new("BEGIN", "VCALENDAR"),
+        ContentLine::new("VERSION", "2.0"),
+        ContentLine::new("PRODID", "-//metime//metime_core//EN"),
+        ContentLine::new("BEGIN", "VEVENT"),
+        ContentLine::new("UID", escape_text(&event.uid)),
+        ContentLine::new("DTSTAMP", format_date_time(stamp)),
+        ContentLine::new("DTSTART", format_date_time(event.time_span.earliest())),
+    ];
+    if let TimeSpan::Interval { .. } = event.time_span {
+        lines.push(ContentLine::new(
+            "DTEND",
+            format_date_time(event.time_span.latest()),
+        ));
+    }
+    lines.push(ContentLine::new("SUMMARY", escape_text(&body.summary)));
+    if !body.description.is_empty() {
+        lines.push(ContentLine::new(
+            "DESCRIPTION",
+            escape_text(&body.description),
+        ));
+    }
+    if !body.location.is_empty() {
+        lines.push(ContentLine::new("LOCATION", escape_text(&body.location)));
+    }
+    if !body.categories.is_empty() {
+        let categories: Vec<_> = body.categories.iter().map(|c| escape_text(c)).collect();
+        lines.push(ContentLine::new("CATEGORIES", categories.join(",")));
+    }
+    lines.push(ContentLine::new(
+        "STATUS",
+        body.status.to_string().to_ascii_uppercase(),
+    ));
+    for alarm in &body.alarms {
+        let state = event.alarm_states.get(&alarm.uid);
+        lines.extend(valarm_lines(alarm, state));
+    }
+    lines.push(ContentLine::new("END", "VEVENT"));
+    lines.push(ContentLine::new("END", "VCALENDAR"));
+
+    let mut out = String::new();
+    for line in lines {
+        line.write(&mut out);
+    }
+    out
+}
+
+/// Reads the first `VEVENT` of a `VCALENDAR` object.
+///
+/// Times with a `TZID` are resolved in that zone, and floating times are
+/// taken to be in UTC. Since time spans cannot yet be whole days, an all-day
+/// event becomes an interval from midnight to midnight UTC.
+pub fn parse_vcalendar(text: &str) -> Result<IcalEvent, IcalError> {
+    let lines = unfold(text)
+        .iter()
+        .map(|line| ContentLine::parse(line))
+        .collect::<Result<Vec<_>, _>>()?;
+    let is = |line: &ContentLine, name: &str, value: &str| {
+        line.name == name && line.value.eq_ignore_ascii_case(value)
+    };
+    let begin = lines
+        .iter()
+        .position(|line| is(line, "BEGIN", "VEVENT"))
+        .ok_or(IcalError::MissingProperty("BEGIN:VEVENT"))?;
+    let end = lines[begin..]
+        .iter()
+        .position(|line| is(line, "END", "VEVENT"))
+        .ok_or(IcalError::MissingProperty("END:VEVENT"))?
+        + begin;
+
+    let mut uid = None;
+    let mut start = None;
+    let mut end_time = None;
+    let mut duration = None;
+    let mut all_day = false;
+    let mut body = EventBody {
+        summary: String::new(),
+        description: String::new(),
+        location: String::new(),
+        categories: Vec::new(),
+        status: EventStatus::default(),
+        alarms: Vec::new(),
+    };
+    let mut alarm_states = BTreeMap::new();
+    let mut i = begin + 1;
+    while i < end {
+        let line = &lines[i];
+        match line.name.as_str() {
+            "BEGIN" => {
+                // skip nested components, reading the alarms
+                let component = line.value.to_ascii_uppercase();
+                let close = lines[i..end]
+                    .iter()
+                    .position(|line| is(line, "END", &component))
+                    .ok_or(IcalError::MissingProperty("END of a component"))?
+                    + i;
+                if component == "VALARM" {
+                    let (alarm, state) = parse_valarm_lines(&lines[i..=close])?;
+                    if let Some(state) = state {
+                        alarm_states.insert(alarm.uid.clone(), state);
+                    }
+                    body.alarms.push(alarm);
+                }
+                i = close;
+            }
+            "UID" => uid = Some(unescape_text(&line.value)),
+            "DTSTART" => {
+                all_day = is_date(line);
+                start = Some(parse_event_time(line)?);
+            }
+            "DTEND" => end_time = Some(parse_event_time(line)?),
+            "DURATION" => {
+                duration = Some(parse_duration(&line.value).ok_or_else(|| line.invalid())?)
+            }
+            "SUMMARY" => body.summary = unescape_text(&line.value),
+            "DESCRIPTION" => body.description = unescape_text(&line.value),
+            "LOCATION" => body.location = unescape_text(&line.value),
+            "CATEGORIES" => body.categories.extend(
+                split_list(&line.value)
+                    .iter()
+                    .map(|category| unescape_text(category)),
+            ),
+            "STATUS" => body.status = line.value.parse().map_err(|_| line.invalid())?,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let uid = uid.ok_or(IcalError::MissingProperty("UID"))?;
+    let start = start.ok_or(IcalError::MissingProperty("DTSTART"))?;
+    let duration = match (end_time, duration) {
+        (Some(end), _) => Some(end - start),
+        (None, Some(duration)) => Some(duration),
+        (None, None) if all_day => Some(TimeDelta::days(1)),
+        (None, None) => None,
+    };
+    let time_span = match duration {
+        Some(duration) if duration < TimeDelta::zero() => {
+            return Err(IcalError::InvalidValue {
+                property: "DTEND".to_owned(),
+                value: "before DTSTART".to_owned(),
+            });
+        }
+        Some(duration) => TimeSpan::Interval { start, duration },
+        None => TimeSpan::Instant(start),
+    };
+    Ok(IcalEvent {
+        uid,
+        body,
+        time_span,
+        alarm_states,
+    })
+}
+
+fn is_date(line: &ContentLine) -> bool {
+    line.param("VALUE")
+        .is_some_and(|value| value.eq_ignore_ascii_case("DATE"))
+}
+
+/// Reads a `DTSTART` or `DTEND`, which may be a UTC time, a local time in a
+/// named zone, a floating time or a date.
+fn parse_event_time(line: &ContentLine) -> Result<DateTime<Utc>, IcalError> {
+    if is_date(line) {
+        let date = NaiveDate::parse_from_str(&line.value, "%Y%m%d").map_err(|_| line.invalid())?;
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    if let Some(time) = parse_date_time(&line.value) {
+        return Ok(time);
+    }
+    let local =
+        NaiveDateTime::parse_from_str(&line.value, "%Y%m%dT%H%M%S").map_err(|_| line.invalid())?;
+    match line.param("TZID") {
+        Some(tzid) => {
+            let zone: chrono_tz::Tz =
+                tzid.trim_start_matches('/')
+                    .parse()
+                    .map_err(|_| IcalError::InvalidValue {
+                        property: "TZID".to_owned(),
+                        value: tzid.to_owned(),
+                    })?;
+            // times skipped by a change of offset are moved past the change
+            Disambiguation::ShiftForward
+                .resolve(&zone, local)
+                .map_err(|_| line.invalid())
+        }
+        None => Ok(local.and_utc()),
+    }
+}
+
+/// Splits a list value at the commas that are not escaped.
+fn split_list(value: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                items.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_round_trip() {
+        let cases = [
+            (TimeDelta::minutes(-15), "-PT15M"),
+            (TimeDelta::zero(), "PT0S"),
+            (TimeDelta::weeks(2), "P2W"),
+            (TimeDelta::days(1) + TimeDelta::seconds(5), "P1DT5S"),
+            (TimeDelta::hours(1) + TimeDelta::minutes(30), "PT1H30M"),
+            (TimeDelta::hours(1) + TimeDelta::seconds(1), "PT1H0M1S"),
+        ];
+        for (duration, text) in cases {
+            assert_eq!(format_duration(duration), text);
+            assert_eq!(parse_duration(text), Some(duration));
+        }
+        assert_eq!(parse_duration("+P1D"), Some(TimeDelta::days(1)));
+        assert_eq!(parse_duration("P"), None);
+        assert_eq!(parse_duration("PT5D"), None);
+        assert_eq!(parse_duration("15M"), None);
+        assert_eq!(parse_duration("PT1é"), None);
+    }
+
+    #[test]
+    fn valarm_round_trip() {
+        let at = Utc.with_ymd_and_hms(2024, 6, 10, 11, 50, 0).unwrap();
+        let alarms = [
+            (
+                Alarm {
+                    uid: "leave".to_owned(),
+                    trigger: AlarmTrigger::Start(TimeDelta::minutes(-15)),
+                    description: "Leave now; it's far, really".to_owned(),
+                },
+                None,
+            ),
+            (
+                Alarm {
+                    uid: "end".to_owned(),
+                    trigger: AlarmTrigger::End(TimeDelta::minutes(5)),
+                    description: String::new(),
+                },
+                Some(AlarmState::Acknowledged(at)),
+            ),
+            (
+                Alarm::new(AlarmTrigger::Absolute(at), "x".repeat(100)),
+                None,
+            ),
+        ];
+        for (alarm, state) in alarms {
+            let text = write_valarm(&alarm, state.as_ref());
+            assert!(text.lines().all(|line| line.len() <= 76));
+            assert_eq!(parse_valarm(&text), Ok((alarm, state)));
+        }
+    }
+
+    #[test]
+    fn parse_foreign_valarm() {
+        let text = "BEGIN:VALARM\r\nTRIGGER;RELATED=END:-P1D\r\nACTION:AUDIO\r\n\
+                    ATTACH;FMTTYPE=audio/basic:ftp://example.com/pub/\r\n sounds/bell-01.aud\r\n\
+                    END:VALARM\r\n";
+        let (alarm, state) = parse_valarm(text).unwrap();
+        assert_eq!(alarm.trigger, AlarmTrigger::End(TimeDelta::days(-1)));
+        assert_eq!(alarm.description, "");
+        assert_eq!(state, None);
+        // without a UID, reading the alarm again gives it the same one
+        assert_eq!(parse_valarm(text).unwrap().0.uid, alarm.uid);
+
+        assert_eq!(
+            parse_valarm("BEGIN:VALARM\r\nACTION:DISPLAY\r\nEND:VALARM\r\n"),
+            Err(IcalError::MissingProperty("TRIGGER"))
+        );
+    }
+
+    #[test]
+    fn vcalendar_round_trip() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let event = IcalEvent {
+            uid: "standup@example.com".to_owned(),
+            body: EventBody {
+                summary: "Standup, daily".to_owned(),
+                description: "Bring notes;\nbe brief".to_owned(),
+                location: "Room 4".to_owned(),
+                categories: vec!["work".to_owned(), "a,b".to_owned()],
+                status: EventStatus::Tentative,
+                alarms: vec![Alarm {
+                    uid: "reminder".to_owned(),
+                    trigger: AlarmTrigger::Start(TimeDelta::minutes(-10)),
+                    description: String::new(),
+                }],
+            },
+            time_span: TimeSpan::Interval {
+                start,
+                duration: TimeDelta::minutes(15),
+            },
+            alarm_states: BTreeMap::from([(
+                "reminder".to_owned(),
+                AlarmState::Acknowledged(start),
+            )]),
+        };
+        let text = write_vcalendar(&event, start);
+        assert!(text.contains("DTEND:20240610T091500Z\r\n"));
+        assert!(text.contains("CATEGORIES:work,a\\,b\r\n"));
+        assert_eq!(parse_vcalendar(&text), Ok(event));
+    }
+
+    #[test]
+    fn parse_foreign_vevent() {
+        let text = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTIMEZONE\r\nTZID:Europe/Paris\r\n\
+                    END:VTIMEZONE\r\nBEGIN:VEVENT\r\nUID:1\r\n\
+                    DTSTART;TZID=Europe/Paris:20240610T090000\r\nDURATION:PT1H\r\n\
+                    SUMMARY:Dentist\r\nCATEGORIES:health\r\nCATEGORIES:errands\r\n\
+                    END:VEVENT\r\nEND:VCALENDAR\r\n";
+        let event = parse_vcalendar(text).unwrap();
+        assert_eq!(
+            event.time_span,
+            TimeSpan::Interval {
+                start: Utc.with_ymd_and_hms(2024, 6, 10, 7, 0, 0).unwrap(),
+                duration: TimeDelta::hours(1),
+            }
+        );
+        assert_eq!(event.body.categories, ["health", "errands"]);
+        assert_eq!(event.body.status, EventStatus::Confirmed);
+
+        let all_day = "BEGIN:VEVENT\r\nUID:2\r\nDTSTART;VALUE=DATE:20240610\r\nEND:VEVENT\r\n";
+        assert_eq!(
+            parse_vcalendar(all_day).unwrap().time_span.duration(),
+            TimeDelta::days(1)
+        );
+        assert_eq!(
+            parse_vcalendar("BEGIN:VEVENT\r\nDTSTART:20240610T090000Z\r\nEND:VEVENT\r\n"),
+            Err(IcalError::MissingProperty("UID"))
+        );
+    }
+}
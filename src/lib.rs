@@ -1,15 +1,59 @@
-#![feature(precise_capturing_in_traits)]
-
-use std::ops::DerefMut;
+use std::{collections::BTreeMap, ops::DerefMut};
 
+mod alarm;
+mod calendar;
 mod domain;
+#[cfg(feature = "serde")]
+mod export;
+mod filter;
+pub mod ical;
+mod oplog;
+mod replica;
+mod report;
 mod repository;
+mod search;
+#[cfg(feature = "caldav")]
+mod sync;
+mod tracker;
 
-pub use domain::{EventBody, EventInstance, TimeSpan};
-pub use repository::{memory_repo::MemoryRepo, Repository};
+pub use alarm::{alarms_due, next_alarm, DueAlarm};
+pub use calendar::{
+    add_calendar, calendar_of, events_in_calendars, move_to_calendar, CalendarError,
+};
+pub use domain::{
+    start_of_day, Alarm, AlarmState, AlarmTrigger, Calendar, CalendarList, Disambiguation,
+    EventBody, EventInstance, EventStatus, LocalTimeError, Outcome, ParseDisambiguationError, Task,
+    TimeSpan, TimeSpanParseError, TimeSpanParseErrorKind, TimeSpanParser, YearInference,
+};
+#[cfg(feature = "serde")]
+pub use export::{export_repo, import_repo, ExportError, ImportError, SCHEMA_VERSION};
+pub use filter::{
+    filter_events, CompiledFilter, Filter, FilterCompileError, FilterParseError, FilterTerm,
+    Predicate, TimeRef,
+};
+pub use oplog::{LogEntry, OpLog, OpLogError, Operation, Snapshot};
+pub use replica::{
+    BodyField, Change, InstanceField, ObjectId, Op, Replica, ReplicaError, ReplicaId, Stamp,
+};
+pub use report::{
+    compare_by_category, compare_by_day, usage_by_body, usage_by_category, usage_by_period, Period,
+    PlanComparison, UsageReport,
+};
+pub use repository::{
+    caching_repo::{CacheStats, CachingRepo},
+    conformance,
+    indexing_repo::IndexingRepo,
+    logging_repo::LoggingRepo,
+    memory_repo::{BodyId, InstanceId, MemoryRepo, ResolveIdError, TaskId},
+    RepoRetrievalError, Repository,
+};
+pub use search::{search_events, SearchIndex};
+#[cfg(feature = "caldav")]
+pub use sync::{sync_caldav, CalDavClient, ConflictPolicy, SyncError, SyncReport, SyncState};
+pub use tracker::{Overlap, RunningTimer, Tracker, TrackerError};
 
 pub fn add_event<R: Repository>(
-    repo: &mut R,
+    repo: &R,
     time_span: TimeSpan,
     title: String,
     desc: String,
@@ -22,19 +66,76 @@ pub fn add_event<R: Repository>(
     let event_body = EventBody {
         summary: title,
         description: desc,
+        location: String::new(),
+        categories: Vec::new(),
+        status: EventStatus::default(),
+        alarms: Vec::new(),
     };
     let (body_id, body) = repo.add_event_body(event_body);
+    let (instance_id, instance) = add_event_instance(repo, body_id, time_span);
 
+    (instance_id, body_id, instance, body)
+}
+
+/// Adds another occurrence of an existing event body to the timeline.
+pub fn add_event_instance<R: Repository>(
+    repo: &R,
+    body_id: R::EventBodyId,
+    time_span: TimeSpan,
+) -> (
+    R::EventInstanceId,
+    impl DerefMut<Target = EventInstance<R::EventBodyId>> + 'static,
+) {
     let time = time_span.earliest();
 
     let event_instance = EventInstance {
         time_span,
         body: body_id,
+        actual: None,
+        outcome: None,
+        alarms: Vec::new(),
+        alarm_states: BTreeMap::new(),
+        uid: None,
     };
     let (instance_id, instance) = repo.add_event_instance(event_instance);
 
     let mut timeline = repo.get_timeline().unwrap();
     timeline.events.insert(time, instance_id);
 
-    (instance_id, body_id, instance, body)
+    (instance_id, instance)
+}
+
+pub fn add_task<R: Repository>(
+    repo: &R,
+    task: Task<R::EventInstanceId>,
+) -> (
+    R::TaskId,
+    impl DerefMut<Target = Task<R::EventInstanceId>> + 'static,
+) {
+    let (task_id, task) = repo.add_task(task);
+
+    let mut task_list = repo.get_task_list().unwrap();
+    task_list.tasks.push(task_id);
+
+    (task_id, task)
+}
+
+/// Sets aside time for working on a task by adding an event for it to the
+/// timeline. The event takes its title, description and categories from the
+/// task.
+pub fn schedule_task<R: Repository>(
+    repo: &R,
+    task_id: R::TaskId,
+    time_span: TimeSpan,
+) -> Result<R::EventInstanceId, RepoRetrievalError> {
+    let mut task = repo.get_task(task_id)?;
+    let (instance_id, _, _, mut body) = add_event(
+        repo,
+        time_span,
+        task.summary.clone(),
+        task.description.clone(),
+    );
+    body.categories = task.categories.clone();
+    task.scheduled = Some(instance_id);
+    Ok(instance_id)
 }
@@ -0,0 +1,591 @@
+//! An append-only log of the operations made on events, from which the state
+//! at any point in time can be rebuilt.
+//!
+//! The log starts from a [`Snapshot`], which is empty until the log is
+//! compacted, and every [`Operation`] after it is kept along with the time it
+//! was made. Replaying the log up to a time gives the state as it was then,
+//! which can be loaded into any [`Repository`].
+
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use derive_more::derive::{Display, Error, From};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{EventBody, EventInstance, TimeSpan},
+    replica::{BodyField, InstanceField, ObjectId},
+    repository::{RepoRetrievalError, Repository},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Operation {
+    CreateBody {
+        id: ObjectId,
+        body: EventBody,
+    },
+    CreateInstance {
+        id: ObjectId,
+        body: ObjectId,
+        time_span: TimeSpan,
+    },
+    EditBody {
+        body: ObjectId,
+        field: BodyField,
+    },
+    /// Edits a field of an instance. Editing its time span, which is how an
+    /// instance is retimed, also moves it on the timeline if it is there.
+    EditInstance {
+        instance: ObjectId,
+        field: InstanceField,
+    },
+    /// Deletes an instance, or a body that no instance refers to.
+    Delete(ObjectId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LogEntry {
+    pub at: DateTime<Utc>,
+    pub operation: Operation,
+}
+
+/// The bodies and instances that exist at some point in the log.
+///
+/// Like in a repository, an instance can exist without being on the
+/// timeline: creating an instance, or moving one, at the start of another
+/// takes the other off the timeline but leaves it as it was otherwise.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "SnapshotRepr"))]
+pub struct Snapshot {
+    /// The time of the last operation that the snapshot includes.
+    pub at: Option<DateTime<Utc>>,
+    pub bodies: BTreeMap<ObjectId, EventBody>,
+    pub instances: BTreeMap<ObjectId, EventInstance<ObjectId>>,
+    /// The instances on the timeline, by the time they start.
+    pub timeline: BTreeMap<DateTime<Utc>, ObjectId>,
+}
+
+/// The IDs that the bodies and the instances of a snapshot were given in a
+/// repository.
+pub(crate) type LoadedIds<R> = (
+    BTreeMap<ObjectId, <R as Repository>::EventBodyId>,
+    BTreeMap<ObjectId, <R as Repository>::EventInstanceId>,
+);
+
+#[derive(Debug, Display, Error, From, PartialEq, Eq)]
+pub enum OpLogError {
+    Retrieval(RepoRetrievalError),
+    #[display("no body or instance has the ID {_0}")]
+    #[from(ignore)]
+    UnknownObject(#[error(not(source))] ObjectId),
+    #[display("something already has the ID {_0}")]
+    #[from(ignore)]
+    DuplicateObject(#[error(not(source))] ObjectId),
+    #[display("the body {_0} still has instances")]
+    #[from(ignore)]
+    InUse(#[error(not(source))] ObjectId),
+    #[display("an operation cannot be logged before the last one, at {_0}")]
+    #[from(ignore)]
+    OutOfOrder(#[error(not(source))] DateTime<Utc>),
+    #[display("the log has been compacted up to {_0}")]
+    #[from(ignore)]
+    Compacted(#[error(not(source))] DateTime<Utc>),
+}
+
+impl Snapshot {
+    /// Checks that every instance refers to a body of the snapshot, that no
+    /// body has the ID of an instance, and that the timeline holds instances
+    /// of the snapshot at the times they start.
+    fn validate(&self) -> Result<(), OpLogError> {
+        if let Some(&id) = self
+            .bodies
+            .keys()
+            .find(|id| self.instances.contains_key(id))
+        {
+            return Err(OpLogError::DuplicateObject(id));
+        }
+        if let Some(instance) = self
+            .instances
+            .values()
+            .find(|instance| !self.bodies.contains_key(&instance.body))
+        {
+            return Err(OpLogError::UnknownObject(instance.body));
+        }
+        match self.timeline.iter().find(|&(start, id)| {
+            self.instances
+                .get(id)
+                .is_none_or(|instance| instance.time_span.earliest() != *start)
+        }) {
+            Some((_, &id)) => Err(OpLogError::UnknownObject(id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies an operation, if it is valid for the current state. An
+    /// invalid operation leaves the state as it was.
+    pub fn apply(&mut self, at: DateTime<Utc>, operation: Operation) -> Result<(), OpLogError> {
+        match operation {
+            Operation::CreateBody { id, body } => {
+                if self.bodies.contains_key(&id) || self.instances.contains_key(&id) {
+                    return Err(OpLogError::DuplicateObject(id));
+                }
+                self.bodies.insert(id, body);
+            }
+            Operation::CreateInstance {
+                id,
+                body,
+                time_span,
+            } => {
+                if self.bodies.contains_key(&id) || self.instances.contains_key(&id) {
+                    return Err(OpLogError::DuplicateObject(id));
+                }
+                if !self.bodies.contains_key(&body) {
+                    return Err(OpLogError::UnknownObject(body));
+                }
+                let instance = EventInstance {
+                    time_span,
+                    body,
+                    actual: None,
+                    outcome: None,
+                    alarms: Vec::new(),
+                    alarm_states: BTreeMap::new(),
+                    uid: None,
+                };
+                self.instances.insert(id, instance);
+                self.timeline.insert(time_span.earliest(), id);
+            }
+            Operation::EditBody { body, field } => {
+                let body = self
+                    .bodies
+                    .get_mut(&body)
+                    .ok_or(OpLogError::UnknownObject(body))?;
+                field.apply(body);
+            }
+            Operation::EditInstance { instance, field } => {
+                if let InstanceField::Body(body) = field {
+                    if !self.bodies.contains_key(&body) {
+                        return Err(OpLogError::UnknownObject(body));
+                    }
+                }
+                let id = instance;
+                let instance = self.instance_mut(id)?;
+                let start = instance.time_span.earliest();
+                field.apply(instance);
+                let moved_to = instance.time_span.earliest();
+                if moved_to != start && self.timeline.get(&start) == Some(&id) {
+                    self.timeline.remove(&start);
+                    self.timeline.insert(moved_to, id);
+                }
+            }
+            Operation::Delete(id) => {
+                if let Some(instance) = self.instances.remove(&id) {
+                    let start = instance.time_span.earliest();
+                    if self.timeline.get(&start) == Some(&id) {
+                        self.timeline.remove(&start);
+                    }
+                } else {
+                    if self.instances.values().any(|instance| instance.body == id) {
+                        return Err(OpLogError::InUse(id));
+                    }
+                    self.bodies
+                        .remove(&id)
+                        .ok_or(OpLogError::UnknownObject(id))?;
+                }
+            }
+        }
+        self.at = Some(at);
+        Ok(())
+    }
+
+    fn instance_mut(&mut self, id: ObjectId) -> Result<&mut EventInstance<ObjectId>, OpLogError> {
+        self.instances
+            .get_mut(&id)
+            .ok_or(OpLogError::UnknownObject(id))
+    }
+
+    /// Adds the bodies and instances of the snapshot to a repository, putting
+    /// those on the timeline of the snapshot on its timeline. Returns the ID that each instance was given in the repository.
+    pub fn load_into<R: Repository>(
+        &self,
+        repo: &R,
+    ) -> Result<BTreeMap<ObjectId, R::EventInstanceId>, OpLogError> {
+        self.load(repo).map(|(_, instances)| instances)
+    }
+
+    /// Like [`load_into`](Self::load_into), but also returns the ID that each
+    /// body was given.
+    pub(crate) fn load<R: Repository>(&self, repo: &R) -> Result<LoadedIds<R>, OpLogError> {
+        // the fields are public, so the snapshot may not be one the log made,
+        // and it is checked before anything is added
+        self.validate()?;
+        let mut bodies = BTreeMap::new();
+        for (&id, body) in &self.bodies {
+            let (local, _) = repo.add_event_body(body.clone());
+            bodies.insert(id, local);
+        }
+        let mut instances = BTreeMap::new();
+        for (&id, instance) in &self.instances {
+            let (local, _) = repo.add_event_instance(EventInstance {
+                time_span: instance.time_span,
+                body: bodies[&instance.body],
+                actual: instance.actual,
+                outcome: instance.outcome,
+                alarms: instance.alarms.clone(),
+                alarm_states: instance.alarm_states.clone(),
+                uid: instance.uid.clone(),
+            });
+            instances.insert(id, local);
+        }
+        let mut timeline = repo
+            .get_timeline()
+            .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+        for (&start, id) in &self.timeline {
+            timeline.events.insert(start, instances[id]);
+        }
+        Ok((bodies, instances))
+    }
+}
+
+/// An append-only log of operations, in the order they were made.
+///
+/// A deserialized log is replayed to check it, so that only logs of valid
+/// operations in order are accepted.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "OpLogRepr"))]
+pub struct OpLog {
+    /// The state before the first entry.
+    base: Snapshot,
+    entries: Vec<LogEntry>,
+    /// The state after the last entry, kept to check new operations against.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    current: Snapshot,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SnapshotRepr {
+    at: Option<DateTime<Utc>>,
+    bodies: BTreeMap<ObjectId, EventBody>,
+    instances: BTreeMap<ObjectId, EventInstance<ObjectId>>,
+    timeline: BTreeMap<DateTime<Utc>, ObjectId>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SnapshotRepr> for Snapshot {
+    type Error = OpLogError;
+
+    fn try_from(repr: SnapshotRepr) -> Result<Self, Self::Error> {
+        let snapshot = Snapshot {
+            at: repr.at,
+            bodies: repr.bodies,
+            instances: repr.instances,
+            timeline: repr.timeline,
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct OpLogRepr {
+    base: Snapshot,
+    entries: Vec<LogEntry>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<OpLogRepr> for OpLog {
+    type Error = OpLogError;
+
+    fn try_from(repr: OpLogRepr) -> Result<Self, Self::Error> {
+        let mut log = OpLog {
+            current: repr.base.clone(),
+            base: repr.base,
+            entries: Vec::new(),
+        };
+        for entry in repr.entries {
+            log.record(entry.at, entry.operation)?;
+        }
+        Ok(log)
+    }
+}
+
+impl OpLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The operations logged since the last compaction.
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    /// Appends an operation made at the given time, which must not be
+    /// before the last one. Invalid operations are not logged.
+    pub fn record(&mut self, at: DateTime<Utc>, operation: Operation) -> Result<(), OpLogError> {
+        if let Some(last) = self.current.at.filter(|&last| at < last) {
+            return Err(OpLogError::OutOfOrder(last));
+        }
+        self.current.apply(at, operation.clone())?;
+        self.entries.push(LogEntry { at, operation });
+        Ok(())
+    }
+
+    /// The state after every logged operation.
+    pub fn current(&self) -> &Snapshot {
+        &self.current
+    }
+
+    /// The state as it was at a point in time, including the operations made
+    /// at exactly that time.
+    pub fn state_at(&self, at: DateTime<Utc>) -> Result<Snapshot, OpLogError> {
+        if let Some(base) = self.base.at.filter(|&base| at < base) {
+            return Err(OpLogError::Compacted(base));
+        }
+        let count = self.entries.partition_point(|entry| entry.at <= at);
+        Ok(self.replay(count))
+    }
+
+    /// Folds the operations made up to a point in time into the snapshot the
+    /// log starts from. The state before that time can no longer be rebuilt.
+    pub fn compact(&mut self, until: DateTime<Utc>) {
+        let count = self.entries.partition_point(|entry| entry.at <= until);
+        self.base = self.replay(count);
+        self.entries.drain(..count);
+    }
+
+    fn replay(&self, count: usize) -> Snapshot {
+        let mut state = self.base.clone();
+        for entry in &self.entries[..count] {
+            // only valid operations are logged, so replaying them cannot fail
+            state
+                .apply(entry.at, entry.operation.clone())
+                .expect("logged operations are valid");
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{domain::EventStatus, repository::memory_repo::MemoryRepo};
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, day, 9, 0, 0).unwrap()
+    }
+
+    fn hour_on(day_of_month: u32) -> TimeSpan {
+        TimeSpan::Interval {
+            start: day(day_of_month),
+            duration: TimeDelta::hours(1),
+        }
+    }
+
+    fn body(summary: &str) -> EventBody {
+        EventBody {
+            summary: summary.to_owned(),
+            description: String::new(),
+            location: String::new(),
+            categories: Vec::new(),
+            status: EventStatus::default(),
+            alarms: Vec::new(),
+        }
+    }
+
+    /// A log where a meeting is created on the 3rd, renamed on the 5th and
+    /// moved on the 7th, and lunch is created on the 4th and deleted on the
+    /// 6th.
+    fn history() -> (OpLog, ObjectId, ObjectId) {
+        let (body, meeting) = (ObjectId(Uuid::from_u128(1)), ObjectId(Uuid::from_u128(2)));
+        let (lunch_body, lunch) = (ObjectId(Uuid::from_u128(3)), ObjectId(Uuid::from_u128(4)));
+        let mut log = OpLog::new();
+        let operations = [
+            (
+                3,
+                Operation::CreateBody {
+                    id: body,
+                    body: self::body("Meeting"),
+                },
+            ),
+            (
+                3,
+                Operation::CreateInstance {
+                    id: meeting,
+                    body,
+                    time_span: hour_on(10),
+                },
+            ),
+            (
+                4,
+                Operation::CreateBody {
+                    id: lunch_body,
+                    body: self::body("Lunch"),
+                },
+            ),
+            (
+                4,
+                Operation::CreateInstance {
+                    id: lunch,
+                    body: lunch_body,
+                    time_span: hour_on(11),
+                },
+            ),
+            (
+                5,
+                Operation::EditBody {
+                    body,
+                    field: BodyField::Summary("Planning".to_owned()),
+                },
+            ),
+            (6, Operation::Delete(lunch)),
+            (
+                7,
+                Operation::EditInstance {
+                    instance: meeting,
+                    field: InstanceField::TimeSpan(hour_on(12)),
+                },
+            ),
+        ];
+        for (at, operation) in operations {
+            log.record(day(at), operation).unwrap();
+        }
+        (log, body, meeting)
+    }
+
+    #[test]
+    fn rebuilds_past_states() {
+        let (log, body, meeting) = history();
+        assert_eq!(log.state_at(day(2)).unwrap(), Snapshot::default());
+        let tuesday = log.state_at(day(4)).unwrap();
+        assert_eq!(tuesday.bodies[&body].summary, "Meeting");
+        assert_eq!(tuesday.instances.len(), 2);
+        let later = log.state_at(day(6)).unwrap();
+        assert_eq!(later.bodies[&body].summary, "Planning");
+        assert_eq!(later.instances.len(), 1);
+        assert_eq!(later.instances[&meeting].time_span, hour_on(10));
+        assert_eq!(log.current().instances[&meeting].time_span, hour_on(12));
+
+        let repo = MemoryRepo::new();
+        let ids = tuesday.load_into(&repo).unwrap();
+        let timeline = repo.get_timeline().unwrap();
+        assert_eq!(timeline.events.len(), 2);
+        assert_eq!(timeline.events[&day(10)], ids[&meeting]);
+    }
+
+    #[test]
+    fn compaction_keeps_the_current_state() {
+        let (mut log, _, _) = history();
+        let current = log.current().clone();
+        log.compact(day(5));
+        assert_eq!(log.entries().len(), 2);
+        assert_eq!(*log.current(), current);
+        assert_eq!(log.state_at(day(4)), Err(OpLogError::Compacted(day(5))));
+        assert!(log.state_at(day(6)).unwrap().instances.len() == 1);
+    }
+
+    #[test]
+    fn rejects_invalid_operations() {
+        let (mut log, body, _) = history();
+        let unknown = ObjectId(Uuid::from_u128(9));
+        assert_eq!(
+            log.record(day(8), Operation::Delete(body)),
+            Err(OpLogError::InUse(body))
+        );
+        assert_eq!(
+            log.record(
+                day(8),
+                Operation::EditInstance {
+                    instance: unknown,
+                    field: InstanceField::TimeSpan(hour_on(1)),
+                }
+            ),
+            Err(OpLogError::UnknownObject(unknown))
+        );
+        assert_eq!(
+            log.record(day(1), Operation::Delete(body)),
+            Err(OpLogError::OutOfOrder(day(7)))
+        );
+        assert_eq!(log.entries().len(), 7);
+    }
+
+    #[test]
+    fn refuses_to_load_dangling_snapshots() {
+        let (log, body, meeting) = history();
+        let mut snapshot = log.current().clone();
+        snapshot.bodies.remove(&body);
+        let repo = MemoryRepo::new();
+        assert_eq!(
+            snapshot.load_into(&repo),
+            Err(OpLogError::UnknownObject(body))
+        );
+        assert!(repo.get_timeline().unwrap().events.is_empty());
+
+        // the meeting does not start on the 1st
+        let mut snapshot = log.current().clone();
+        snapshot.timeline.insert(day(1), meeting);
+        assert_eq!(
+            snapshot.load_into(&repo),
+            Err(OpLogError::UnknownObject(meeting))
+        );
+    }
+
+    #[test]
+    fn displaced_instances_stay_off_the_timeline() {
+        let (mut log, body, meeting) = history();
+        let standup = ObjectId(Uuid::from_u128(5));
+        let operation = Operation::CreateInstance {
+            id: standup,
+            body,
+            time_span: hour_on(12),
+        };
+        log.record(day(8), operation).unwrap();
+        // moving the meeting away does not put it back
+        let operation = Operation::EditInstance {
+            instance: meeting,
+            field: InstanceField::TimeSpan(hour_on(13)),
+        };
+        log.record(day(9), operation).unwrap();
+        let current = log.current();
+        assert_eq!(current.instances.len(), 2);
+        assert_eq!(current.timeline, BTreeMap::from([(day(12), standup)]));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn checks_deserialized_logs() {
+        let (log, body, _) = history();
+        let json = serde_json::to_value(&log).unwrap();
+        let loaded: OpLog = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(loaded.current(), log.current());
+        assert_eq!(loaded.entries(), log.entries());
+
+        // the body of the meeting deleted before anything else happens
+        let mut corrupt = json.clone();
+        let delete = serde_json::to_value(LogEntry {
+            at: day(3),
+            operation: Operation::Delete(body),
+        })
+        .unwrap();
+        corrupt["entries"].as_array_mut().unwrap().insert(2, delete);
+        let error = serde_json::from_value::<OpLog>(corrupt).unwrap_err();
+        assert!(error.to_string().contains("still has instances"));
+
+        let mut reordered = json.clone();
+        reordered["entries"].as_array_mut().unwrap().swap(0, 6);
+        assert!(serde_json::from_value::<OpLog>(reordered).is_err());
+
+        let mut dangling = serde_json::to_value(log.current()).unwrap();
+        dangling["bodies"].as_object_mut().unwrap().clear();
+        assert!(serde_json::from_value::<Snapshot>(dangling).is_err());
+    }
+}
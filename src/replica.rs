@@ -0,0 +1,718 @@
+//! Replication of a repository between devices that are edited while
+//! offline.
+//!
+//! A [`Replica`] wraps a repository and records every change made through it
+//! as an [`Op`]. Replicas exchange their ops in any order, any number of
+//! times, and end up with the same bodies, instances and timeline: each field
+//! of a body or an instance takes the value that was written last, and a
+//! deleted instance stays deleted.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::prelude::*;
+use derive_more::derive::{Display, Error, From};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    domain::{Alarm, AlarmState, EventBody, EventInstance, EventStatus, Outcome, TimeSpan},
+    repository::{RepoRetrievalError, Repository},
+};
+
+/// Identifies a replica.
+#[derive(Debug, Display, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReplicaId(pub Uuid);
+
+/// Identifies a body or an instance across all replicas, unlike the IDs of
+/// the repository each replica wraps.
+#[derive(Debug, Display, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ObjectId(pub Uuid);
+
+/// When an op was made, as a Lamport clock. Stamps are totally ordered, with
+/// ties between replicas broken by their IDs; of two writes to the same
+/// field, the one with the greater stamp wins.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stamp {
+    pub counter: u64,
+    pub replica: ReplicaId,
+}
+
+/// A single change, as exchanged between replicas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Op {
+    pub stamp: Stamp,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Change {
+    /// Sets a field of a body, creating the body if it does not exist yet.
+    Body { id: ObjectId, field: BodyField },
+    /// Sets a field of an instance, creating the instance if it does not
+    /// exist yet.
+    Instance { id: ObjectId, field: InstanceField },
+    /// Removes an instance from the timeline for good.
+    DeleteInstance(ObjectId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BodyField {
+    Summary(String),
+    Description(String),
+    Location(String),
+    Categories(Vec<String>),
+    Status(EventStatus),
+    Alarms(Vec<Alarm>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum InstanceField {
+    Body(ObjectId),
+    TimeSpan(TimeSpan),
+    Actual(Option<TimeSpan>),
+    Outcome(Option<Outcome>),
+    Alarms(Vec<Alarm>),
+    AlarmStates(BTreeMap<String, AlarmState>),
+    Uid(Option<String>),
+}
+
+impl BodyField {
+    /// Every field of a body.
+    pub fn all(body: &EventBody) -> Vec<Self> {
+        vec![
+            BodyField::Summary(body.summary.clone()),
+            BodyField::Description(body.description.clone()),
+            BodyField::Location(body.location.clone()),
+            BodyField::Categories(body.categories.clone()),
+            BodyField::Status(body.status),
+            BodyField::Alarms(body.alarms.clone()),
+        ]
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            BodyField::Summary(_) => "summary",
+            BodyField::Description(_) => "description",
+            BodyField::Location(_) => "location",
+            BodyField::Categories(_) => "categories",
+            BodyField::Status(_) => "status",
+            BodyField::Alarms(_) => "alarms",
+        }
+    }
+
+    pub fn apply(self, body: &mut EventBody) {
+        match self {
+            BodyField::Summary(summary) => body.summary = summary,
+            BodyField::Description(description) => body.description = description,
+            BodyField::Location(location) => body.location = location,
+            BodyField::Categories(categories) => body.categories = categories,
+            BodyField::Status(status) => body.status = status,
+            BodyField::Alarms(alarms) => body.alarms = alarms,
+        }
+    }
+}
+
+impl InstanceField {
+    /// Every field of an instance.
+    pub fn all(instance: &EventInstance<ObjectId>) -> Vec<Self> {
+        vec![
+            InstanceField::Body(instance.body),
+            InstanceField::TimeSpan(instance.time_span),
+            InstanceField::Actual(instance.actual),
+            InstanceField::Outcome(instance.outcome),
+            InstanceField::Alarms(instance.alarms.clone()),
+            InstanceField::AlarmStates(instance.alarm_states.clone()),
+            InstanceField::Uid(instance.uid.clone()),
+        ]
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            InstanceField::Body(_) => "body",
+            InstanceField::TimeSpan(_) => "time_span",
+            InstanceField::Actual(_) => "actual",
+            InstanceField::Outcome(_) => "outcome",
+            InstanceField::Alarms(_) => "alarms",
+            InstanceField::AlarmStates(_) => "alarm_states",
+            InstanceField::Uid(_) => "uid",
+        }
+    }
+
+    pub fn apply(self, instance: &mut EventInstance<ObjectId>) {
+        match self {
+            InstanceField::Body(body) => instance.body = body,
+            InstanceField::TimeSpan(time_span) => instance.time_span = time_span,
+            InstanceField::Actual(actual) => instance.actual = actual,
+            InstanceField::Outcome(outcome) => instance.outcome = outcome,
+            InstanceField::Alarms(alarms) => instance.alarms = alarms,
+            InstanceField::AlarmStates(states) => instance.alarm_states = states,
+            InstanceField::Uid(uid) => instance.uid = uid,
+        }
+    }
+}
+
+#[derive(Debug, Display, Error, From, PartialEq, Eq)]
+pub enum ReplicaError {
+    Retrieval(RepoRetrievalError),
+    #[display("no body or instance has the ID {_0}")]
+    #[from(ignore)]
+    UnknownObject(#[error(not(source))] ObjectId),
+    #[display("the instance {_0} has been deleted")]
+    #[from(ignore)]
+    Deleted(#[error(not(source))] ObjectId),
+}
+
+#[derive(Debug)]
+struct ObjectState<Id> {
+    local: Id,
+    /// The stamp of the last write to each field.
+    stamps: BTreeMap<&'static str, Stamp>,
+}
+
+#[derive(Debug)]
+struct InstanceState<Id> {
+    object: ObjectState<Id>,
+    body: Option<ObjectId>,
+    /// Where the instance is placed on the timeline, once its time span is
+    /// known.
+    start: Option<DateTime<Utc>>,
+}
+
+/// A repository whose changes are recorded so that they can be replayed on
+/// other replicas of it.
+///
+/// A replica has to be the only thing changing its repository, and it only
+/// manages the bodies and instances that were created through it or through
+/// the replicas it merged with. Since the timeline holds one instance per
+/// start time, of several instances that start at the same time only the one
+/// with the greatest [`ObjectId`] is on the timeline.
+#[derive(Debug)]
+pub struct Replica<R: Repository> {
+    repo: R,
+    id: ReplicaId,
+    clock: u64,
+    log: Vec<Op>,
+    seen: BTreeSet<Stamp>,
+    /// The greatest counter seen from each replica.
+    version: BTreeMap<ReplicaId, u64>,
+    bodies: BTreeMap<ObjectId, ObjectState<R::EventBodyId>>,
+    body_objects: BTreeMap<R::EventBodyId, ObjectId>,
+    instances: BTreeMap<ObjectId, InstanceState<R::EventInstanceId>>,
+    deleted: BTreeSet<ObjectId>,
+}
+
+impl<R: Repository> Replica<R>
+where
+    R::EventBodyId: Ord,
+    R::EventInstanceId: Ord,
+{
+    /// Starts a new replica with a random ID.
+    pub fn new(repo: R) -> Self {
+        Self::with_id(repo, ReplicaId(Uuid::new_v4()))
+    }
+
+    /// Starts a new replica with the given ID, which must not be used by any
+    /// other replica.
+    pub fn with_id(repo: R, id: ReplicaId) -> Self {
+        Self {
+            repo,
+            id,
+            clock: 0,
+            log: Vec::new(),
+            seen: BTreeSet::new(),
+            version: BTreeMap::new(),
+            bodies: BTreeMap::new(),
+            body_objects: BTreeMap::new(),
+            instances: BTreeMap::new(),
+            deleted: BTreeSet::new(),
+        }
+    }
+
+    pub fn id(&self) -> ReplicaId {
+        self.id
+    }
+
+    /// The repository holding the current state. It should only be read;
+    /// changes made to it directly are not replicated.
+    pub fn repo(&self) -> &R {
+        &self.repo
+    }
+
+    /// The ID in the repository of a body.
+    pub fn body_id(&self, id: ObjectId) -> Option<R::EventBodyId> {
+        self.bodies.get(&id).map(|state| state.local)
+    }
+
+    /// The ID in the repository of an instance.
+    pub fn instance_id(&self, id: ObjectId) -> Option<R::EventInstanceId> {
+        self.instances.get(&id).map(|state| state.object.local)
+    }
+
+    /// Every op this replica has made or merged, in the order they were
+    /// applied.
+    pub fn log(&self) -> &[Op] {
+        &self.log
+    }
+
+    /// The greatest counter this replica has seen from each replica, which
+    /// tells another replica which ops it is missing.
+    pub fn version(&self) -> &BTreeMap<ReplicaId, u64> {
+        &self.version
+    }
+
+    /// The ops that a replica at the given version has not seen.
+    pub fn ops_since(&self, version: &BTreeMap<ReplicaId, u64>) -> Vec<Op> {
+        self.log
+            .iter()
+            .filter(|op| op.stamp.counter > version.get(&op.stamp.replica).copied().unwrap_or(0))
+            .cloned()
+            .collect()
+    }
+
+    /// Applies ops from another replica. Ops that were already applied are
+    /// ignored.
+    pub fn merge(&mut self, ops: impl IntoIterator<Item = Op>) -> Result<(), ReplicaError> {
+        let mut ops: Vec<_> = ops.into_iter().collect();
+        ops.sort_by_key(|op| op.stamp);
+        for op in ops {
+            self.apply(op)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_body(&mut self, body: EventBody) -> Result<ObjectId, ReplicaError> {
+        let id = ObjectId(Uuid::new_v4());
+        for field in BodyField::all(&body) {
+            self.record(Change::Body { id, field })?;
+        }
+        Ok(id)
+    }
+
+    /// Changes a body, recording the fields that the edit changed.
+    pub fn edit_body(
+        &mut self,
+        id: ObjectId,
+        edit: impl FnOnce(&mut EventBody),
+    ) -> Result<(), ReplicaError> {
+        let local = self.body_id(id).ok_or(ReplicaError::UnknownObject(id))?;
+        let before = self.repo.get_event_body(local)?.clone();
+        let mut after = before.clone();
+        edit(&mut after);
+        let changed = BodyField::all(&after)
+            .into_iter()
+            .zip(BodyField::all(&before))
+            .filter(|(after, before)| after != before);
+        for (field, _) in changed.collect::<Vec<_>>() {
+            self.record(Change::Body { id, field })?;
+        }
+        Ok(())
+    }
+
+    /// Adds an instance of a body to the timeline.
+    pub fn add_instance(
+        &mut self,
+        body: ObjectId,
+        time_span: TimeSpan,
+    ) -> Result<ObjectId, ReplicaError> {
+        if !self.bodies.contains_key(&body) {
+            return Err(ReplicaError::UnknownObject(body));
+        }
+        let id = ObjectId(Uuid::new_v4());
+        let instance = EventInstance {
+            time_span,
+            body,
+            actual: None,
+            outcome: None,
+            alarms: Vec::new(),
+            alarm_states: BTreeMap::new(),
+            uid: None,
+        };
+        for field in InstanceField::all(&instance) {
+            self.record(Change::Instance { id, field })?;
+        }
+        Ok(id)
+    }
+
+    /// Changes an instance, recording the fields that the edit changed. The
+    /// edit sees the body of the instance by its [`ObjectId`].
+    pub fn edit_instance(
+        &mut self,
+        id: ObjectId,
+        edit: impl FnOnce(&mut EventInstance<ObjectId>),
+    ) -> Result<(), ReplicaError> {
+        let before = self.instance(id)?;
+        let mut after = before.clone();
+        edit(&mut after);
+        if !self.bodies.contains_key(&after.body) {
+            return Err(ReplicaError::UnknownObject(after.body));
+        }
+        let changed = InstanceField::all(&after)
+            .into_iter()
+            .zip(InstanceField::all(&before))
+            .filter(|(after, before)| after != before);
+        for (field, _) in changed.collect::<Vec<_>>() {
+            self.record(Change::Instance { id, field })?;
+        }
+        Ok(())
+    }
+
+    /// Removes an instance from the timeline. Deleted instances cannot be
+    /// brought back, even by edits made elsewhere at the same time.
+    pub fn delete_instance(&mut self, id: ObjectId) -> Result<(), ReplicaError> {
+        self.instance(id)?;
+        self.record(Change::DeleteInstance(id))
+    }
+
+    fn instance(&self, id: ObjectId) -> Result<EventInstance<ObjectId>, ReplicaError> {
+        let state = self
+            .instances
+            .get(&id)
+            .ok_or(ReplicaError::UnknownObject(id))?;
+        if self.deleted.contains(&id) {
+            return Err(ReplicaError::Deleted(id));
+        }
+        let instance = self.repo.get_event_instance(state.object.local)?;
+        let body = state
+            .body
+            .or_else(|| self.body_objects.get(&instance.body).copied())
+            .ok_or(ReplicaError::UnknownObject(id))?;
+        Ok(EventInstance {
+            time_span: instance.time_span,
+            body,
+            actual: instance.actual,
+            outcome: instance.outcome,
+            alarms: instance.alarms.clone(),
+            alarm_states: instance.alarm_states.clone(),
+            uid: instance.uid.clone(),
+        })
+    }
+
+    fn record(&mut self, change: Change) -> Result<(), ReplicaError> {
+        let stamp = Stamp {
+            counter: self.clock + 1,
+            replica: self.id,
+        };
+        self.apply(Op { stamp, change })
+    }
+
+    fn apply(&mut self, op: Op) -> Result<(), ReplicaError> {
+        if self.seen.contains(&op.stamp) {
+            return Ok(());
+        }
+        let stamp = op.stamp;
+        match op.change.clone() {
+            Change::Body { id, field } => {
+                let local = self.ensure_body(id);
+                let state = self.bodies.get_mut(&id).unwrap();
+                if is_newer(&mut state.stamps, field.name(), stamp) {
+                    field.apply(&mut *self.repo.get_event_body(local)?);
+                }
+            }
+            Change::Instance { id, field } => {
+                let body = match &field {
+                    InstanceField::Body(body) => Some(*body),
+                    _ => None,
+                };
+                let local = self.ensure_instance(id, body);
+                let state = self.instances.get_mut(&id).unwrap();
+                if is_newer(&mut state.object.stamps, field.name(), stamp) {
+                    let mut moved = None;
+                    match &field {
+                        InstanceField::Body(body) => state.body = Some(*body),
+                        InstanceField::TimeSpan(time_span) => {
+                            moved = Some(state.start);
+                            state.start = Some(time_span.earliest());
+                        }
+                        _ => {}
+                    }
+                    let mut instance = self.repo.get_event_instance(local)?;
+                    match field {
+                        InstanceField::Body(body) => instance.body = self.ensure_body(body),
+                        InstanceField::TimeSpan(time_span) => instance.time_span = time_span,
+                        InstanceField::Actual(actual) => instance.actual = actual,
+                        InstanceField::Outcome(outcome) => instance.outcome = outcome,
+                        InstanceField::Alarms(alarms) => instance.alarms = alarms,
+                        InstanceField::AlarmStates(states) => instance.alarm_states = states,
+                        InstanceField::Uid(uid) => instance.uid = uid,
+                    }
+                    drop(instance);
+                    if let Some(old) = moved {
+                        self.reslot(old)?;
+                        self.reslot(self.instances[&id].start)?;
+                    }
+                }
+            }
+            Change::DeleteInstance(id) => {
+                self.ensure_instance(id, None);
+                if self.deleted.insert(id) {
+                    self.reslot(self.instances[&id].start)?;
+                }
+            }
+        }
+        self.seen.insert(stamp);
+        self.clock = self.clock.max(stamp.counter);
+        let seen = self.version.entry(stamp.replica).or_default();
+        *seen = (*seen).max(stamp.counter);
+        self.log.push(op);
+        Ok(())
+    }
+
+    /// Returns the local ID of a body, adding an empty body to the repository
+    /// if it is not there yet.
+    fn ensure_body(&mut self, id: ObjectId) -> R::EventBodyId {
+        if let Some(state) = self.bodies.get(&id) {
+            return state.local;
+        }
+        let (local, _) = self.repo.add_event_body(EventBody {
+            summary: String::new(),
+            description: String::new(),
+            location: String::new(),
+            categories: Vec::new(),
+            status: EventStatus::default(),
+            alarms: Vec::new(),
+        });
+        self.bodies.insert(
+            id,
+            ObjectState {
+                local,
+                stamps: BTreeMap::new(),
+            },
+        );
+        self.body_objects.insert(local, id);
+        local
+    }
+
+    /// Returns the local ID of an instance, adding an instance of `body` to
+    /// the repository if it is not there yet. The instance is not placed on
+    /// the timeline until its time span is set.
+    fn ensure_instance(&mut self, id: ObjectId, body: Option<ObjectId>) -> R::EventInstanceId {
+        if let Some(state) = self.instances.get(&id) {
+            return state.object.local;
+        }
+        // an instance's first op sets its body, unless that op was lost, in
+        // which case the instance gets a body of its own until it arrives
+        let body = match body {
+            Some(body) => self.ensure_body(body),
+            None => {
+                self.repo
+                    .add_event_body(EventBody {
+                        summary: String::new(),
+                        description: String::new(),
+                        location: String::new(),
+                        categories: Vec::new(),
+                        status: EventStatus::default(),
+                        alarms: Vec::new(),
+                    })
+                    .0
+            }
+        };
+        let (local, _) = self.repo.add_event_instance(EventInstance {
+            time_span: TimeSpan::Instant(DateTime::UNIX_EPOCH),
+            body,
+            actual: None,
+            outcome: None,
+            alarms: Vec::new(),
+            alarm_states: BTreeMap::new(),
+            uid: None,
+        });
+        self.instances.insert(
+            id,
+            InstanceState {
+                object: ObjectState {
+                    local,
+                    stamps: BTreeMap::new(),
+                },
+                body: None,
+                start: None,
+            },
+        );
+        local
+    }
+
+    /// Puts the right instance on the timeline at a start time, which is the
+    /// live instance starting then with the greatest ID, if there is one.
+    fn reslot(&mut self, start: Option<DateTime<Utc>>) -> Result<(), ReplicaError> {
+        let Some(start) = start else {
+            return Ok(());
+        };
+        let winner = self
+            .instances
+            .iter()
+            .filter(|(id, state)| state.start == Some(start) && !self.deleted.contains(id))
+            .map(|(_, state)| state.object.local)
+            .next_back();
+        let mut timeline = self
+            .repo
+            .get_timeline()
+            .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+        match winner {
+            Some(local) => timeline.events.insert(start, local),
+            None => timeline.events.remove(&start),
+        };
+        Ok(())
+    }
+}
+
+/// Records a write to a field if it is newer than the last one, returning
+/// whether it was.
+fn is_newer(stamps: &mut BTreeMap<&'static str, Stamp>, field: &'static str, stamp: Stamp) -> bool {
+    if stamps.get(field).is_some_and(|last| *last > stamp) {
+        return false;
+    }
+    stamps.insert(field, stamp);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::repository::memory_repo::MemoryRepo;
+
+    fn replica(n: u128) -> Replica<MemoryRepo> {
+        Replica::with_id(MemoryRepo::new(), ReplicaId(Uuid::from_u128(n)))
+    }
+
+    fn body(summary: &str) -> EventBody {
+        EventBody {
+            summary: summary.to_owned(),
+            description: String::new(),
+            location: String::new(),
+            categories: Vec::new(),
+            status: EventStatus::default(),
+            alarms: Vec::new(),
+        }
+    }
+
+    fn hour(hour: u32) -> TimeSpan {
+        TimeSpan::Interval {
+            start: Utc.with_ymd_and_hms(2024, 6, 10, hour, 0, 0).unwrap(),
+            duration: TimeDelta::hours(1),
+        }
+    }
+
+    /// What is on the timeline of a replica, in a form that can be compared
+    /// across replicas.
+    fn contents(replica: &Replica<MemoryRepo>) -> Vec<(DateTime<Utc>, String, String)> {
+        let repo = replica.repo();
+        let events: Vec<_> = repo
+            .get_timeline()
+            .unwrap()
+            .events
+            .iter()
+            .map(|(&time, &id)| (time, id))
+            .collect();
+        events
+            .into_iter()
+            .map(|(time, id)| {
+                let instance = repo.get_event_instance(id).unwrap();
+                let body = repo.get_event_body(instance.body).unwrap();
+                (time, body.summary.clone(), body.location.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_edits_converge_in_any_order() {
+        let mut laptop = replica(1);
+        let standup = laptop.add_body(body("Standup")).unwrap();
+        let instance = laptop.add_instance(standup, hour(9)).unwrap();
+        let mut desktop = replica(2);
+        desktop.merge(laptop.ops_since(desktop.version())).unwrap();
+        assert_eq!(contents(&desktop), contents(&laptop));
+
+        // edited on both while offline
+        let since_laptop = laptop.version().clone();
+        let since_desktop = desktop.version().clone();
+        laptop
+            .edit_body(standup, |body| body.summary = "Daily standup".to_owned())
+            .unwrap();
+        laptop
+            .edit_instance(instance, |instance| instance.time_span = hour(10))
+            .unwrap();
+        desktop
+            .edit_body(standup, |body| body.location = "Room 4".to_owned())
+            .unwrap();
+        desktop
+            .edit_body(standup, |body| body.summary = "Team standup".to_owned())
+            .unwrap();
+        let lunch = desktop.add_body(body("Lunch")).unwrap();
+        desktop.add_instance(lunch, hour(12)).unwrap();
+
+        let from_laptop = laptop.ops_since(&since_desktop);
+        let from_desktop = desktop.ops_since(&since_laptop);
+        laptop.merge(from_desktop.clone()).unwrap();
+        desktop.merge(from_laptop.clone()).unwrap();
+        assert_eq!(contents(&laptop), contents(&desktop));
+        assert_eq!(
+            contents(&laptop),
+            [
+                (
+                    hour(10).earliest(),
+                    "Team standup".to_owned(),
+                    "Room 4".to_owned()
+                ),
+                (hour(12).earliest(), "Lunch".to_owned(), String::new()),
+            ]
+        );
+
+        // a fresh replica gets there too, from ops in reverse and twice over
+        let mut phone = replica(3);
+        let mut ops = desktop.log().to_vec();
+        ops.reverse();
+        phone.merge(ops.clone()).unwrap();
+        phone.merge(ops).unwrap();
+        assert_eq!(contents(&phone), contents(&laptop));
+    }
+
+    #[test]
+    fn deletion_wins_over_concurrent_edits() {
+        let mut laptop = replica(1);
+        let standup = laptop.add_body(body("Standup")).unwrap();
+        let instance = laptop.add_instance(standup, hour(9)).unwrap();
+        let mut desktop = replica(2);
+        desktop.merge(laptop.log().to_vec()).unwrap();
+
+        laptop.delete_instance(instance).unwrap();
+        desktop
+            .edit_instance(instance, |instance| instance.time_span = hour(11))
+            .unwrap();
+        laptop.merge(desktop.log().to_vec()).unwrap();
+        desktop.merge(laptop.log().to_vec()).unwrap();
+        assert!(contents(&laptop).is_empty());
+        assert!(contents(&desktop).is_empty());
+        assert_eq!(
+            desktop.edit_instance(instance, |_| {}),
+            Err(ReplicaError::Deleted(instance))
+        );
+    }
+
+    #[test]
+    fn instances_share_their_bodies() {
+        let mut laptop = replica(1);
+        let standup = laptop.add_body(body("Standup")).unwrap();
+        laptop.add_instance(standup, hour(9)).unwrap();
+        laptop.add_instance(standup, hour(10)).unwrap();
+        let mut desktop = replica(2);
+        desktop.merge(laptop.log().to_vec()).unwrap();
+        // the repository holds the one body and nothing else
+        for replica in [&laptop, &desktop] {
+            assert_eq!(
+                replica.repo().resolve_body_id(""),
+                Ok(replica.body_id(standup).unwrap())
+            );
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use chrono::{Days, Months, TimeDelta};
+
+use crate::{
+    domain::{start_of_day, EventBody, EventInstance, Outcome},
+    repository::Repository,
+};
+
+/// How the time actually spent on a group of event instances compares to the
+/// time planned for them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PlanComparison {
+    pub instances: usize,
+    /// How many of the instances have no known actual duration. They are left
+    /// out of both `planned` and `actual`, so that the two stay comparable.
+    pub unrecorded: usize,
+    pub planned: TimeDelta,
+    pub actual: TimeDelta,
+    pub done: usize,
+    pub skipped: usize,
+    pub partial: usize,
+}
+
+impl PlanComparison {
+    fn add<B>(&mut self, instance: &EventInstance<B>) {
+        self.instances += 1;
+        match instance.actual_duration() {
+            Some(actual) => {
+                self.planned += instance.time_span.duration();
+                self.actual += actual;
+            }
+            None => self.unrecorded += 1,
+        }
+        match instance.outcome {
+            Some(Outcome::Done) => self.done += 1,
+            Some(Outcome::Skipped) => self.skipped += 1,
+            Some(Outcome::Partial) => self.partial += 1,
+            None => {}
+        }
+    }
+
+    /// How much longer than planned the events took, or a negative amount if
+    /// they took less time than planned.
+    pub fn overrun(&self) -> TimeDelta {
+        self.actual - self.planned
+    }
+}
+
+/// Compares planned and actual durations of the instances planned to start
+/// in `[from, to)`, grouped by the day they were planned for in the time zone
+/// of `from`.
+pub fn compare_by_day<R: Repository, Tz: TimeZone>(
+    repo: &R,
+    from: &DateTime<Tz>,
+    to: &DateTime<Tz>,
+) -> BTreeMap<NaiveDate, PlanComparison> {
+    let zone = from.timezone();
+    let mut days = BTreeMap::<_, PlanComparison>::new();
+    for_each_instance_between(repo, from.to_utc(), to.to_utc(), |instance, _| {
+        let day = instance
+            .time_span
+            .earliest()
+            .with_timezone(&zone)
+            .date_naive();
+        days.entry(day).or_default().add(instance);
+    });
+    days
+}
+
+/// Compares planned and actual durations of the instances planned to start
+/// in `[from, to)`, grouped by category. An instance counts towards each of
+/// its body's categories, or towards `None` if its body has none.
+pub fn compare_by_category<R: Repository>(
+    repo: &R,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> BTreeMap<Option<String>, PlanComparison> {
+    let mut categories = BTreeMap::<_, PlanComparison>::new();
+    for_each_instance_between(repo, from, to, |instance, body| {
+        if body.categories.is_empty() {
+            categories.entry(None).or_default().add(instance);
+        }
+        for category in &body.categories {
+            categories
+                .entry(Some(category.clone()))
+                .or_default()
+                .add(instance);
+        }
+    });
+    categories
+}
+
+/// How the time in a window was used: how long the events in it took, in
+/// total and per group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageReport<K> {
+    pub groups: BTreeMap<K, TimeDelta>,
+    /// The time taken by all events together, with each event counted once
+    /// no matter how many groups it is in.
+    pub total: TimeDelta,
+    /// How much of the window is taken by at least one event. This is less
+    /// than `total` if events overlap.
+    pub covered: TimeDelta,
+}
+
+impl<K> UsageReport<K> {
+    /// The share of the total time that `duration` makes up, in percent.
+    pub fn percentage(&self, duration: TimeDelta) -> f64 {
+        if self.total.is_zero() {
+            return 0.0;
+        }
+        100.0 * duration.num_milliseconds() as f64 / self.total.num_milliseconds() as f64
+    }
+}
+
+/// A length of calendar time to group usage by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    /// A week starting on the given day.
+    Week(Weekday),
+    Month,
+}
+
+impl Period {
+    /// Returns the first day of the period containing `date`.
+    fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Day => date,
+            Period::Week(week_start) => date.week(week_start).first_day(),
+            Period::Month => date.with_day(1).unwrap(),
+        }
+    }
+
+    /// Returns the first day of the period after the one starting on `start`.
+    fn next(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Day => start + Days::new(1),
+            Period::Week(_) => start + Days::new(7),
+            Period::Month => start + Months::new(1),
+        }
+    }
+}
+
+/// Adds up the time that events took in `[from, to)` by category. An event
+/// counts towards each of its body's categories, or towards `None` if its
+/// body has none.
+pub fn usage_by_category<R: Repository>(
+    repo: &R,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> UsageReport<Option<String>> {
+    usage(&uses(repo, from, to), |time_use| {
+        let duration = time_use.end - time_use.start;
+        if time_use.categories.is_empty() {
+            return vec![(None, duration)];
+        }
+        time_use
+            .categories
+            .iter()
+            .map(|category| (Some(category.clone()), duration))
+            .collect()
+    })
+}
+
+/// Adds up the time that events took in `[from, to)` by event body, so that
+/// all instances of a recurring event count together.
+pub fn usage_by_body<R: Repository>(
+    repo: &R,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> UsageReport<R::EventBodyId>
+where
+    R::EventBodyId: Ord,
+{
+    usage(&uses(repo, from, to), |time_use| {
+        vec![(time_use.body, time_use.end - time_use.start)]
+    })
+}
+
+/// Adds up the time that events took in `[from, to)` by the period it falls
+/// in, as calendar time in the time zone of `from`. Periods are identified by
+/// their first day, and events that cross from one period into the next count
+/// towards both.
+pub fn usage_by_period<R: Repository, Tz: TimeZone>(
+    repo: &R,
+    from: &DateTime<Tz>,
+    to: &DateTime<Tz>,
+    period: Period,
+) -> UsageReport<NaiveDate> {
+    let zone = from.timezone();
+    usage(&uses(repo, from.to_utc(), to.to_utc()), |time_use| {
+        let mut parts = Vec::new();
+        let mut period_start = period.start(time_use.start.with_timezone(&zone).date_naive());
+        let mut start = time_use.start;
+        while start < time_use.end {
+            let next = period.next(period_start);
+            // a day that cannot start in the zone ends the last part
+            let end = start_of_day(&zone, next)
+                .map_or(time_use.end, |next| next.to_utc().min(time_use.end));
+            parts.push((period_start, end - start));
+            period_start = next;
+            start = end;
+        }
+        parts
+    })
+}
+
+/// The time taken by one event instance, clipped to the window of a report.
+struct TimeUse<EventBodyId> {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    body: EventBodyId,
+    categories: Vec<String>,
+}
+
+/// Finds the time taken by every event in `[from, to)`. An event takes the
+/// time it actually happened if that was recorded, or else the time it was
+/// planned for; skipped events take no time at all.
+fn uses<R: Repository>(
+    repo: &R,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<TimeUse<R::EventBodyId>> {
+    let mut uses = Vec::new();
+    let Some(timeline) = repo.get_timeline() else {
+        return uses;
+    };
+    // the timeline is ordered by start, so events that started before the
+    // window but run into it must be looked for as well
+    for &id in timeline.events.range(..to).map(|(_, id)| id) {
+        let Ok(instance) = repo.get_event_instance(id) else {
+            continue;
+        };
+        if instance.outcome == Some(Outcome::Skipped) {
+            continue;
+        }
+        let time_span = instance.actual.unwrap_or(instance.time_span);
+        let start = time_span.earliest().max(from);
+        let end = time_span.latest().min(to);
+        if start >= end {
+            continue;
+        }
+        let Ok(body) = repo.get_event_body(instance.body) else {
+            continue;
+        };
+        uses.push(TimeUse {
+            start,
+            end,
+            body: instance.body,
+            categories: body.categories.clone(),
+        });
+    }
+    uses
+}
+
+/// Puts together a report from the time taken by each event, which `groups`
+/// splits into the groups it counts towards.
+fn usage<B, K: Ord>(
+    uses: &[TimeUse<B>],
+    mut groups: impl FnMut(&TimeUse<B>) -> Vec<(K, TimeDelta)>,
+) -> UsageReport<K> {
+    let mut report = UsageReport {
+        groups: BTreeMap::new(),
+        total: TimeDelta::zero(),
+        covered: TimeDelta::zero(),
+    };
+    for time_use in uses {
+        report.total += time_use.end - time_use.start;
+        for (key, duration) in groups(time_use) {
+            *report.groups.entry(key).or_default() += duration;
+        }
+    }
+
+    let mut spans: Vec<_> = uses
+        .iter()
+        .map(|time_use| (time_use.start, time_use.end))
+        .collect();
+    spans.sort();
+    let mut covered_until = DateTime::<Utc>::MIN_UTC;
+    for (start, end) in spans {
+        let start = start.max(covered_until);
+        if start < end {
+            report.covered += end - start;
+            covered_until = end;
+        }
+    }
+    report
+}
+
+/// Calls `f` with every instance that starts in `[from, to)`, in order of
+/// time, along with its body.
+fn for_each_instance_between<R: Repository>(
+    repo: &R,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    mut f: impl FnMut(&EventInstance<R::EventBodyId>, &EventBody),
+) {
+    let Some(timeline) = repo.get_timeline() else {
+        return;
+    };
+    if from >= to {
+        return;
+    }
+    for &id in timeline.events.range(from..to).map(|(_, id)| id) {
+        let Ok(instance) = repo.get_event_instance(id) else {
+            continue;
+        };
+        let Ok(body) = repo.get_event_body(instance.body) else {
+            continue;
+        };
+        f(&instance, &body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryRepo, TimeSpan};
+
+    #[test]
+    fn compares_planned_and_actual() {
+        let repo = MemoryRepo::new();
+        let at = |d, h| Utc.with_ymd_and_hms(2024, 6, d, h, 0, 0).unwrap();
+        let hour = TimeDelta::hours(1);
+        let add = |start, tag: Option<&str>, actual, outcome| {
+            let time_span = TimeSpan::Interval {
+                start,
+                duration: hour,
+            };
+            let (_, _, mut instance, mut body) =
+                crate::add_event(&repo, time_span, String::new(), String::new());
+            body.categories.extend(tag.map(str::to_owned));
+            instance.actual = actual;
+            instance.outcome = outcome;
+        };
+        // ran half an hour long
+        add(
+            at(10, 9),
+            Some("work"),
+            Some(TimeSpan::Interval {
+                start: at(10, 9),
+                duration: hour * 3 / 2,
+            }),
+            Some(Outcome::Done),
+        );
+        add(at(10, 13), Some("work"), None, Some(Outcome::Skipped));
+        add(at(11, 9), None, None, Some(Outcome::Done));
+        add(at(11, 13), Some("work"), None, None);
+        // outside the window
+        add(at(12, 9), None, None, Some(Outcome::Skipped));
+
+        let days = compare_by_day(&repo, &at(10, 0), &at(12, 0));
+        let monday = days[&NaiveDate::from_ymd_opt(2024, 6, 10).unwrap()];
+        assert_eq!(monday.instances, 2);
+        assert_eq!((monday.done, monday.skipped), (1, 1));
+        assert_eq!(monday.planned, hour * 2);
+        assert_eq!(monday.actual, hour * 3 / 2);
+        assert_eq!(monday.overrun(), -hour / 2);
+        let tuesday = days[&NaiveDate::from_ymd_opt(2024, 6, 11).unwrap()];
+        assert_eq!(tuesday.unrecorded, 1);
+        assert_eq!(tuesday.overrun(), TimeDelta::zero());
+        assert_eq!(days.len(), 2);
+
+        let categories = compare_by_category(&repo, at(10, 0), at(12, 0));
+        let work = categories[&Some("work".to_owned())];
+        assert_eq!((work.instances, work.unrecorded), (3, 1));
+        assert_eq!(categories[&None].instances, 1);
+    }
+
+    #[test]
+    fn usage_is_clipped_and_split() {
+        let repo = MemoryRepo::new();
+        let zone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let at = |m, d, h| zone.with_ymd_and_hms(2024, m, d, h, 0, 0).unwrap();
+        let hour = TimeDelta::hours(1);
+        let add = |start: DateTime<FixedOffset>, hours, tag: &str| {
+            let time_span = TimeSpan::Interval {
+                start: start.to_utc(),
+                duration: hour * hours,
+            };
+            let (_, body_id, _, mut body) =
+                crate::add_event(&repo, time_span, String::new(), String::new());
+            body.categories.push(tag.to_owned());
+            body_id
+        };
+        // starts before the window
+        add(at(5, 30, 22), 4, "sleep");
+        // crosses from May into June
+        let late = add(at(5, 31, 22), 4, "work");
+        // overlaps the previous one
+        add(at(5, 31, 23), 2, "sleep");
+        // starts when the window ends
+        add(at(6, 1, 12), 1, "work");
+
+        let from = at(5, 31, 0);
+        let to = at(6, 1, 12);
+        let categories = usage_by_category(&repo, from.to_utc(), to.to_utc());
+        assert_eq!(categories.groups[&Some("sleep".to_owned())], hour * 4);
+        assert_eq!(categories.groups[&Some("work".to_owned())], hour * 4);
+        assert_eq!(categories.total, hour * 8);
+        assert_eq!(categories.covered, hour * 6);
+        assert_eq!(categories.percentage(hour * 2), 25.0);
+
+        let months = usage_by_period(&repo, &from, &to, Period::Month);
+        let may = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let june = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        assert_eq!(months.groups[&may], hour * 5);
+        assert_eq!(months.groups[&june], hour * 3);
+        let weeks = usage_by_period(&repo, &from, &to, Period::Week(Weekday::Mon));
+        assert_eq!(weeks.groups.len(), 1);
+
+        let bodies = usage_by_body(&repo, from.to_utc(), to.to_utc());
+        assert_eq!(bodies.groups[&late], hour * 4);
+    }
+}
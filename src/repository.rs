@@ -1,13 +1,22 @@
 use std::ops::DerefMut;
 
-use crate::domain::{EventBody, EventInstance, Timeline};
+use derive_more::derive::{Display, Error};
 
+use crate::domain::{CalendarList, EventBody, EventInstance, Task, TaskList, Timeline};
+
+pub mod caching_repo;
+pub mod conformance;
+pub mod indexing_repo;
+pub mod logging_repo;
 pub mod memory_repo;
 
 // TODO explain the concept of "retrieval", which is like a borrow for repo
 // data
 /// Trait for interacting with some backing repository for retrieving, caching,
 /// and modifying application data in-memory.
+///
+/// The functions in [`conformance`] check that an implementation follows the
+/// rules for retrievals that the rest of the crate relies on.
 pub trait Repository {
     fn get_timeline(
         &self,
@@ -53,14 +62,46 @@ pub trait Repository {
         Self::EventBodyId,
         impl DerefMut<Target = EventBody> + 'static + use<Self>,
     );
+
+    fn get_task_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = TaskList<Self::TaskId>> + 'static + use<Self>>;
+
+    type TaskId: Copy;
+
+    /// Get the data of a task given its ID.
+    fn get_task(
+        &self,
+        id: Self::TaskId,
+    ) -> Result<
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<Self>,
+        RepoRetrievalError,
+    >;
+
+    /// Adds a new task to the repository. Returns the ID of the task and a
+    /// reference to the data.
+    #[must_use]
+    fn add_task(
+        &self,
+        task: Task<Self::EventInstanceId>,
+    ) -> (
+        Self::TaskId,
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<Self>,
+    );
+
+    fn get_calendar_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = CalendarList<Self::EventInstanceId>> + 'static + use<Self>>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Display, Error, PartialEq, Eq)]
 pub enum RepoRetrievalError {
     /// The item associated with the ID has already been retrieved. Either use
     /// the existing retrieval or release it back to the repo before retrieving
     /// it again.
+    #[display("the item is already retrieved")]
     AlreadyRetrieved,
     /// The item associated with the ID could not be found.
+    #[display("no item has that ID")]
     IdNotFound,
 }
@@ -0,0 +1,497 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use crate::domain::{CalendarList, EventBody, EventInstance, Task, TaskList, Timeline};
+
+use super::{RepoRetrievalError, Repository};
+
+/// A repository that keeps copies of the event bodies and instances it
+/// retrieves from another, slower repository, so that only the most recently
+/// used ones have to be held in memory.
+///
+/// Changes are made to the copies and written back to the inner repository
+/// when a copy is evicted to make room for another, or on [`flush`]. Copies
+/// that are retrieved at the time stay in the cache until they are returned.
+/// Anything else, including tasks and newly added items, goes straight to the
+/// inner repository.
+///
+/// [`flush`]: CachingRepo::flush
+#[derive(Debug)]
+pub struct CachingRepo<R: Repository> {
+    inner: R,
+    /// How many bodies and instances are kept, unless more are retrieved at
+    /// once.
+    capacity: usize,
+    cache: Mutex<Cache<R::EventInstanceId, R::EventBodyId>>,
+}
+
+/// How well a [`CachingRepo`] has been doing.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Retrievals served from the cache.
+    pub hits: u64,
+    /// Retrievals that had to go to the inner repository.
+    pub misses: u64,
+    /// Items dropped from the cache to make room for others.
+    pub evictions: u64,
+    /// Changed items written to the inner repository.
+    pub write_backs: u64,
+}
+
+#[derive(Debug)]
+struct Cache<I, B> {
+    instances: HashMap<I, Entry<EventInstance<B>>>,
+    bodies: HashMap<B, Entry<EventBody>>,
+    /// Every cached item by when it was last retrieved, oldest first.
+    recency: BTreeMap<u64, Key<I, B>>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+type CacheOf<R> = Cache<<R as Repository>::EventInstanceId, <R as Repository>::EventBodyId>;
+
+#[derive(Debug, Copy, Clone)]
+enum Key<I, B> {
+    Instance(I),
+    Body(B),
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+    last_used: u64,
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    /// The cached copy, or `None` while it is retrieved.
+    data: Option<T>,
+    /// Whether the copy has been changed since it was last written back.
+    dirty: bool,
+}
+
+impl<R: Repository> CachingRepo<R> {
+    /// Wraps `inner`, keeping at most `capacity` bodies and instances in
+    /// memory at once.
+    pub fn new(inner: R, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            cache: Mutex::new(Cache {
+                instances: HashMap::new(),
+                bodies: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// The repository being cached. It does not have changes that are still
+    /// only in the cache.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats
+    }
+
+    /// How many bodies and instances are in the cache.
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().recency.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes every changed item in the cache back to the inner repository,
+    /// except those that are retrieved at the time, which are written once
+    /// they have been returned and are flushed or evicted.
+    pub fn flush(&self) -> Result<(), RepoRetrievalError> {
+        let mut cache = self.cache.lock().unwrap();
+        let Cache {
+            instances,
+            bodies,
+            stats,
+            ..
+        } = &mut *cache;
+        for (&id, entry) in instances.iter() {
+            let mut slot = entry.slot.lock().unwrap();
+            if let Some(data) = slot.data.as_ref().filter(|_| slot.dirty) {
+                *self.inner.get_event_instance(id)? = data.clone();
+                slot.dirty = false;
+                stats.write_backs += 1;
+            }
+        }
+        for (&id, entry) in bodies.iter() {
+            let mut slot = entry.slot.lock().unwrap();
+            if let Some(data) = slot.data.as_ref().filter(|_| slot.dirty) {
+                *self.inner.get_event_body(id)? = data.clone();
+                slot.dirty = false;
+                stats.write_backs += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Repository> CachingRepo<R>
+where
+    R::EventInstanceId: Eq + Hash,
+    R::EventBodyId: Eq + Hash,
+{
+    /// Drops the least recently used items that are not retrieved until the
+    /// cache is back within its capacity, writing back the changed ones.
+    fn evict(&self, cache: &mut CacheOf<R>) {
+        let Cache {
+            instances,
+            bodies,
+            recency,
+            stats,
+            ..
+        } = cache;
+        let mut over = recency.len().saturating_sub(self.capacity);
+        let mut evicted = Vec::new();
+        for (&tick, &key) in recency.iter() {
+            if over == 0 {
+                break;
+            }
+            let dirty = match key {
+                Key::Instance(id) => write_back(instances, id, |data| {
+                    *self.inner.get_event_instance(id)? = data.clone();
+                    Ok(())
+                }),
+                Key::Body(id) => write_back(bodies, id, |data| {
+                    *self.inner.get_event_body(id)? = data.clone();
+                    Ok(())
+                }),
+            };
+            // items that are retrieved at the moment stay
+            if let Some(dirty) = dirty {
+                evicted.push(tick);
+                stats.evictions += 1;
+                stats.write_backs += u64::from(dirty);
+                over -= 1;
+            }
+        }
+        for tick in evicted {
+            recency.remove(&tick);
+        }
+    }
+
+    /// Lends out a cached item, or a copy of it from the inner repository,
+    /// and marks it as the most recently used.
+    fn lend<K: Eq + Hash + Copy, T>(
+        &self,
+        id: K,
+        key: Key<R::EventInstanceId, R::EventBodyId>,
+        map: fn(&mut CacheOf<R>) -> &mut HashMap<K, Entry<T>>,
+        fetch: impl FnOnce() -> Result<T, RepoRetrievalError>,
+    ) -> Result<CachedRef<T>, RepoRetrievalError> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let now = cache.clock;
+        let guard = match map(&mut cache).get_mut(&id) {
+            Some(entry) => {
+                let data = entry
+                    .slot
+                    .lock()
+                    .unwrap()
+                    .data
+                    .take()
+                    .ok_or(RepoRetrievalError::AlreadyRetrieved)?;
+                let last_used = std::mem::replace(&mut entry.last_used, now);
+                let guard = CachedRef::new(data, Arc::clone(&entry.slot));
+                cache.recency.remove(&last_used);
+                cache.stats.hits += 1;
+                guard
+            }
+            None => {
+                let data = fetch()?;
+                let slot = Arc::new(Mutex::new(Slot {
+                    data: None,
+                    dirty: false,
+                }));
+                let entry = Entry {
+                    slot: Arc::clone(&slot),
+                    last_used: now,
+                };
+                map(&mut cache).insert(id, entry);
+                cache.stats.misses += 1;
+                CachedRef::new(data, slot)
+            }
+        };
+        cache.recency.insert(now, key);
+        self.evict(&mut cache);
+        Ok(guard)
+    }
+}
+
+/// Removes an item from the cache, writing it back first if it was changed.
+/// Returns whether it was changed, or `None` if it has to stay in the cache
+/// because it is retrieved or could not be written back.
+fn write_back<K: Eq + Hash, T>(
+    map: &mut HashMap<K, Entry<T>>,
+    id: K,
+    write: impl FnOnce(&T) -> Result<(), RepoRetrievalError>,
+) -> Option<bool> {
+    let slot = map.get(&id)?.slot.lock().unwrap();
+    let data = slot.data.as_ref()?;
+    let dirty = slot.dirty;
+    if dirty && write(data).is_err() {
+        return None;
+    }
+    drop(slot);
+    map.remove(&id);
+    Some(dirty)
+}
+
+impl<R: Repository> Drop for CachingRepo<R> {
+    fn drop(&mut self) {
+        // changes still in the cache are lost if they cannot be written back
+        let _ = self.flush();
+    }
+}
+
+impl<R: Repository> Repository for CachingRepo<R>
+where
+    R::EventInstanceId: Eq + Hash + 'static,
+    R::EventBodyId: Eq + Hash + 'static,
+{
+    fn get_timeline(
+        &self,
+    ) -> Option<impl DerefMut<Target = Timeline<Self::EventInstanceId>> + 'static + use<R>> {
+        self.inner.get_timeline()
+    }
+
+    type EventInstanceId = R::EventInstanceId;
+
+    fn get_event_instance(
+        &self,
+        id: Self::EventInstanceId,
+    ) -> Result<
+        impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<R>,
+        RepoRetrievalError,
+    > {
+        self.lend(
+            id,
+            Key::Instance(id),
+            |cache| &mut cache.instances,
+            || Ok(self.inner.get_event_instance(id)?.clone()),
+        )
+    }
+
+    fn add_event_instance(
+        &self,
+        instance: EventInstance<Self::EventBodyId>,
+    ) -> (
+        Self::EventInstanceId,
+        impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<R>,
+    ) {
+        self.inner.add_event_instance(instance)
+    }
+
+    type EventBodyId = R::EventBodyId;
+
+    fn get_event_body(
+        &self,
+        id: Self::EventBodyId,
+    ) -> Result<impl DerefMut<Target = EventBody> + 'static + use<R>, RepoRetrievalError> {
+        self.lend(
+            id,
+            Key::Body(id),
+            |cache| &mut cache.bodies,
+            || Ok(self.inner.get_event_body(id)?.clone()),
+        )
+    }
+
+    fn add_event_body(
+        &self,
+        body: EventBody,
+    ) -> (
+        Self::EventBodyId,
+        impl DerefMut<Target = EventBody> + 'static + use<R>,
+    ) {
+        self.inner.add_event_body(body)
+    }
+
+    fn get_task_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = TaskList<Self::TaskId>> + 'static + use<R>> {
+        self.inner.get_task_list()
+    }
+
+    type TaskId = R::TaskId;
+
+    fn get_task(
+        &self,
+        id: Self::TaskId,
+    ) -> Result<
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<R>,
+        RepoRetrievalError,
+    > {
+        self.inner.get_task(id)
+    }
+
+    fn add_task(
+        &self,
+        task: Task<Self::EventInstanceId>,
+    ) -> (
+        Self::TaskId,
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<R>,
+    ) {
+        self.inner.add_task(task)
+    }
+
+    fn get_calendar_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = CalendarList<Self::EventInstanceId>> + 'static + use<R>>
+    {
+        self.inner.get_calendar_list()
+    }
+}
+
+/// A cached item that has been retrieved. It goes back into its slot in the
+/// cache when dropped, marked as changed if it was borrowed mutably.
+struct CachedRef<T> {
+    // only an option so that it can be moved out in the destructor
+    data: Option<T>,
+    slot: Arc<Mutex<Slot<T>>>,
+    changed: bool,
+}
+
+impl<T> CachedRef<T> {
+    fn new(data: T, slot: Arc<Mutex<Slot<T>>>) -> Self {
+        Self {
+            data: Some(data),
+            slot,
+            changed: false,
+        }
+    }
+}
+
+impl<T> Deref for CachedRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+            .as_ref()
+            .expect("data should be Some in normal operation")
+    }
+}
+
+impl<T> DerefMut for CachedRef<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
+        self.data
+            .as_mut()
+            .expect("data should be Some in normal operation")
+    }
+}
+
+impl<T> Drop for CachedRef<T> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.data = self.data.take();
+        slot.dirty |= self.changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use super::*;
+    use crate::{domain::TimeSpan, repository::memory_repo::MemoryRepo};
+
+    // a small cache, so that items are evicted and fetched again all the time
+    crate::repository_conformance_tests!(CachingRepo::new(MemoryRepo::new(), 2));
+
+    fn add_bodies(repo: &MemoryRepo, count: usize) -> Vec<crate::BodyId> {
+        (0..count)
+            .map(|i| {
+                let start = Utc.with_ymd_and_hms(2024, 6, 10, i as u32, 0, 0).unwrap();
+                let (_, body_id, ..) = crate::add_event(
+                    repo,
+                    TimeSpan::Instant(start),
+                    format!("Event {i}"),
+                    String::new(),
+                );
+                body_id
+            })
+            .collect()
+    }
+
+    #[test]
+    fn evicts_least_recently_used_and_writes_back() {
+        let inner = MemoryRepo::new();
+        let ids = add_bodies(&inner, 3);
+        let repo = CachingRepo::new(inner, 2);
+
+        repo.get_event_body(ids[0]).unwrap().summary = "Changed".to_owned();
+        assert_eq!(repo.get_event_body(ids[1]).unwrap().summary, "Event 1");
+        assert_eq!(repo.get_event_body(ids[0]).unwrap().summary, "Changed");
+        // the change has not reached the inner repository yet
+        assert_eq!(
+            repo.inner().get_event_body(ids[0]).unwrap().summary,
+            "Event 0"
+        );
+
+        // the third body pushes out the second, which was used least recently
+        // and has no changes, and then the changed first one
+        let _ = repo.get_event_body(ids[2]).unwrap();
+        assert_eq!(repo.len(), 2);
+        let _ = repo.get_event_body(ids[1]).unwrap();
+        assert_eq!(
+            repo.inner().get_event_body(ids[0]).unwrap().summary,
+            "Changed"
+        );
+        assert_eq!(
+            repo.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 4,
+                evictions: 2,
+                write_backs: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_retrieved_items_and_flushes() {
+        let inner = MemoryRepo::new();
+        let ids = add_bodies(&inner, 3);
+        let repo = CachingRepo::new(inner, 1);
+
+        let mut first = repo.get_event_body(ids[0]).unwrap();
+        let mut second = repo.get_event_body(ids[1]).unwrap();
+        assert!(matches!(
+            repo.get_event_body(ids[0]),
+            Err(RepoRetrievalError::AlreadyRetrieved)
+        ));
+        first.location = "Office".to_owned();
+        second.location = "Home".to_owned();
+        drop((first, second));
+        assert_eq!(repo.len(), 2);
+
+        repo.flush().unwrap();
+        assert_eq!(repo.stats().write_backs, 2);
+        assert_eq!(
+            repo.inner().get_event_body(ids[0]).unwrap().location,
+            "Office"
+        );
+        assert_eq!(
+            repo.inner().get_event_body(ids[1]).unwrap().location,
+            "Home"
+        );
+        // flushing again has nothing to write
+        repo.flush().unwrap();
+        assert_eq!(repo.stats().write_backs, 2);
+    }
+}
@@ -0,0 +1,343 @@
+//! Checks that a [`Repository`] behaves the way the rest of the crate relies
+//! on, for testing new backends.
+//!
+//! Every check takes a function that makes a new, empty repository, and
+//! panics if the repository misbehaves. The easiest way to run them all is
+//! [`repository_conformance_tests!`](crate::repository_conformance_tests),
+//! which turns each into a test:
+//!
+//! ```ignore
+//! #[cfg(test)]
+//! mod tests {
+//!     metime_core::repository_conformance_tests!(MyRepo::open_temporary());
+//! }
+//! ```
+
+use std::{collections::BTreeMap, fmt::Debug, sync::Barrier, thread};
+
+use chrono::{prelude::*, TimeDelta};
+
+use crate::domain::{
+    Alarm, AlarmState, AlarmTrigger, Calendar, EventBody, EventInstance, EventStatus, Outcome,
+    Task, TimeSpan,
+};
+
+use super::{RepoRetrievalError, Repository};
+
+/// Generates a test for every check in [`conformance`](self), each run on a
+/// repository made by evaluating `$make`.
+#[macro_export]
+macro_rules! repository_conformance_tests {
+    ($make:expr) => {
+        #[test]
+        fn lends_items_exclusively() {
+            $crate::conformance::lends_items_exclusively(|| $make);
+        }
+
+        #[test]
+        fn keeps_changes_made_through_retrievals() {
+            $crate::conformance::keeps_changes_made_through_retrievals(|| $make);
+        }
+
+        #[test]
+        fn reports_missing_ids() {
+            $crate::conformance::reports_missing_ids(|| $make);
+        }
+
+        #[test]
+        fn round_trips_every_field() {
+            $crate::conformance::round_trips_every_field(|| $make);
+        }
+
+        #[test]
+        fn keeps_the_timeline_consistent() {
+            $crate::conformance::keeps_the_timeline_consistent(|| $make);
+        }
+
+        #[test]
+        fn lends_across_threads() {
+            $crate::conformance::lends_across_threads(|| $make);
+        }
+    };
+}
+
+fn time(hour: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 6, 10, hour, 0, 0).unwrap()
+}
+
+fn body(summary: &str) -> EventBody {
+    EventBody {
+        summary: summary.to_owned(),
+        description: String::new(),
+        location: String::new(),
+        categories: Vec::new(),
+        status: EventStatus::default(),
+        alarms: Vec::new(),
+    }
+}
+
+/// An item can only be retrieved once at a time, and can be retrieved again
+/// once the retrieval is dropped.
+pub fn lends_items_exclusively<R: Repository>(make: impl Fn() -> R) {
+    let repo = make();
+    let (instance_id, body_id, instance, body) = crate::add_event(
+        &repo,
+        TimeSpan::Instant(time(9)),
+        "Standup".to_owned(),
+        String::new(),
+    );
+    let (task_id, task) = crate::add_task(&repo, Task::new("Report".to_owned()));
+    // items being added count as retrieved until they are returned
+    assert!(matches!(
+        repo.get_event_instance(instance_id),
+        Err(RepoRetrievalError::AlreadyRetrieved)
+    ));
+    drop((instance, body, task));
+
+    let instance = repo.get_event_instance(instance_id).unwrap();
+    assert!(matches!(
+        repo.get_event_instance(instance_id),
+        Err(RepoRetrievalError::AlreadyRetrieved)
+    ));
+    let body = repo.get_event_body(body_id).unwrap();
+    assert!(matches!(
+        repo.get_event_body(body_id),
+        Err(RepoRetrievalError::AlreadyRetrieved)
+    ));
+    let task = repo.get_task(task_id).unwrap();
+    assert!(matches!(
+        repo.get_task(task_id),
+        Err(RepoRetrievalError::AlreadyRetrieved)
+    ));
+    drop((instance, body, task));
+    assert!(repo.get_event_instance(instance_id).is_ok());
+    assert!(repo.get_event_body(body_id).is_ok());
+    assert!(repo.get_task(task_id).is_ok());
+
+    let timeline = repo.get_timeline().unwrap();
+    assert!(repo.get_timeline().is_none());
+    drop(timeline);
+    assert!(repo.get_timeline().is_some());
+    let task_list = repo.get_task_list().unwrap();
+    assert!(repo.get_task_list().is_none());
+    drop(task_list);
+    assert!(repo.get_task_list().is_some());
+    let calendar_list = repo.get_calendar_list().unwrap();
+    assert!(repo.get_calendar_list().is_none());
+    drop(calendar_list);
+    assert!(repo.get_calendar_list().is_some());
+}
+
+/// Changes made through a retrieval are kept when it is dropped, including
+/// the retrieval given when an item is added.
+pub fn keeps_changes_made_through_retrievals<R: Repository>(make: impl Fn() -> R) {
+    let repo = make();
+    let (body_id, mut body) = repo.add_event_body(body("Standup"));
+    body.summary = "Planning".to_owned();
+    drop(body);
+    assert_eq!(repo.get_event_body(body_id).unwrap().summary, "Planning");
+
+    repo.get_event_body(body_id).unwrap().location = "Room 1".to_owned();
+    let body = repo.get_event_body(body_id).unwrap();
+    assert_eq!(body.summary, "Planning");
+    assert_eq!(body.location, "Room 1");
+    drop(body);
+
+    let (task_id, mut task) = repo.add_task(Task::new("Report".to_owned()));
+    task.priority = Some(1);
+    drop(task);
+    repo.get_task(task_id).unwrap().percent_complete = 50;
+    let task = repo.get_task(task_id).unwrap();
+    assert_eq!((task.priority, task.percent_complete), (Some(1), 50));
+}
+
+/// Retrieving an ID that the repository never gave out fails with
+/// [`RepoRetrievalError::IdNotFound`]. The IDs are the last ones given out by
+/// another repository made by `make`, which has had more items added than
+/// the one asked, so that backends numbering items from the start in each
+/// repository are checked as well.
+pub fn reports_missing_ids<R: Repository>(make: impl Fn() -> R) {
+    let (other, repo) = (make(), make());
+    let add = |repo: &R| {
+        let (instance_id, body_id, ..) = crate::add_event(
+            repo,
+            TimeSpan::Instant(time(9)),
+            String::new(),
+            String::new(),
+        );
+        let (task_id, _) = crate::add_task(repo, Task::new(String::new()));
+        (instance_id, body_id, task_id)
+    };
+    add(&repo);
+    add(&other);
+    let (instance_id, body_id, task_id) = add(&other);
+
+    assert!(matches!(
+        repo.get_event_instance(instance_id),
+        Err(RepoRetrievalError::IdNotFound)
+    ));
+    assert!(matches!(
+        repo.get_event_body(body_id),
+        Err(RepoRetrievalError::IdNotFound)
+    ));
+    assert!(matches!(
+        repo.get_task(task_id),
+        Err(RepoRetrievalError::IdNotFound)
+    ));
+}
+
+/// Every field of every kind of item comes back the way it was stored.
+pub fn round_trips_every_field<R: Repository>(make: impl Fn() -> R)
+where
+    R::EventInstanceId: PartialEq + Debug,
+    R::EventBodyId: PartialEq + Debug,
+{
+    let repo = make();
+    let body = EventBody {
+        summary: "Standup".to_owned(),
+        description: "Daily sync".to_owned(),
+        location: "Room 1".to_owned(),
+        categories: vec!["work".to_owned(), "team".to_owned()],
+        status: EventStatus::Tentative,
+        alarms: vec![Alarm {
+            uid: "join".to_owned(),
+            trigger: AlarmTrigger::Start(-TimeDelta::minutes(5)),
+            description: "Join the call".to_owned(),
+        }],
+    };
+    let (body_id, _) = repo.add_event_body(body.clone());
+    let instance = EventInstance {
+        time_span: TimeSpan::Interval {
+            start: time(9),
+            duration: TimeDelta::minutes(15),
+        },
+        body: body_id,
+        actual: Some(TimeSpan::Interval {
+            start: time(9),
+            duration: TimeDelta::minutes(25),
+        }),
+        outcome: Some(Outcome::Partial),
+        alarms: vec![Alarm {
+            uid: "prepare".to_owned(),
+            trigger: AlarmTrigger::Absolute(time(8)),
+            description: String::new(),
+        }],
+        alarm_states: BTreeMap::from([
+            ("join".to_owned(), AlarmState::Acknowledged(time(8))),
+            ("prepare".to_owned(), AlarmState::Snoozed(time(9))),
+        ]),
+        uid: Some("standup@example.com".to_owned()),
+    };
+    let (instance_id, _) = repo.add_event_instance(instance.clone());
+    let task = Task {
+        description: "For the quarter".to_owned(),
+        categories: vec!["work".to_owned()],
+        due: Some(TimeSpan::Instant(time(17))),
+        estimated_duration: Some(TimeDelta::minutes(90)),
+        priority: Some(2),
+        percent_complete: 40,
+        completed_at: Some(time(16)),
+        scheduled: Some(instance_id),
+        ..Task::new("Write report".to_owned())
+    };
+    let (task_id, _) = repo.add_task(task.clone());
+    let calendar = Calendar {
+        colour: "#3465a4".to_owned(),
+        default_zone: Some(chrono_tz::Europe::Berlin),
+        events: vec![instance_id],
+        ..Calendar::new("work".to_owned())
+    };
+    repo.get_calendar_list()
+        .unwrap()
+        .calendars
+        .push(calendar.clone());
+
+    assert_eq!(*repo.get_event_body(body_id).unwrap(), body);
+    assert_eq!(*repo.get_event_instance(instance_id).unwrap(), instance);
+    assert_eq!(*repo.get_task(task_id).unwrap(), task);
+    assert_eq!(repo.get_calendar_list().unwrap().calendars, [calendar]);
+}
+
+/// Events added with [`add_event`](crate::add_event) and its relatives are on
+/// the timeline under their start times, and stay there.
+pub fn keeps_the_timeline_consistent<R: Repository>(make: impl Fn() -> R)
+where
+    R::EventInstanceId: PartialEq + Debug,
+    R::TaskId: PartialEq + Debug,
+{
+    let repo = make();
+    assert!(repo.get_timeline().unwrap().events.is_empty());
+    let (later, body_id, ..) = crate::add_event(
+        &repo,
+        TimeSpan::Instant(time(14)),
+        "Review".to_owned(),
+        String::new(),
+    );
+    let (earlier, _) = crate::add_event_instance(&repo, body_id, TimeSpan::Instant(time(9)));
+    let (task_id, _) = crate::add_task(&repo, Task::new("Report".to_owned()));
+    let scheduled = crate::schedule_task(&repo, task_id, TimeSpan::Instant(time(11))).unwrap();
+
+    let timeline = repo.get_timeline().unwrap();
+    let ids: Vec<_> = timeline.events.values().copied().collect();
+    assert_eq!(ids, [earlier, scheduled, later]);
+    for (&start, &id) in &timeline.events {
+        let instance = repo.get_event_instance(id).unwrap();
+        assert_eq!(instance.time_span.earliest(), start);
+        // every instance on the timeline has a body
+        assert!(repo.get_event_body(instance.body).is_ok());
+    }
+    drop(timeline);
+    assert_eq!(repo.get_task_list().unwrap().tasks, [task_id]);
+    assert_eq!(repo.get_task(task_id).unwrap().scheduled, Some(scheduled));
+}
+
+/// Items can be retrieved from several threads at once, each retrieval
+/// excluding the others, and changes made on one thread are seen on the
+/// others.
+pub fn lends_across_threads<R: Repository + Sync>(make: impl Fn() -> R)
+where
+    R::EventBodyId: Send + Sync,
+{
+    const THREADS: usize = 4;
+    const ROUNDS: usize = 25;
+
+    let repo = make();
+    let (body_id, _) = repo.add_event_body(body(""));
+    let barrier = Barrier::new(THREADS);
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let (repo, barrier) = (&repo, &barrier);
+            scope.spawn(move || {
+                barrier.wait();
+                let mut added = 0;
+                while added < ROUNDS {
+                    // whoever holds the body is the only one changing it
+                    match repo.get_event_body(body_id) {
+                        Ok(mut body) => {
+                            body.categories.push(thread.to_string());
+                            added += 1;
+                        }
+                        Err(RepoRetrievalError::AlreadyRetrieved) => thread::yield_now(),
+                        Err(e) => panic!("{e}"),
+                    }
+                }
+                // items added on other threads are independent of this one's
+                let (own_id, _) = repo.add_event_body(body(&thread.to_string()));
+                assert_eq!(
+                    repo.get_event_body(own_id).unwrap().summary,
+                    thread.to_string()
+                );
+            });
+        }
+    });
+    let body = repo.get_event_body(body_id).unwrap();
+    assert_eq!(body.categories.len(), THREADS * ROUNDS);
+    for thread in 0..THREADS {
+        let count = body
+            .categories
+            .iter()
+            .filter(|category| **category == thread.to_string())
+            .count();
+        assert_eq!(count, ROUNDS);
+    }
+}
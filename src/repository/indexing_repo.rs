@@ -0,0 +1,231 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    domain::{CalendarList, EventBody, EventInstance, Task, TaskList, Timeline},
+    search::SearchIndex,
+};
+
+use super::{RepoRetrievalError, Repository};
+
+/// A repository that keeps a [`SearchIndex`] of the bodies of another
+/// repository up to date, whichever part of the crate changes them.
+///
+/// Added bodies are indexed straight away, and a retrieved body is reindexed
+/// when it is returned, if it was borrowed mutably. Changes made to the inner
+/// repository directly are not seen.
+#[derive(Debug)]
+pub struct IndexingRepo<R: Repository> {
+    inner: R,
+    index: Arc<Mutex<SearchIndex<R::EventBodyId>>>,
+}
+
+impl<R: Repository> IndexingRepo<R>
+where
+    R::EventBodyId: Ord,
+{
+    /// Wraps `inner`, indexing the bodies of the instances on its timeline.
+    pub fn new(inner: R) -> Self {
+        let mut index = SearchIndex::new();
+        if let Some(timeline) = inner.get_timeline() {
+            for &id in timeline.events.values() {
+                let Ok(instance) = inner.get_event_instance(id) else {
+                    continue;
+                };
+                if let Ok(body) = inner.get_event_body(instance.body) {
+                    index.index_body(instance.body, &body);
+                }
+            }
+        }
+        Self {
+            inner,
+            index: Arc::new(Mutex::new(index)),
+        }
+    }
+
+    /// The repository being indexed.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// The index of every body added or changed so far. Bodies returned
+    /// through the repository wait until it is released, so it should not be
+    /// held on to.
+    pub fn index(&self) -> MutexGuard<'_, SearchIndex<R::EventBodyId>> {
+        self.index.lock().unwrap()
+    }
+}
+
+impl<R: Repository> Repository for IndexingRepo<R>
+where
+    R::EventBodyId: Ord + Send + 'static,
+{
+    fn get_timeline(
+        &self,
+    ) -> Option<impl DerefMut<Target = Timeline<Self::EventInstanceId>> + 'static + use<R>> {
+        self.inner.get_timeline()
+    }
+
+    type EventInstanceId = R::EventInstanceId;
+
+    fn get_event_instance(
+        &self,
+        id: Self::EventInstanceId,
+    ) -> Result<
+        impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<R>,
+        RepoRetrievalError,
+    > {
+        self.inner.get_event_instance(id)
+    }
+
+    fn add_event_instance(
+        &self,
+        instance: EventInstance<Self::EventBodyId>,
+    ) -> (
+        Self::EventInstanceId,
+        impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<R>,
+    ) {
+        self.inner.add_event_instance(instance)
+    }
+
+    type EventBodyId = R::EventBodyId;
+
+    fn get_event_body(
+        &self,
+        id: Self::EventBodyId,
+    ) -> Result<impl DerefMut<Target = EventBody> + 'static + use<R>, RepoRetrievalError> {
+        let guard = self.inner.get_event_body(id)?;
+        Ok(IndexedBody::new(guard, id, &self.index))
+    }
+
+    fn add_event_body(
+        &self,
+        body: EventBody,
+    ) -> (
+        Self::EventBodyId,
+        impl DerefMut<Target = EventBody> + 'static + use<R>,
+    ) {
+        let (id, guard) = self.inner.add_event_body(body);
+        self.index().index_body(id, &guard);
+        (id, IndexedBody::new(guard, id, &self.index))
+    }
+
+    fn get_task_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = TaskList<Self::TaskId>> + 'static + use<R>> {
+        self.inner.get_task_list()
+    }
+
+    type TaskId = R::TaskId;
+
+    fn get_task(
+        &self,
+        id: Self::TaskId,
+    ) -> Result<
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<R>,
+        RepoRetrievalError,
+    > {
+        self.inner.get_task(id)
+    }
+
+    fn add_task(
+        &self,
+        task: Task<Self::EventInstanceId>,
+    ) -> (
+        Self::TaskId,
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<R>,
+    ) {
+        self.inner.add_task(task)
+    }
+
+    fn get_calendar_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = CalendarList<Self::EventInstanceId>> + 'static + use<R>>
+    {
+        self.inner.get_calendar_list()
+    }
+}
+
+/// A body retrieved from the inner repository, which is reindexed when it is
+/// returned if it was borrowed mutably.
+struct IndexedBody<G: DerefMut<Target = EventBody>, B: Copy + Ord> {
+    guard: G,
+    id: B,
+    index: Arc<Mutex<SearchIndex<B>>>,
+    changed: bool,
+}
+
+impl<G: DerefMut<Target = EventBody>, B: Copy + Ord> IndexedBody<G, B> {
+    fn new(guard: G, id: B, index: &Arc<Mutex<SearchIndex<B>>>) -> Self {
+        Self {
+            guard,
+            id,
+            index: Arc::clone(index),
+            changed: false,
+        }
+    }
+}
+
+impl<G: DerefMut<Target = EventBody>, B: Copy + Ord> Deref for IndexedBody<G, B> {
+    type Target = EventBody;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut<Target = EventBody>, B: Copy + Ord> DerefMut for IndexedBody<G, B> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
+        &mut self.guard
+    }
+}
+
+impl<G: DerefMut<Target = EventBody>, B: Copy + Ord> Drop for IndexedBody<G, B> {
+    fn drop(&mut self) {
+        if self.changed {
+            self.index.lock().unwrap().index_body(self.id, &self.guard);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::prelude::*;
+
+    use super::*;
+    use crate::{domain::TimeSpan, repository::memory_repo::MemoryRepo, search::search_events};
+
+    crate::repository_conformance_tests!(IndexingRepo::new(MemoryRepo::new()));
+
+    #[test]
+    fn indexes_bodies_however_they_are_changed() {
+        let inner = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+        let (instance, dentist, ..) = crate::add_event(
+            &inner,
+            TimeSpan::Instant(start),
+            "Dentist".to_owned(),
+            String::new(),
+        );
+        let repo = IndexingRepo::new(inner);
+        assert_eq!(repo.index().query("dent"), [dentist].into());
+
+        let (_, standup, ..) = crate::add_event(
+            &repo,
+            TimeSpan::Instant(start + chrono::Duration::hours(1)),
+            "Standup".to_owned(),
+            String::new(),
+        );
+        assert_eq!(repo.index().query("stand"), [standup].into());
+
+        // reading a body leaves it as it is, changing it reindexes it
+        let _ = repo.get_event_body(dentist).unwrap().summary.len();
+        repo.get_event_body(dentist).unwrap().summary = "Orthodontist".to_owned();
+        assert!(repo.index().query("dentist").is_empty());
+        let index = repo.index();
+        assert_eq!(search_events(&repo, &index, "ortho"), vec![instance]);
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use chrono::prelude::*;
+use uuid::Uuid;
+
+use crate::{
+    domain::{CalendarList, EventBody, EventInstance, Task, TaskList, Timeline},
+    oplog::{OpLog, OpLogError, Operation},
+    replica::{BodyField, InstanceField, ObjectId},
+};
+
+use super::{RepoRetrievalError, Repository};
+
+/// A repository that appends every change to the events of another
+/// repository to an [`OpLog`], whichever part of the crate makes it.
+///
+/// Adding a body or an instance logs its creation, changes made to a
+/// retrieved body or instance are logged field by field when it is returned,
+/// and taking an instance off the timeline logs its deletion. An instance
+/// that is only displaced from the timeline, by another one put at its
+/// start, is not deleted, as the repository still holds it. Tasks and
+/// calendars are not logged.
+///
+/// Operations that the log rejects, such as edits of an instance that was
+/// deleted from it, are kept aside to be looked at with
+/// [`take_rejected`](Self::take_rejected).
+#[derive(Debug)]
+pub struct LoggingRepo<R: Repository> {
+    inner: R,
+    ids: Arc<Mutex<Ids<R::EventInstanceId, R::EventBodyId>>>,
+    log: Log,
+}
+
+/// An operation that the log would not take, and why.
+type Rejected = (Operation, OpLogError);
+
+/// Where the operations made through a [`LoggingRepo`] go.
+#[derive(Debug, Clone)]
+struct Log {
+    operations: Arc<Mutex<OpLog>>,
+    rejected: Arc<Mutex<Vec<Rejected>>>,
+}
+
+/// The IDs in the log of the bodies and instances of the inner repository.
+#[derive(Debug)]
+struct Ids<I, B> {
+    instances: HashMap<I, ObjectId>,
+    bodies: HashMap<B, ObjectId>,
+}
+
+impl<R: Repository> LoggingRepo<R>
+where
+    R::EventInstanceId: Eq + Hash,
+    R::EventBodyId: Eq + Hash,
+{
+    /// Wraps `inner`, which should be empty, after loading the current state
+    /// of `log` into it. Changes made to `inner` directly are not logged.
+    pub fn new(inner: R, log: OpLog) -> Result<Self, OpLogError> {
+        let (bodies, instances) = log.current().load(&inner)?;
+        let ids = Ids {
+            instances: instances
+                .into_iter()
+                .map(|(id, local)| (local, id))
+                .collect(),
+            bodies: bodies.into_iter().map(|(id, local)| (local, id)).collect(),
+        };
+        Ok(Self {
+            inner,
+            ids: Arc::new(Mutex::new(ids)),
+            log: Log {
+                operations: Arc::new(Mutex::new(log)),
+                rejected: Arc::default(),
+            },
+        })
+    }
+
+    /// The repository being logged.
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// The log of every change so far. Changes made through the repository
+    /// wait until it is released, so it should not be held on to.
+    pub fn log(&self) -> MutexGuard<'_, OpLog> {
+        self.log.operations.lock().unwrap()
+    }
+
+    /// Takes the operations that the log has rejected since the last call,
+    /// in the order they were made. Each of them is a change to the
+    /// repository that the log does not have.
+    pub fn take_rejected(&self) -> Vec<Rejected> {
+        std::mem::take(&mut *self.log.rejected.lock().unwrap())
+    }
+
+    /// The ID in the log of an instance of the repository.
+    pub fn instance_object(&self, id: R::EventInstanceId) -> Option<ObjectId> {
+        self.ids.lock().unwrap().instances.get(&id).copied()
+    }
+
+    /// The ID in the log of a body of the repository.
+    pub fn body_object(&self, id: R::EventBodyId) -> Option<ObjectId> {
+        self.ids.lock().unwrap().bodies.get(&id).copied()
+    }
+}
+
+impl Log {
+    /// Appends an operation to the log, made now or, if the clock has gone
+    /// back, at the time of the last one.
+    fn record(&self, operation: Operation) {
+        let mut log = self.operations.lock().unwrap();
+        let at = log
+            .current()
+            .at
+            .map_or_else(Utc::now, |last| Utc::now().max(last));
+        if let Err(e) = log.record(at, operation.clone()) {
+            self.rejected.lock().unwrap().push((operation, e));
+        }
+    }
+}
+
+/// An instance as the log sees it, with the ID of its body in the log.
+fn as_logged<I, B: Eq + Hash>(
+    ids: &Ids<I, B>,
+    instance: &EventInstance<B>,
+) -> Option<EventInstance<ObjectId>> {
+    Some(EventInstance {
+        time_span: instance.time_span,
+        body: *ids.bodies.get(&instance.body)?,
+        actual: instance.actual,
+        outcome: instance.outcome,
+        alarms: instance.alarms.clone(),
+        alarm_states: instance.alarm_states.clone(),
+        uid: instance.uid.clone(),
+    })
+}
+
+/// Logs the fields of an instance that differ between two versions of it.
+fn log_instance_edit<I, B: Eq + Hash>(
+    ids: &Mutex<Ids<I, B>>,
+    log: &Log,
+    id: ObjectId,
+    before: &EventInstance<B>,
+    after: &EventInstance<B>,
+) {
+    let ids = ids.lock().unwrap();
+    let (Some(before), Some(after)) = (as_logged(&ids, before), as_logged(&ids, after)) else {
+        return;
+    };
+    let changed = InstanceField::all(&after)
+        .into_iter()
+        .zip(InstanceField::all(&before))
+        .filter(|(after, before)| after != before);
+    for (field, _) in changed {
+        log.record(Operation::EditInstance {
+            instance: id,
+            field,
+        });
+    }
+}
+
+fn log_body_edit(log: &Log, id: ObjectId, before: &EventBody, after: &EventBody) {
+    let changed = BodyField::all(after)
+        .into_iter()
+        .zip(BodyField::all(before))
+        .filter(|(after, before)| after != before);
+    for (field, _) in changed {
+        log.record(Operation::EditBody { body: id, field });
+    }
+}
+
+impl<R: Repository> LoggingRepo<R>
+where
+    R::EventInstanceId: Eq + Hash + Send + 'static,
+    R::EventBodyId: Eq + Hash + Send + 'static,
+{
+    fn logged_instance<G: DerefMut<Target = EventInstance<R::EventBodyId>>>(
+        &self,
+        guard: G,
+        id: Option<ObjectId>,
+    ) -> LoggedRef<G, EventInstance<R::EventBodyId>> {
+        let ids = Arc::clone(&self.ids);
+        let log = self.log.clone();
+        LoggedRef::new(guard, Clone::clone, move |before, after| {
+            if let Some(id) = id {
+                log_instance_edit(&ids, &log, id, &before, after);
+            }
+        })
+    }
+
+    fn logged_body<G: DerefMut<Target = EventBody>>(
+        &self,
+        guard: G,
+        id: Option<ObjectId>,
+    ) -> LoggedRef<G, EventBody> {
+        let log = self.log.clone();
+        LoggedRef::new(guard, Clone::clone, move |before, after| {
+            if let Some(id) = id {
+                log_body_edit(&log, id, &before, after);
+            }
+        })
+    }
+}
+
+impl<R: Repository> Repository for LoggingRepo<R>
+where
+    R::EventInstanceId: Eq + Hash + Send + 'static,
+    R::EventBodyId: Eq + Hash + Send + 'static,
+{
+    fn get_timeline(
+        &self,
+    ) -> Option<impl DerefMut<Target = Timeline<Self::EventInstanceId>> + 'static + use<R>> {
+        let guard = self.inner.get_timeline()?;
+        let ids = Arc::clone(&self.ids);
+        let log = self.log.clone();
+        let on_timeline = |timeline: &Timeline<R::EventInstanceId>| {
+            timeline.events.values().copied().collect::<Vec<_>>()
+        };
+        Some(LoggedRef::new(guard, on_timeline, move |before, after| {
+            let ids = ids.lock().unwrap();
+            let on_timeline: BTreeSet<_> = after
+                .events
+                .values()
+                .filter_map(|id| ids.instances.get(id).copied())
+                .collect();
+            for id in before {
+                let Some(&object) = ids.instances.get(&id) else {
+                    continue;
+                };
+                let logged_start = {
+                    let log = log.operations.lock().unwrap();
+                    let current = log.current();
+                    current
+                        .timeline
+                        .iter()
+                        .find(|&(_, &other)| other == object)
+                        .map(|(&start, _)| start)
+                };
+                // an instance that another one took the place of is only
+                // displaced, and creating or moving that one takes it off the
+                // timeline of the log too
+                let displaced = logged_start.is_some_and(|start| after.events.contains_key(&start));
+                if !on_timeline.contains(&object) && logged_start.is_some() && !displaced {
+                    log.record(Operation::Delete(object));
+                }
+            }
+        }))
+    }
+
+    type EventInstanceId = R::EventInstanceId;
+
+    fn get_event_instance(
+        &self,
+        id: Self::EventInstanceId,
+    ) -> Result<
+        impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<R>,
+        RepoRetrievalError,
+    > {
+        let guard = self.inner.get_event_instance(id)?;
+        let object = self.ids.lock().unwrap().instances.get(&id).copied();
+        Ok(self.logged_instance(guard, object))
+    }
+
+    fn add_event_instance(
+        &self,
+        instance: EventInstance<Self::EventBodyId>,
+    ) -> (
+        Self::EventInstanceId,
+        impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<R>,
+    ) {
+        let mut ids = self.ids.lock().unwrap();
+        let object = as_logged(&ids, &instance).map(|logged| {
+            let id = ObjectId(Uuid::new_v4());
+            self.log.record(Operation::CreateInstance {
+                id,
+                body: logged.body,
+                time_span: logged.time_span,
+            });
+            // the rest of the fields are logged as edits of a new instance
+            let created = EventInstance {
+                actual: None,
+                outcome: None,
+                alarms: Vec::new(),
+                alarm_states: Default::default(),
+                uid: None,
+                ..logged.clone()
+            };
+            let changed = InstanceField::all(&logged)
+                .into_iter()
+                .zip(InstanceField::all(&created))
+                .filter(|(after, before)| after != before);
+            for (field, _) in changed {
+                self.log.record(Operation::EditInstance {
+                    instance: id,
+                    field,
+                });
+            }
+            id
+        });
+        let (local, guard) = self.inner.add_event_instance(instance);
+        if let Some(object) = object {
+            ids.instances.insert(local, object);
+        }
+        drop(ids);
+        (local, self.logged_instance(guard, object))
+    }
+
+    type EventBodyId = R::EventBodyId;
+
+    fn get_event_body(
+        &self,
+        id: Self::EventBodyId,
+    ) -> Result<impl DerefMut<Target = EventBody> + 'static + use<R>, RepoRetrievalError> {
+        let guard = self.inner.get_event_body(id)?;
+        let object = self.ids.lock().unwrap().bodies.get(&id).copied();
+        Ok(self.logged_body(guard, object))
+    }
+
+    fn add_event_body(
+        &self,
+        body: EventBody,
+    ) -> (
+        Self::EventBodyId,
+        impl DerefMut<Target = EventBody> + 'static + use<R>,
+    ) {
+        let object = ObjectId(Uuid::new_v4());
+        self.log.record(Operation::CreateBody {
+            id: object,
+            body: body.clone(),
+        });
+        let (local, guard) = self.inner.add_event_body(body);
+        self.ids.lock().unwrap().bodies.insert(local, object);
+        (local, self.logged_body(guard, Some(object)))
+    }
+
+    fn get_task_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = TaskList<Self::TaskId>> + 'static + use<R>> {
+        self.inner.get_task_list()
+    }
+
+    type TaskId = R::TaskId;
+
+    fn get_task(
+        &self,
+        id: Self::TaskId,
+    ) -> Result<
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<R>,
+        RepoRetrievalError,
+    > {
+        self.inner.get_task(id)
+    }
+
+    fn add_task(
+        &self,
+        task: Task<Self::EventInstanceId>,
+    ) -> (
+        Self::TaskId,
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<R>,
+    ) {
+        self.inner.add_task(task)
+    }
+
+    fn get_calendar_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = CalendarList<Self::EventInstanceId>> + 'static + use<R>>
+    {
+        self.inner.get_calendar_list()
+    }
+}
+
+/// What a [`LoggedRef`] logs when it is returned, from what was kept of the
+/// item and the item as it is now.
+type OnReturn<S, T> = Box<dyn FnOnce(S, &T) + Send>;
+
+/// An item retrieved from the inner repository, which logs what changed when
+/// it is returned. `S` is what is kept of the item to compare against, taken
+/// when it is first borrowed mutably.
+struct LoggedRef<G: DerefMut, S> {
+    guard: G,
+    before: Option<S>,
+    keep: fn(&G::Target) -> S,
+    on_return: Option<OnReturn<S, G::Target>>,
+}
+
+impl<G: DerefMut, S> LoggedRef<G, S> {
+    fn new(
+        guard: G,
+        keep: fn(&G::Target) -> S,
+        on_return: impl FnOnce(S, &G::Target) + Send + 'static,
+    ) -> Self {
+        Self {
+            guard,
+            before: None,
+            keep,
+            on_return: Some(Box::new(on_return)),
+        }
+    }
+}
+
+impl<G: DerefMut, S> Deref for LoggedRef<G, S> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut, S> DerefMut for LoggedRef<G, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        if self.before.is_none() {
+            self.before = Some((self.keep)(&self.guard));
+        }
+        &mut self.guard
+    }
+}
+
+impl<G: DerefMut, S> Drop for LoggedRef<G, S> {
+    fn drop(&mut self) {
+        // the item goes back to the inner repository after this, with the
+        // rest of the fields
+        if let (Some(before), Some(on_return)) = (self.before.take(), self.on_return.take()) {
+            on_return(before, &self.guard);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{domain::TimeSpan, repository::memory_repo::MemoryRepo};
+
+    crate::repository_conformance_tests!(LoggingRepo::new(MemoryRepo::new(), OpLog::new()).unwrap());
+
+    #[test]
+    fn logs_changes_made_through_the_library() {
+        let repo = LoggingRepo::new(MemoryRepo::new(), OpLog::new()).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let (instance, body, ..) = crate::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Standup".to_owned(),
+            String::new(),
+        );
+        repo.get_event_body(body).unwrap().summary = "Planning".to_owned();
+        let later = TimeSpan::Instant(start + TimeDelta::hours(1));
+        repo.get_event_instance(instance).unwrap().time_span = later;
+        let _ = repo.get_event_body(body).unwrap().summary.len();
+
+        let (body, instance) = (
+            repo.body_object(body).unwrap(),
+            repo.instance_object(instance).unwrap(),
+        );
+        let log = repo.log();
+        let operations: Vec<_> = log.entries().iter().map(|entry| &entry.operation).collect();
+        assert_eq!(operations.len(), 4);
+        assert!(matches!(operations[0], Operation::CreateBody { id, .. } if *id == body));
+        assert!(matches!(operations[1], Operation::CreateInstance { id, .. } if *id == instance));
+        assert_eq!(
+            *operations[2],
+            Operation::EditBody {
+                body,
+                field: BodyField::Summary("Planning".to_owned()),
+            }
+        );
+        assert_eq!(
+            *operations[3],
+            Operation::EditInstance {
+                instance,
+                field: InstanceField::TimeSpan(later),
+            }
+        );
+        let snapshot = log.current().clone();
+        drop(log);
+
+        // taking the instance off the timeline deletes it
+        repo.get_timeline().unwrap().events.clear();
+        assert_eq!(
+            repo.log().entries().last().unwrap().operation,
+            Operation::Delete(instance)
+        );
+
+        // the log rebuilds the repository as it was
+        let rebuilt = LoggingRepo::new(MemoryRepo::new(), repo.log().clone()).unwrap();
+        assert!(rebuilt.get_timeline().unwrap().events.is_empty());
+        let copy = MemoryRepo::new();
+        snapshot.load_into(&copy).unwrap();
+        let id = copy.get_timeline().unwrap().events[&(start + TimeDelta::hours(1))];
+        let copied = copy.get_event_instance(id).unwrap();
+        assert_eq!(
+            copy.get_event_body(copied.body).unwrap().summary,
+            "Planning"
+        );
+    }
+
+    #[test]
+    fn keeps_displaced_instances() {
+        let repo = LoggingRepo::new(MemoryRepo::new(), OpLog::new()).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let (standup, ..) = crate::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Standup".to_owned(),
+            String::new(),
+        );
+        // lunch takes the place of the standup on the timeline, which the
+        // repository still holds and which can still be edited
+        let (lunch, ..) = crate::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Lunch".to_owned(),
+            String::new(),
+        );
+        let later = TimeSpan::Instant(start + TimeDelta::hours(1));
+        repo.get_event_instance(standup).unwrap().time_span = later;
+        assert!(repo.take_rejected().is_empty());
+        assert!(!repo
+            .log()
+            .entries()
+            .iter()
+            .any(|entry| matches!(entry.operation, Operation::Delete(_))));
+
+        let rebuilt = MemoryRepo::new();
+        let ids = repo.log().current().load_into(&rebuilt).unwrap();
+        let timeline = rebuilt.get_timeline().unwrap();
+        assert_eq!(timeline.events.len(), 1);
+        let on_timeline = timeline.events[&start];
+        assert_eq!(on_timeline, ids[&repo.instance_object(lunch).unwrap()]);
+        let standup = ids[&repo.instance_object(standup).unwrap()];
+        assert_eq!(
+            rebuilt.get_event_instance(standup).unwrap().time_span,
+            later
+        );
+
+        // lunch is deleted with the timeline cleared, so edits of it are
+        // rejected, and kept aside rather than lost
+        drop(timeline);
+        repo.get_timeline().unwrap().events.clear();
+        let lunch_object = repo.instance_object(lunch).unwrap();
+        assert_eq!(
+            repo.log().entries().last().unwrap().operation,
+            Operation::Delete(lunch_object)
+        );
+        repo.get_event_instance(lunch).unwrap().time_span = later;
+        assert_eq!(
+            repo.take_rejected(),
+            [(
+                Operation::EditInstance {
+                    instance: lunch_object,
+                    field: InstanceField::TimeSpan(later),
+                },
+                OpLogError::UnknownObject(lunch_object),
+            )]
+        );
+        assert!(repo.take_rejected().is_empty());
+    }
+}
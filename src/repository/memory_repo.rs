@@ -1,24 +1,146 @@
 use std::{
     collections::HashMap,
-    fmt::Debug,
+    fmt::{self, Debug, Display},
+    hash::Hash,
     ops::{Deref, DerefMut},
+    str::FromStr,
     sync::{Arc, Mutex},
 };
 
-use derive_more::{
-    derive::{From, TryInto},
-    TryIntoError,
-};
+use derive_more::derive::{Display, Error};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::domain::{EventBody, EventInstance, Timeline};
+use crate::domain::{CalendarList, EventBody, EventInstance, Task, TaskList, Timeline};
 
 use super::{RepoRetrievalError, Repository};
 
+/// Defines an ID type for one kind of item in a [`MemoryRepo`]. IDs of
+/// different kinds cannot be mixed up: each is only accepted where that kind
+/// of item is expected.
+macro_rules! id_type {
+    ($(#[$meta:meta])* $name:ident, $what:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        #[cfg_attr(feature = "serde", serde(transparent))]
+        pub struct $name(Uuid);
+
+        impl $name {
+            fn new() -> Self {
+                Self(Uuid::new_v4())
+            }
+
+            /// The first eight characters of the ID, which are enough to tell
+            /// it apart from the others in all but the largest repositories.
+            pub fn short(&self) -> String {
+                self.0.simple().to_string()[..8].to_owned()
+            }
+
+            /// Finds the one ID among `ids` that starts with `prefix`,
+            /// ignoring case and hyphens, so that prefixes of both the
+            /// [`short`](Self::short) and the hyphenated form work.
+            pub fn resolve(
+                ids: impl IntoIterator<Item = Self>,
+                prefix: &str,
+            ) -> Result<Self, ResolveIdError> {
+                resolve_prefix(ids, prefix, $what)
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                Display::fmt(&self.0, f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = uuid::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map(Self)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Identifies an event instance in a [`MemoryRepo`].
+    InstanceId,
+    "event"
+);
+id_type!(
+    /// Identifies an event body in a [`MemoryRepo`].
+    BodyId,
+    "event body"
+);
+id_type!(
+    /// Identifies a task in a [`MemoryRepo`].
+    TaskId,
+    "task"
+);
+
+/// Why a shortened ID could not be resolved to a single item.
+#[derive(Debug, Display, Error, PartialEq, Eq)]
+pub enum ResolveIdError {
+    #[display("no {what} has an ID starting with {prefix}")]
+    NoMatch { what: &'static str, prefix: String },
+    #[display("{count} {what}s have IDs starting with {prefix}")]
+    Ambiguous {
+        what: &'static str,
+        prefix: String,
+        count: usize,
+    },
+}
+
+fn resolve_prefix<Id: Display>(
+    ids: impl IntoIterator<Item = Id>,
+    prefix: &str,
+    what: &'static str,
+) -> Result<Id, ResolveIdError> {
+    let prefix = prefix.to_lowercase();
+    let digits = prefix.replace('-', "");
+    let mut matches: Vec<_> = ids
+        .into_iter()
+        .filter(|id| id.to_string().replace('-', "").starts_with(&digits))
+        .collect();
+    match matches.len() {
+        0 => Err(ResolveIdError::NoMatch { what, prefix }),
+        1 => Ok(matches.remove(0)),
+        count => Err(ResolveIdError::Ambiguous {
+            what,
+            prefix,
+            count,
+        }),
+    }
+}
+
+/// Items of one kind, each in a slot of its own so that they can be lent out
+/// separately.
+type Store<Id, T> = Mutex<HashMap<Id, SlotPtr<Box<T>>>>;
+
+/// A repository that keeps everything in memory.
+///
+/// Each kind of item has its own ID type, so passing the ID of one kind where
+/// another is expected does not compile:
+///
+/// ```compile_fail
+/// use metime_core::{MemoryRepo, Repository, TimeSpan};
+///
+/// let repo = MemoryRepo::new();
+/// let now = TimeSpan::Instant(chrono::Utc::now());
+/// let (instance_id, ..) = metime_core::add_event(&repo, now, String::new(), String::new());
+/// repo.get_event_body(instance_id);
+/// ```
 #[derive(Default, Debug)]
 pub struct MemoryRepo {
-    timeline: SlotPtr<Box<Timeline<Uuid>>>,
-    blobs: Mutex<HashMap<Uuid, SlotPtr<Blob>>>,
+    timeline: SlotPtr<Box<Timeline<InstanceId>>>,
+    task_list: SlotPtr<Box<TaskList<TaskId>>>,
+    calendar_list: SlotPtr<Box<CalendarList<InstanceId>>>,
+    instances: Store<InstanceId, EventInstance<BodyId>>,
+    bodies: Store<BodyId, EventBody>,
+    tasks: Store<TaskId, Task<InstanceId>>,
 }
 
 impl MemoryRepo {
@@ -26,31 +148,60 @@ impl MemoryRepo {
         Self::default()
     }
 
-    fn lend_from_blobs<T>(&self, id: Uuid) -> Result<RepoRef<T, Blob>, RepoRetrievalError>
-    where
-        Box<T>: Into<Blob>,
-        Blob: TryInto<Box<T>, Error = TryIntoError<Blob>>,
-    {
-        let entry_ptr = self
-            .blobs
-            .lock()
-            .unwrap()
-            .get(&id)
-            .ok_or(RepoRetrievalError::IdNotFound)?
-            .clone();
-        lend_item(entry_ptr, |blob| blob.try_into().map_err(|e| e.input))
-            .ok_or(RepoRetrievalError::AlreadyRetrieved)
+    /// Finds the event instance whose ID starts with `prefix`, among all the
+    /// instances in the repository.
+    pub fn resolve_instance_id(&self, prefix: &str) -> Result<InstanceId, ResolveIdError> {
+        InstanceId::resolve(self.instances.lock().unwrap().keys().copied(), prefix)
+    }
+
+    /// Finds the event body whose ID starts with `prefix`.
+    pub fn resolve_body_id(&self, prefix: &str) -> Result<BodyId, ResolveIdError> {
+        BodyId::resolve(self.bodies.lock().unwrap().keys().copied(), prefix)
+    }
+
+    /// Finds the task whose ID starts with `prefix`.
+    pub fn resolve_task_id(&self, prefix: &str) -> Result<TaskId, ResolveIdError> {
+        TaskId::resolve(self.tasks.lock().unwrap().keys().copied(), prefix)
     }
 }
 
+fn lend_from<Id: Eq + Hash, T>(
+    store: &Store<Id, T>,
+    id: Id,
+) -> Result<RepoRef<T>, RepoRetrievalError> {
+    let entry_ptr = store
+        .lock()
+        .unwrap()
+        .get(&id)
+        .ok_or(RepoRetrievalError::IdNotFound)?
+        .clone();
+    lend_item(entry_ptr).ok_or(RepoRetrievalError::AlreadyRetrieved)
+}
+
+/// Adds an item under a new ID, lending it out straight away.
+fn add_to<Id: Eq + Hash + Copy, T>(store: &Store<Id, T>, id: Id, item: T) -> (Id, RepoRef<T>) {
+    // construct the entry as empty; the returned reference will fill in the
+    // entry when it is dropped
+    let entry = SlotPtr(Arc::new(Mutex::new(None)));
+    store.lock().unwrap().insert(id, SlotPtr::clone(&entry));
+
+    (
+        id,
+        RepoRef {
+            data: Some(Box::new(item)),
+            home_slot: entry,
+        },
+    )
+}
+
 impl Repository for MemoryRepo {
     fn get_timeline(
         &self,
     ) -> Option<impl DerefMut<Target = Timeline<Self::EventInstanceId>> + 'static + use<>> {
-        lend_item(self.timeline.clone(), Ok)
+        lend_item(self.timeline.clone())
     }
 
-    type EventInstanceId = Uuid;
+    type EventInstanceId = InstanceId;
 
     fn get_event_instance(
         &self,
@@ -59,7 +210,7 @@ impl Repository for MemoryRepo {
         impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<>,
         RepoRetrievalError,
     > {
-        self.lend_from_blobs(id)
+        lend_from(&self.instances, id)
     }
 
     fn add_event_instance(
@@ -69,29 +220,16 @@ impl Repository for MemoryRepo {
         Self::EventInstanceId,
         impl DerefMut<Target = EventInstance<Self::EventBodyId>> + 'static + use<>,
     ) {
-        let id = Uuid::new_v4();
-
-        // construct the entry as empty; the returned reference will fill in the
-        // entry when it is dropped
-        let entry = SlotPtr(Arc::new(Mutex::new(None)));
-        self.blobs.lock().unwrap().insert(id, entry.clone());
-
-        (
-            id,
-            RepoRef {
-                data: Some(Box::new(instance)),
-                home_slot: entry,
-            },
-        )
+        add_to(&self.instances, InstanceId::new(), instance)
     }
 
-    type EventBodyId = Uuid;
+    type EventBodyId = BodyId;
 
     fn get_event_body(
         &self,
         id: Self::EventBodyId,
     ) -> Result<impl DerefMut<Target = EventBody> + 'static + use<>, RepoRetrievalError> {
-        self.lend_from_blobs(id)
+        lend_from(&self.bodies, id)
     }
 
     fn add_event_body(
@@ -101,51 +239,53 @@ impl Repository for MemoryRepo {
         Self::EventBodyId,
         impl DerefMut<Target = EventBody> + 'static + use<>,
     ) {
-        let id = Uuid::new_v4();
+        add_to(&self.bodies, BodyId::new(), body)
+    }
 
-        // construct the entry as empty; the returned reference will fill in the
-        // entry when it is dropped
-        let entry = SlotPtr(Arc::new(Mutex::new(None)));
-        self.blobs
-            .lock()
-            .unwrap()
-            .insert(id, SlotPtr::clone(&entry));
+    fn get_task_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = TaskList<Self::TaskId>> + 'static + use<>> {
+        lend_item(self.task_list.clone())
+    }
+
+    type TaskId = TaskId;
 
-        (
-            id,
-            RepoRef {
-                data: Some(Box::new(body)),
-                home_slot: entry,
-            },
-        )
+    fn get_task(
+        &self,
+        id: Self::TaskId,
+    ) -> Result<
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<>,
+        RepoRetrievalError,
+    > {
+        lend_from(&self.tasks, id)
     }
-}
 
-fn lend_item<T, S, F>(entry_ptr: SlotPtr<S>, convert_item: F) -> Option<RepoRef<T, S>>
-where
-    Box<T>: Into<S>,
-    F: FnOnce(S) -> Result<Box<T>, S>,
-{
-    // make sure the contents exist (i.e. not already retrieved) and are
-    // of the right type
-    let mut entry = entry_ptr.0.lock().unwrap();
-    let contents = entry.take()?;
-    match convert_item(contents) {
-        Ok(correct_type) => {
-            drop(entry); // end the borrow of entry_ptr
-            Some(RepoRef {
-                data: Some(correct_type),
-                home_slot: entry_ptr,
-            })
-        }
-        Err(other_type) => {
-            // put the entry back because it was not the expected type
-            *entry = Some(other_type);
-            panic!("entry was not the expected type");
-        }
+    fn add_task(
+        &self,
+        task: Task<Self::EventInstanceId>,
+    ) -> (
+        Self::TaskId,
+        impl DerefMut<Target = Task<Self::EventInstanceId>> + 'static + use<>,
+    ) {
+        add_to(&self.tasks, TaskId::new(), task)
+    }
+
+    fn get_calendar_list(
+        &self,
+    ) -> Option<impl DerefMut<Target = CalendarList<Self::EventInstanceId>> + 'static + use<>> {
+        lend_item(self.calendar_list.clone())
     }
 }
 
+fn lend_item<T>(entry_ptr: SlotPtr<Box<T>>) -> Option<RepoRef<T>> {
+    // make sure the contents exist (i.e. not already retrieved)
+    let data = entry_ptr.0.lock().unwrap().take()?;
+    Some(RepoRef {
+        data: Some(data),
+        home_slot: entry_ptr,
+    })
+}
+
 struct SlotPtr<T>(Arc<Mutex<Option<T>>>);
 
 impl<T> Clone for SlotPtr<T> {
@@ -182,29 +322,17 @@ where
     }
 }
 
-#[derive(Debug, From, TryInto)]
-enum Blob {
-    EventInstance(Box<EventInstance<Uuid>>),
-    EventBody(Box<EventBody>),
-}
-
 #[derive(Debug)]
-struct RepoRef<T, S>
-where
-    Box<T>: Into<S>,
-{
+struct RepoRef<T> {
     // This is only an option so that it can be moved out in the destructor.
     // During normal operation, it can be assumed that this is always `Some`.
     /// The data being referenced.
     data: Option<Box<T>>,
     /// The slot where the data will be returned when this reference is dropped.
-    home_slot: SlotPtr<S>,
+    home_slot: SlotPtr<Box<T>>,
 }
 
-impl<T, S> Deref for RepoRef<T, S>
-where
-    Box<T>: Into<S>,
-{
+impl<T> Deref for RepoRef<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -215,10 +343,7 @@ where
     }
 }
 
-impl<T, S> DerefMut for RepoRef<T, S>
-where
-    Box<T>: Into<S>,
-{
+impl<T> DerefMut for RepoRef<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.data
             .as_mut()
@@ -227,10 +352,7 @@ where
     }
 }
 
-impl<T, S> Drop for RepoRef<T, S>
-where
-    Box<T>: Into<S>,
-{
+impl<T> Drop for RepoRef<T> {
     fn drop(&mut self) {
         let mut home_slot = self.home_slot.0.lock().unwrap();
         if home_slot.is_some() {
@@ -243,6 +365,39 @@ where
             .data
             .take()
             .expect("data should be Some before the destructor");
-        *home_slot = Some(data.into());
+        *home_slot = Some(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::repository_conformance_tests!(MemoryRepo::new());
+
+    #[test]
+    fn resolves_short_ids() {
+        let repo = MemoryRepo::new();
+        let ids: Vec<_> = (0..3)
+            .map(|_| repo.add_task(Task::new(String::new())).0)
+            .collect();
+        for &id in &ids {
+            assert_eq!(repo.resolve_task_id(&id.short()), Ok(id));
+            assert_eq!(repo.resolve_task_id(&id.to_string().to_uppercase()), Ok(id));
+            assert_eq!(id.to_string().parse(), Ok(id));
+            // prefixes longer than the short form, in either form
+            let simple = id.to_string().replace('-', "");
+            assert_eq!(repo.resolve_task_id(&simple[..10]), Ok(id));
+            assert_eq!(repo.resolve_task_id(&id.to_string()[..11]), Ok(id));
+        }
+        assert!(matches!(
+            repo.resolve_task_id(""),
+            Err(ResolveIdError::Ambiguous { count: 3, .. })
+        ));
+        // a task's ID is not an event's
+        assert!(matches!(
+            repo.resolve_instance_id(&ids[0].short()),
+            Err(ResolveIdError::NoMatch { what: "event", .. })
+        ));
     }
 }
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::{domain::EventBody, repository::Repository};
+
+/// An inverted index over the text of event bodies, for finding bodies by the
+/// words in their summary and description.
+///
+/// Matching ignores case and diacritics, and each word of a query matches any
+/// indexed word that it is a prefix of. The index does not watch the
+/// repository by itself; [`IndexingRepo`] keeps one up to date with the bodies
+/// added and changed through it.
+///
+/// [`IndexingRepo`]: crate::IndexingRepo
+#[derive(Debug)]
+pub struct SearchIndex<EventBodyId> {
+    /// Maps each normalized word to the bodies containing it.
+    postings: BTreeMap<String, BTreeSet<EventBodyId>>,
+    /// Maps each indexed body to the words it was indexed under, so that they
+    /// can be removed when the body changes.
+    words: BTreeMap<EventBodyId, BTreeSet<String>>,
+}
+
+impl<EventBodyId> Default for SearchIndex<EventBodyId> {
+    fn default() -> Self {
+        Self {
+            postings: BTreeMap::new(),
+            words: BTreeMap::new(),
+        }
+    }
+}
+
+impl<EventBodyId: Copy + Ord> SearchIndex<EventBodyId> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes a body under its current text, replacing whatever it was
+    /// previously indexed under.
+    pub fn index_body(&mut self, id: EventBodyId, body: &EventBody) {
+        self.remove_body(id);
+        let words: BTreeSet<_> = tokenize(&body.summary)
+            .chain(tokenize(&body.description))
+            .collect();
+        for word in &words {
+            self.postings.entry(word.clone()).or_default().insert(id);
+        }
+        self.words.insert(id, words);
+    }
+
+    /// Removes a body from the index.
+    pub fn remove_body(&mut self, id: EventBodyId) {
+        let Some(words) = self.words.remove(&id) else {
+            return;
+        };
+        for word in words {
+            if let Some(ids) = self.postings.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Returns the bodies that match every word of the query. A query without
+    /// any words matches nothing.
+    pub fn query(&self, query: &str) -> BTreeSet<EventBodyId> {
+        let mut result: Option<BTreeSet<EventBodyId>> = None;
+        for word in tokenize(query) {
+            let matching: BTreeSet<_> = self
+                .postings
+                .range(word.clone()..)
+                .take_while(|(indexed, _)| indexed.starts_with(&word))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect();
+            result = Some(match result {
+                Some(result) => &result & &matching,
+                None => matching,
+            });
+        }
+        result.unwrap_or_default()
+    }
+}
+
+/// Finds the event instances whose bodies match the query, in order of time.
+pub fn search_events<R: Repository>(
+    repo: &R,
+    index: &SearchIndex<R::EventBodyId>,
+    query: &str,
+) -> Vec<R::EventInstanceId>
+where
+    R::EventBodyId: Ord,
+{
+    let bodies = index.query(query);
+    if bodies.is_empty() {
+        return Vec::new();
+    }
+    let Some(timeline) = repo.get_timeline() else {
+        return Vec::new();
+    };
+    timeline
+        .events
+        .values()
+        .copied()
+        .filter(|&id| {
+            repo.get_event_instance(id)
+                .is_ok_and(|instance| bodies.contains(&instance.body))
+        })
+        .collect()
+}
+
+/// Splits text into lowercase words with diacritics removed.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(fold)
+}
+
+/// Lowercases text and removes its diacritics, so that it can be compared
+/// loosely.
+pub(crate) fn fold(text: &str) -> String {
+    text.nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(summary: &str, description: &str) -> EventBody {
+        EventBody {
+            summary: summary.to_owned(),
+            description: description.to_owned(),
+            location: String::new(),
+            categories: Vec::new(),
+            status: Default::default(),
+            alarms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn tokenize_folds_case_and_diacritics() {
+        let words: Vec<_> = tokenize("Café crème, RÉSUMÉ-review").collect();
+        assert_eq!(words, ["cafe", "creme", "resume", "review"]);
+    }
+
+    #[test]
+    fn query_matches_prefixes_of_all_words() {
+        let mut index = SearchIndex::new();
+        index.index_body(1, &body("Dentist appointment", "Dr. Müller"));
+        index.index_body(2, &body("Team standup", "daily"));
+        index.index_body(3, &body("Dentures", ""));
+
+        assert_eq!(index.query("dent"), BTreeSet::from([1, 3]));
+        assert_eq!(index.query("DENT mull"), BTreeSet::from([1]));
+        assert_eq!(index.query("stand daily"), BTreeSet::from([2]));
+        assert_eq!(index.query("standup weekly"), BTreeSet::new());
+        assert_eq!(index.query("  "), BTreeSet::new());
+    }
+
+    #[test]
+    fn reindexing_replaces_old_words() {
+        let mut index = SearchIndex::new();
+        index.index_body(7, &body("Lunch", ""));
+        index.index_body(7, &body("Dinner", ""));
+        assert_eq!(index.query("lunch"), BTreeSet::new());
+        assert_eq!(index.query("dinner"), BTreeSet::from([7]));
+
+        index.remove_body(7);
+        assert_eq!(index.query("dinner"), BTreeSet::new());
+    }
+}
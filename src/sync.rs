@@ -0,0 +1,825 @@
+//! Two-way synchronisation of the timeline with a calendar on a CalDAV
+//! server.
+//!
+//! Each event instance on the timeline corresponds to one resource of the
+//! calendar. The [`SyncState`] remembers, for each resource, the ETag it had
+//! and the event as it was when the two sides last agreed, which tells apart
+//! changes made on the server from changes made locally. Changes on both
+//! sides are conflicts, resolved by a [`ConflictPolicy`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+};
+
+use derive_more::derive::{Display, Error, From, FromStr};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    ical::{self, IcalEvent, StoreEventError},
+    repository::{RepoRetrievalError, Repository},
+};
+
+pub use client::CalDavClient;
+use client::Conditional;
+
+mod client;
+
+/// Which side wins when an event was changed both locally and on the server
+/// since the last sync.
+#[derive(Debug, Default, Display, FromStr, Copy, Clone, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the local version, overwriting the one on the server.
+    #[display("local")]
+    Local,
+    /// Keep the version on the server, discarding the local changes.
+    #[default]
+    #[display("remote")]
+    Remote,
+}
+
+/// What the last sync with a calendar left behind, needed to tell what has
+/// changed since then. A state only makes sense for the calendar it was used
+/// with, so it can be serialized to keep it alongside the calendar's URL.
+///
+/// Resources are matched with instances by the [`uid`] of the instance, which
+/// is dumped and restored along with it, so a saved state carries on with
+/// the repository restored from a dump.
+///
+/// [`uid`]: crate::EventInstance::uid
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    sync_token: Option<String>,
+    /// The synced resources, by href.
+    resources: BTreeMap<String, SyncedResource>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncedResource {
+    etag: String,
+    /// The event as it was on both sides after the last sync, with the UID
+    /// of the instance it was synced with.
+    event: IcalEvent,
+}
+
+impl SyncState {
+    /// The state of a repository that has never been synced.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// What a sync did.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Events added or changed locally to match the server.
+    pub pulled: usize,
+    /// Events added or changed on the server to match the repository.
+    pub pushed: usize,
+    /// Events removed from the timeline because they were deleted on the
+    /// server.
+    pub deleted_locally: usize,
+    /// Events deleted from the server because they were removed from the
+    /// timeline.
+    pub deleted_remotely: usize,
+    pub conflicts: usize,
+    /// Resources that could not be synced, each with the reason why.
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum SyncError {
+    #[display("could not reach the server: {_0}")]
+    Io(io::Error),
+    #[display("could not read the repository: {_0}")]
+    Retrieval(RepoRetrievalError),
+    #[display("not an http:// URL: {_0}")]
+    #[from(ignore)]
+    InvalidUrl(#[error(not(source))] String),
+    #[display("the server answered {request} with status {status}")]
+    #[from(ignore)]
+    UnexpectedStatus { request: String, status: u16 },
+    #[display("the server sent a malformed response to {_0}")]
+    #[from(ignore)]
+    MalformedResponse(#[error(not(source))] String),
+}
+
+/// Brings the timeline of a repository and a CalDAV calendar up to date with
+/// each other: changes made on the server are pulled, then local changes are
+/// pushed.
+///
+/// Events removed from the timeline are deleted from the server, and events
+/// deleted from the server are removed from the timeline. An event from the
+/// server is skipped if another event on the timeline already starts at the
+/// same time. An event that the state does not know of takes the server's
+/// version if an instance on the timeline already has its UID.
+pub fn sync_caldav<R: Repository>(
+    repo: &R,
+    client: &CalDavClient,
+    state: &mut SyncState,
+    policy: ConflictPolicy,
+) -> Result<SyncReport, SyncError>
+where
+    R::EventInstanceId: Ord,
+    R::EventBodyId: PartialEq,
+{
+    let mut report = SyncReport::default();
+    let mut by_uid = instances_by_uid(repo)?;
+    // a state none of whose events are in the repository was left by another
+    // repository, and acting on it would delete their resources
+    if !state.resources.is_empty()
+        && !state
+            .resources
+            .values()
+            .any(|synced| by_uid.contains_key(&synced.event.uid))
+    {
+        *state = SyncState::new();
+    }
+    let listing = client.changes(state.sync_token.as_deref())?;
+    let mut changes: BTreeMap<_, _> = listing.changes.into_iter().collect();
+    if listing.complete {
+        for href in state.resources.keys() {
+            changes.entry(href.clone()).or_insert(None);
+        }
+    }
+    // our own changes come back with the ETags they were given
+    changes.retain(|href, etag| match (state.resources.get(href), etag) {
+        (Some(synced), Some(etag)) => synced.etag != *etag,
+        (synced, None) => synced.is_some(),
+        (None, Some(_)) => true,
+    });
+    let to_fetch: Vec<_> = changes
+        .iter()
+        .filter(|(_, etag)| etag.is_some())
+        .map(|(href, _)| href.as_str())
+        .collect();
+    let mut fetched: BTreeMap<_, _> = client
+        .fetch(&to_fetch)?
+        .into_iter()
+        .map(|(href, etag, data)| (href, (etag, data)))
+        .collect();
+
+    for href in changes.into_keys() {
+        let synced = state.resources.get(&href);
+        let id = synced.and_then(|synced| by_uid.get(&synced.event.uid).copied());
+        let local = synced
+            .zip(id)
+            .and_then(|(synced, id)| local_event(repo, id, &synced.event.uid));
+        let changed_locally = synced.is_some_and(|synced| local.as_ref() != Some(&synced.event));
+
+        let Some((etag, data)) = fetched.remove(&href) else {
+            // deleted on the server
+            if state.resources.remove(&href).is_none() {
+                continue;
+            }
+            match local.zip(id) {
+                Some((local, _)) if changed_locally && policy == ConflictPolicy::Local => {
+                    report.conflicts += 1;
+                    if let Conditional::Done(etag) = client.put(&href, None, &write(&local))? {
+                        state.resources.insert(
+                            href,
+                            SyncedResource {
+                                etag: etag.unwrap_or_default(),
+                                event: local,
+                            },
+                        );
+                        report.pushed += 1;
+                    }
+                }
+                Some((_, id)) => {
+                    report.conflicts += usize::from(changed_locally);
+                    remove_from_timeline(repo, id);
+                    report.deleted_locally += 1;
+                }
+                None => {}
+            }
+            continue;
+        };
+        let remote = match ical::parse_vcalendar(&data) {
+            Ok(remote) => remote,
+            Err(e) => {
+                report.skipped.push(format!("{href}: {e}"));
+                continue;
+            }
+        };
+        if changed_locally {
+            report.conflicts += 1;
+            if policy == ConflictPolicy::Local {
+                let result = match &local {
+                    Some(local) => client
+                        .put(&href, Some(&etag), &write(local))?
+                        .map(|etag| (etag.unwrap_or_default(), local.clone())),
+                    None => client
+                        .delete(&href, &etag)?
+                        .map(|()| (String::new(), remote)),
+                };
+                match (result, local.is_some()) {
+                    (Conditional::Done((etag, event)), true) => {
+                        let synced = state.resources.get_mut(&href).unwrap();
+                        synced.etag = etag;
+                        synced.event = event;
+                        report.pushed += 1;
+                    }
+                    (Conditional::Done(_), false) => {
+                        state.resources.remove(&href);
+                        report.deleted_remotely += 1;
+                    }
+                    // changed yet again; the next sync will see it
+                    (Conditional::PreconditionFailed, _) => {}
+                }
+                continue;
+            }
+        }
+        // the state may have been lost since the instance was last synced
+        let id = id.or_else(|| by_uid.get(&remote.uid).copied());
+        let instance = match ical::store_event(repo, id, &remote) {
+            Ok(instance) => instance,
+            Err(StoreEventError::TimelineCollision(_)) => {
+                report.skipped.push(format!(
+                    "{href}: another event already starts at the same time"
+                ));
+                continue;
+            }
+            Err(StoreEventError::Retrieval(e)) => return Err(e.into()),
+        };
+        by_uid.insert(remote.uid.clone(), instance);
+        let event = local_event(repo, instance, &remote.uid).unwrap_or(remote);
+        state.resources.insert(href, SyncedResource { etag, event });
+        report.pulled += 1;
+    }
+
+    // push what changed locally
+    let by_uid = instances_by_uid(repo)?;
+    let hrefs: Vec<_> = state.resources.keys().cloned().collect();
+    for href in hrefs {
+        let synced = &state.resources[&href];
+        let Some(&id) = by_uid.get(&synced.event.uid) else {
+            match client.delete(&href, &synced.etag)? {
+                Conditional::Done(()) => {
+                    state.resources.remove(&href);
+                    report.deleted_remotely += 1;
+                }
+                Conditional::PreconditionFailed => report.conflicts += 1,
+            }
+            continue;
+        };
+        let Some(local) = local_event(repo, id, &synced.event.uid) else {
+            continue;
+        };
+        if local == synced.event {
+            continue;
+        }
+        match client.put(&href, Some(&synced.etag), &write(&local))? {
+            Conditional::Done(etag) => {
+                let synced = state.resources.get_mut(&href).unwrap();
+                synced.etag = etag.unwrap_or_default();
+                synced.event = local;
+                report.pushed += 1;
+            }
+            Conditional::PreconditionFailed => report.conflicts += 1,
+        }
+    }
+    let known: BTreeSet<_> = state
+        .resources
+        .values()
+        .map(|synced| synced.event.uid.clone())
+        .collect();
+    for (id, uid) in timeline_instances(repo)? {
+        if uid.as_ref().is_some_and(|uid| known.contains(uid)) {
+            continue;
+        }
+        let uid = uid.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let Some(local) = local_event(repo, id, &uid) else {
+            continue;
+        };
+        let href = format!("{}{uid}.ics", client.collection());
+        if let Conditional::Done(etag) = client.put(&href, None, &write(&local))? {
+            if let Ok(mut instance) = repo.get_event_instance(id) {
+                instance.uid = Some(uid);
+            }
+            state.resources.insert(
+                href,
+                SyncedResource {
+                    etag: etag.unwrap_or_default(),
+                    event: local,
+                },
+            );
+            report.pushed += 1;
+        }
+    }
+
+    state.sync_token = listing.sync_token;
+    Ok(report)
+}
+
+impl<T> Conditional<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Conditional<U> {
+        match self {
+            Conditional::Done(value) => Conditional::Done(f(value)),
+            Conditional::PreconditionFailed => Conditional::PreconditionFailed,
+        }
+    }
+}
+
+fn write(event: &IcalEvent) -> String {
+    ical::write_vcalendar(event, chrono::Utc::now())
+}
+
+/// The instances on the timeline, each with its UID if it has one.
+type TimelineInstances<EventInstanceId> = Vec<(EventInstanceId, Option<String>)>;
+
+fn timeline_instances<R: Repository>(
+    repo: &R,
+) -> Result<TimelineInstances<R::EventInstanceId>, SyncError> {
+    let ids: Vec<_> = repo
+        .get_timeline()
+        .ok_or(RepoRetrievalError::AlreadyRetrieved)?
+        .events
+        .values()
+        .copied()
+        .collect();
+    let mut instances = Vec::new();
+    for id in ids {
+        match repo.get_event_instance(id) {
+            Ok(instance) => instances.push((id, instance.uid.clone())),
+            Err(RepoRetrievalError::IdNotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(instances)
+}
+
+/// The instances on the timeline that have a UID, by UID.
+fn instances_by_uid<R: Repository>(
+    repo: &R,
+) -> Result<BTreeMap<String, R::EventInstanceId>, SyncError> {
+    Ok(timeline_instances(repo)?
+        .into_iter()
+        .filter_map(|(id, uid)| Some((uid?, id)))
+        .collect())
+}
+
+fn local_event<R: Repository>(repo: &R, id: R::EventInstanceId, uid: &str) -> Option<IcalEvent> {
+    let instance = repo.get_event_instance(id).ok()?;
+    let body = repo.get_event_body(instance.body).ok()?;
+    Some(IcalEvent::from_instance(uid.to_owned(), &instance, &body))
+}
+
+fn remove_from_timeline<R: Repository>(repo: &R, id: R::EventInstanceId)
+where
+    R::EventInstanceId: Ord,
+{
+    if let Some(mut timeline) = repo.get_timeline() {
+        timeline.events.retain(|_, other| *other != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use chrono::{prelude::*, TimeDelta};
+
+    use super::*;
+    use crate::{
+        domain::{Alarm, AlarmTrigger, TimeSpan},
+        repository::memory_repo::MemoryRepo,
+    };
+
+    /// A CalDAV server that keeps its resources in memory, with just enough
+    /// of the protocol for syncing.
+    #[derive(Default)]
+    struct MockServer {
+        /// The ETag, data and sync token of the last change of each
+        /// resource; deleted resources have no ETag or data.
+        resources: BTreeMap<String, (Option<(String, String)>, u64)>,
+        token: u64,
+        puts: usize,
+    }
+
+    impl MockServer {
+        fn set(&mut self, href: &str, data: Option<String>) {
+            self.token += 1;
+            let value = data.map(|data| (format!("\"e{}\"", self.token), data));
+            self.resources.insert(href.to_owned(), (value, self.token));
+        }
+
+        fn data(&self, href: &str) -> Option<&str> {
+            let (_, data) = self.resources.get(href)?.0.as_ref()?;
+            Some(data)
+        }
+
+        fn live(&self) -> usize {
+            self.resources
+                .values()
+                .filter(|(value, _)| value.is_some())
+                .count()
+        }
+
+        fn handle(
+            &mut self,
+            method: &str,
+            path: &str,
+            if_match: Option<&str>,
+            body: &str,
+        ) -> (u16, String, String) {
+            let etag = self
+                .resources
+                .get(path)
+                .and_then(|(value, _)| value.as_ref())
+                .map(|(etag, _)| etag.clone());
+            match method {
+                "REPORT" if body.contains("sync-collection") => {
+                    let token = body
+                        .split("<d:sync-token>")
+                        .nth(1)
+                        .and_then(|rest| rest.split('<').next())
+                        .unwrap_or_default();
+                    let since: u64 = match token.strip_prefix("mock-") {
+                        Some(token) => token.parse().unwrap(),
+                        None if token.is_empty() => 0,
+                        None => {
+                            let error = "<d:error xmlns:d=\"DAV:\"><d:valid-sync-token/></d:error>";
+                            return (403, String::new(), error.to_owned());
+                        }
+                    };
+                    let mut out = String::from("<d:multistatus xmlns:d=\"DAV:\">");
+                    for (href, (value, changed)) in &self.resources {
+                        match value {
+                            _ if *changed <= since => {}
+                            Some((etag, _)) => out += &format!("<d:response><d:href>{href}</d:href><d:propstat><d:prop><d:getetag>{etag}</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"),
+                            None => out += &format!("<d:response><d:href>{href}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>"),
+                        }
+                    }
+                    out += &format!(
+                        "<d:sync-token>mock-{}</d:sync-token></d:multistatus>",
+                        self.token
+                    );
+                    (207, String::new(), out)
+                }
+                "REPORT" => {
+                    let mut out = String::from("<d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\">");
+                    for href in body
+                        .split("<d:href>")
+                        .skip(1)
+                        .filter_map(|rest| rest.split('<').next())
+                    {
+                        if let Some((etag, data)) = self
+                            .resources
+                            .get(href)
+                            .and_then(|(value, _)| value.as_ref())
+                        {
+                            let data = data.replace('&', "&amp;").replace('<', "&lt;");
+                            out += &format!("<d:response><d:href>{href}</d:href><d:propstat><d:prop><d:getetag>{etag}</d:getetag><c:calendar-data>{data}</c:calendar-data></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>");
+                        }
+                    }
+                    out += "</d:multistatus>";
+                    (207, String::new(), out)
+                }
+                "PUT" | "DELETE" => {
+                    let precondition = match if_match {
+                        Some("*") | None => true,
+                        Some(if_match) => etag.as_deref() == Some(if_match),
+                    };
+                    if !precondition || (method == "PUT" && if_match.is_none() && etag.is_some()) {
+                        return (412, String::new(), String::new());
+                    }
+                    if method == "PUT" {
+                        self.puts += 1;
+                        let status = if etag.is_some() { 204 } else { 201 };
+                        self.set(path, Some(body.to_owned()));
+                        let etag = self.resources[path].0.as_ref().unwrap().0.clone();
+                        (status, etag, String::new())
+                    } else {
+                        self.set(path, None);
+                        (204, String::new(), String::new())
+                    }
+                }
+                _ => (405, String::new(), String::new()),
+            }
+        }
+    }
+
+    fn serve(mock: Arc<Mutex<MockServer>>) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cal/", server.server_addr().to_ip().unwrap());
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let if_match = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("If-Match"))
+                    .map(|header| header.value.to_string());
+                let (status, etag, body) = mock.lock().unwrap().handle(
+                    request.method().as_str(),
+                    request.url(),
+                    if_match.as_deref(),
+                    &body,
+                );
+                let mut response = tiny_http::Response::from_string(body).with_status_code(status);
+                if !etag.is_empty() {
+                    response.add_header(tiny_http::Header::from_bytes("ETag", etag).unwrap());
+                }
+                request.respond(response).unwrap();
+            }
+        });
+        url
+    }
+
+    fn vcalendar(uid: &str, hour: u32, summary: &str) -> String {
+        format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:{uid}\r\nDTSTART:20240610T{hour:02}0000Z\r\n\
+             DURATION:PT1H\r\nSUMMARY:{summary}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
+        )
+    }
+
+    fn summaries(repo: &MemoryRepo) -> Vec<String> {
+        let ids: Vec<_> = repo
+            .get_timeline()
+            .unwrap()
+            .events
+            .values()
+            .copied()
+            .collect();
+        ids.into_iter()
+            .map(|id| {
+                let instance = repo.get_event_instance(id).unwrap();
+                repo.get_event_body(instance.body).unwrap().summary.clone()
+            })
+            .collect()
+    }
+
+    fn rename(repo: &MemoryRepo, index: usize, summary: &str) {
+        let id = *repo
+            .get_timeline()
+            .unwrap()
+            .events
+            .values()
+            .nth(index)
+            .unwrap();
+        let instance = repo.get_event_instance(id).unwrap();
+        repo.get_event_body(instance.body).unwrap().summary = summary.to_owned();
+    }
+
+    #[test]
+    fn pulls_pushes_and_resolves_conflicts() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 9, "Standup")));
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 14, 0, 0).unwrap();
+        let time_span = TimeSpan::Interval {
+            start,
+            duration: TimeDelta::hours(1),
+        };
+        let _ = crate::add_event(&repo, time_span, "Review".to_owned(), String::new());
+        let mut state = SyncState::new();
+
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!((report.pulled, report.pushed), (1, 1));
+        assert_eq!(summaries(&repo), ["Standup", "Review"]);
+        assert_eq!(mock.lock().unwrap().live(), 2);
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report, SyncReport::default());
+
+        // an edit on each side
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 10, "Late standup")));
+        rename(&repo, 1, "Code review");
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!((report.pulled, report.pushed, report.conflicts), (1, 1, 0));
+        assert_eq!(summaries(&repo), ["Late standup", "Code review"]);
+        let pushed = state
+            .resources
+            .keys()
+            .find(|href| *href != "/cal/a.ics")
+            .unwrap();
+        assert!(mock
+            .lock()
+            .unwrap()
+            .data(pushed)
+            .unwrap()
+            .contains("SUMMARY:Code review"));
+
+        // the same event edited on both sides
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 10, "Remote")));
+        rename(&repo, 0, "Local");
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report.conflicts, 1);
+        assert_eq!(summaries(&repo)[0], "Remote");
+
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 10, "Remote again")));
+        rename(&repo, 0, "Local again");
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Local).unwrap();
+        assert_eq!((report.conflicts, report.pushed), (1, 1));
+        assert_eq!(summaries(&repo)[0], "Local again");
+        assert!(mock
+            .lock()
+            .unwrap()
+            .data("/cal/a.ics")
+            .unwrap()
+            .contains("SUMMARY:Local again"));
+    }
+
+    #[test]
+    fn deletions_go_both_ways() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        {
+            let mut mock = mock.lock().unwrap();
+            mock.set("/cal/a.ics", Some(vcalendar("a", 9, "Standup")));
+            mock.set("/cal/b.ics", Some(vcalendar("b", 11, "Lunch")));
+        }
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let mut state = SyncState::new();
+        sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(summaries(&repo), ["Standup", "Lunch"]);
+
+        mock.lock().unwrap().set("/cal/a.ics", None);
+        let id = *repo.get_timeline().unwrap().events.values().nth(1).unwrap();
+        remove_from_timeline(&repo, id);
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!((report.deleted_locally, report.deleted_remotely), (1, 1));
+        assert!(summaries(&repo).is_empty());
+        assert_eq!(mock.lock().unwrap().live(), 0);
+    }
+
+    #[test]
+    fn pulls_into_instances_that_share_a_body() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let at = |hour| TimeSpan::Interval {
+            start: Utc.with_ymd_and_hms(2024, 6, 10, hour, 0, 0).unwrap(),
+            duration: TimeDelta::hours(1),
+        };
+        let (first, body, mut instance, _) =
+            crate::add_event(&repo, at(9), "Standup".to_owned(), String::new());
+        let reminder = Alarm::new(AlarmTrigger::Start(-TimeDelta::minutes(10)), String::new());
+        instance.alarms.push(reminder.clone());
+        drop(instance);
+        let _ = crate::add_event_instance(&repo, body, at(11));
+        let mut state = SyncState::new();
+        sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(mock.lock().unwrap().live(), 2);
+
+        let uid = repo.get_event_instance(first).unwrap().uid.clone().unwrap();
+        let href = state
+            .resources
+            .iter()
+            .find(|(_, synced)| synced.event.uid == uid)
+            .map(|(href, _)| href.clone())
+            .unwrap();
+        {
+            let mut mock = mock.lock().unwrap();
+            let data = mock.data(&href).unwrap().replace("Standup", "Planning");
+            mock.set(&href, Some(data));
+        }
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!((report.pulled, report.pushed), (1, 0));
+        assert_eq!(summaries(&repo), ["Planning", "Standup"]);
+        let instance = repo.get_event_instance(first).unwrap();
+        assert_ne!(instance.body, body);
+        assert_eq!(instance.alarms, [reminder]);
+        assert!(repo
+            .get_event_body(instance.body)
+            .unwrap()
+            .alarms
+            .is_empty());
+    }
+
+    #[test]
+    fn saved_states_carry_on_with_their_repository_only() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 9, "Standup")));
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let mut state = SyncState::new();
+        sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+
+        let saved = serde_json::to_string(&state).unwrap();
+        let mut state: SyncState = serde_json::from_str(&saved).unwrap();
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report, SyncReport::default());
+
+        // a repository that has never seen the event pulls it again instead
+        // of deleting it from the server
+        let other = MemoryRepo::new();
+        let mut state: SyncState = serde_json::from_str(&saved).unwrap();
+        let report = sync_caldav(&other, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!((report.pulled, report.deleted_remotely), (1, 0));
+        assert_eq!(summaries(&other), ["Standup"]);
+    }
+
+    #[test]
+    fn carries_on_with_a_restored_repository() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 9, "Standup")));
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let start = Utc.with_ymd_and_hms(2024, 6, 10, 14, 0, 0).unwrap();
+        let _ = crate::add_event(
+            &repo,
+            TimeSpan::Instant(start),
+            "Review".to_owned(),
+            String::new(),
+        );
+        let mut state = SyncState::new();
+        sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        let saved = serde_json::to_string(&state).unwrap();
+        let mut dump = Vec::new();
+        crate::export_repo(&repo, &mut dump).unwrap();
+
+        // the next session starts from the dump and the saved state
+        let restored = MemoryRepo::new();
+        crate::import_repo(&restored, dump.as_slice()).unwrap();
+        let mut state: SyncState = serde_json::from_str(&saved).unwrap();
+        let puts = mock.lock().unwrap().puts;
+        let report = sync_caldav(&restored, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report, SyncReport::default());
+        assert_eq!(mock.lock().unwrap().puts, puts);
+
+        rename(&restored, 1, "Code review");
+        let report = sync_caldav(&restored, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report.pushed, 1);
+        assert_eq!(mock.lock().unwrap().live(), 2);
+
+        // with the state lost, the events are matched up by their UIDs
+        let mut state = SyncState::new();
+        let report = sync_caldav(&restored, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!((report.pulled, report.pushed), (2, 0));
+        assert_eq!(summaries(&restored), ["Standup", "Code review"]);
+        assert_eq!(mock.lock().unwrap().live(), 2);
+    }
+
+    #[test]
+    fn full_listings_find_deletions() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        {
+            let mut mock = mock.lock().unwrap();
+            mock.set("/cal/a.ics", Some(vcalendar("a", 9, "Standup")));
+            mock.set("/cal/b.ics", Some(vcalendar("b", 11, "Lunch")));
+        }
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let mut state = SyncState::new();
+        sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+
+        // a server that no longer knows the token lists what it has, which
+        // leaves out what was deleted
+        mock.lock().unwrap().resources.remove("/cal/a.ics");
+        state.sync_token = Some("expired".to_owned());
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report.deleted_locally, 1);
+        assert_eq!(summaries(&repo), ["Lunch"]);
+    }
+
+    #[test]
+    fn holding_an_instance_keeps_the_state() {
+        let mock = Arc::new(Mutex::new(MockServer::default()));
+        mock.lock()
+            .unwrap()
+            .set("/cal/a.ics", Some(vcalendar("a", 9, "Standup")));
+        let client = CalDavClient::new(&serve(mock.clone())).unwrap();
+        let repo = MemoryRepo::new();
+        let mut state = SyncState::new();
+        sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+
+        let id = *repo.get_timeline().unwrap().events.values().next().unwrap();
+        let held = repo.get_event_instance(id).unwrap();
+        assert!(matches!(
+            sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote),
+            Err(SyncError::Retrieval(RepoRetrievalError::AlreadyRetrieved))
+        ));
+        drop(held);
+        let report = sync_caldav(&repo, &client, &mut state, ConflictPolicy::Remote).unwrap();
+        assert_eq!(report, SyncReport::default());
+    }
+
+    #[test]
+    fn parses_urls() {
+        let client = CalDavClient::new("http://localhost:5232/calendars/metime").unwrap();
+        assert_eq!(client.collection(), "/calendars/metime/");
+        assert!(matches!(
+            CalDavClient::new("https://example.com/"),
+            Err(SyncError::InvalidUrl(_))
+        ));
+    }
+}
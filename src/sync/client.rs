@@ -0,0 +1,371 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use roxmltree::{Document, Node};
+
+use super::SyncError;
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+
+/// A CalDAV calendar collection on a server, reached over plain HTTP.
+///
+/// There is no support for TLS or authentication, so the server has to be
+/// local or reached through something that provides them, like an SSH
+/// tunnel.
+#[derive(Debug, Clone)]
+pub struct CalDavClient {
+    /// The `host[:port]` part of the URL, as sent in the `Host` header.
+    host: String,
+    /// The path of the collection, ending in a slash.
+    collection: String,
+}
+
+/// The resources of the collection that changed, as listed by the server.
+pub(super) struct Changes {
+    /// The href of each resource that changed, with its new ETag, or `None`
+    /// if it was deleted.
+    pub changes: Vec<(String, Option<String>)>,
+    /// Whether the list covers every resource of the collection, so that any
+    /// resource missing from it has been deleted.
+    pub complete: bool,
+    /// The sync token to give next time, if the server has them.
+    pub sync_token: Option<String>,
+}
+
+/// What became of a request that was conditional on an ETag.
+pub(super) enum Conditional<T> {
+    Done(T),
+    /// The resource changed on the server since the ETag was read.
+    PreconditionFailed,
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// A `<response>` of a WebDAV multistatus response.
+struct Entry {
+    href: String,
+    /// Whether the resource was found, as opposed to being reported as
+    /// deleted.
+    found: bool,
+    etag: Option<String>,
+    calendar_data: Option<String>,
+}
+
+impl CalDavClient {
+    /// Connects to the collection at a URL like
+    /// `http://localhost:5232/calendars/metime/`.
+    pub fn new(url: &str) -> Result<Self, SyncError> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| SyncError::InvalidUrl(url.to_owned()))?;
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if host.is_empty() {
+            return Err(SyncError::InvalidUrl(url.to_owned()));
+        }
+        let mut collection = if path.is_empty() { "/" } else { path }.to_owned();
+        if !collection.ends_with('/') {
+            collection.push('/');
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            collection,
+        })
+    }
+
+    /// The path of the collection, ending in a slash.
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Lists the resources that changed since the sync token was given out,
+    /// or all of them if there is no token or the server no longer accepts
+    /// it. Servers without `sync-collection` are listed in full.
+    pub(super) fn changes(&self, sync_token: Option<&str>) -> Result<Changes, SyncError> {
+        let body = format!(
+            "<d:sync-collection xmlns:d=\"DAV:\"><d:sync-token>{}</d:sync-token>\
+             <d:sync-level>1</d:sync-level><d:prop><d:getetag/></d:prop></d:sync-collection>",
+            escape_xml(sync_token.unwrap_or_default())
+        );
+        let response = self.request("REPORT", &self.collection, &[("Depth", "1")], &body)?;
+        if response.status == 207 {
+            let document = parse_xml(&response.body)?;
+            let next_token = document
+                .root_element()
+                .children()
+                .find(|node| node.has_tag_name((DAV, "sync-token")))
+                .and_then(|node| node.text())
+                .map(|token| token.trim().to_owned());
+            let changes = self
+                .entries(&document)
+                .map(|entry| (entry.href, entry.etag.filter(|_| entry.found)))
+                .collect();
+            // without a token, the server lists every resource there is
+            return Ok(Changes {
+                changes,
+                complete: sync_token.is_none(),
+                sync_token: next_token,
+            });
+        }
+        if sync_token.is_some() && response.body.contains("valid-sync-token") {
+            return self.changes(None);
+        }
+
+        let body = "<d:propfind xmlns:d=\"DAV:\"><d:prop><d:getetag/></d:prop></d:propfind>";
+        let response = self.request("PROPFIND", &self.collection, &[("Depth", "1")], body)?;
+        if response.status != 207 {
+            return Err(self.unexpected("PROPFIND", &self.collection, &response));
+        }
+        let document = parse_xml(&response.body)?;
+        let changes = self
+            .entries(&document)
+            .filter_map(|entry| Some((entry.href, Some(entry.etag?))))
+            .collect();
+        Ok(Changes {
+            changes,
+            complete: true,
+            sync_token: None,
+        })
+    }
+
+    /// Fetches the ETag and data of resources. Resources that are missing
+    /// from the result were deleted since they were listed.
+    pub(super) fn fetch(&self, hrefs: &[&str]) -> Result<Vec<(String, String, String)>, SyncError> {
+        if hrefs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let hrefs: String = hrefs
+            .iter()
+            .map(|href| format!("<d:href>{}</d:href>", escape_xml(href)))
+            .collect();
+        let body = format!(
+            "<c:calendar-multiget xmlns:d=\"DAV:\" xmlns:c=\"{CALDAV}\">\
+             <d:prop><d:getetag/><c:calendar-data/></d:prop>{hrefs}</c:calendar-multiget>"
+        );
+        let response = self.request("REPORT", &self.collection, &[("Depth", "1")], &body)?;
+        if response.status != 207 {
+            return Err(self.unexpected("REPORT", &self.collection, &response));
+        }
+        let document = parse_xml(&response.body)?;
+        Ok(self
+            .entries(&document)
+            .filter_map(|entry| Some((entry.href, entry.etag?, entry.calendar_data?)))
+            .collect())
+    }
+
+    /// Uploads a resource, either replacing the version with the given ETag
+    /// or creating a new one. Returns the new ETag, if the server gives one.
+    pub(super) fn put(
+        &self,
+        href: &str,
+        if_match: Option<&str>,
+        data: &str,
+    ) -> Result<Conditional<Option<String>>, SyncError> {
+        let condition = match if_match {
+            Some(etag) => ("If-Match", etag),
+            None => ("If-None-Match", "*"),
+        };
+        let headers = [("Content-Type", "text/calendar; charset=utf-8"), condition];
+        let response = self.request("PUT", href, &headers, data)?;
+        match response.status {
+            412 => Ok(Conditional::PreconditionFailed),
+            _ if response.is_success() => {
+                let etag = match response.header("ETag") {
+                    Some(etag) => Some(etag.to_owned()),
+                    // the server changed the data as it stored it
+                    None => self
+                        .request("HEAD", href, &[], "")?
+                        .header("ETag")
+                        .map(str::to_owned),
+                };
+                Ok(Conditional::Done(etag))
+            }
+            _ => Err(self.unexpected("PUT", href, &response)),
+        }
+    }
+
+    /// Deletes the version of a resource with the given ETag.
+    pub(super) fn delete(&self, href: &str, etag: &str) -> Result<Conditional<()>, SyncError> {
+        let response = self.request("DELETE", href, &[("If-Match", etag)], "")?;
+        match response.status {
+            412 => Ok(Conditional::PreconditionFailed),
+            // someone else deleted it first
+            404 => Ok(Conditional::Done(())),
+            _ if response.is_success() => Ok(Conditional::Done(())),
+            _ => Err(self.unexpected("DELETE", href, &response)),
+        }
+    }
+
+    /// Reads the `<response>`s of a multistatus response, leaving out the
+    /// collection itself.
+    fn entries<'a>(&'a self, document: &'a Document) -> impl Iterator<Item = Entry> + 'a {
+        document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name((DAV, "response")))
+            .filter_map(|response| {
+                let href = child(response, (DAV, "href"))?.text()?.trim();
+                let href = self.path_of(href);
+                if href == self.collection || href.ends_with('/') {
+                    return None;
+                }
+                let status_ok = |node: Node| {
+                    child(node, (DAV, "status"))
+                        .and_then(|status| status.text())
+                        .is_none_or(|status| status.contains(" 200 "))
+                };
+                let mut entry = Entry {
+                    href,
+                    found: status_ok(response),
+                    etag: None,
+                    calendar_data: None,
+                };
+                let props = response
+                    .children()
+                    .filter(|node| node.has_tag_name((DAV, "propstat")) && status_ok(*node))
+                    .filter_map(|propstat| child(propstat, (DAV, "prop")))
+                    .flat_map(|prop| prop.children());
+                for prop in props {
+                    let text = prop.text().map(str::to_owned);
+                    if prop.has_tag_name((DAV, "getetag")) {
+                        entry.etag = text.map(|etag| etag.trim().to_owned());
+                    } else if prop.has_tag_name((CALDAV, "calendar-data")) {
+                        entry.calendar_data = text;
+                    }
+                }
+                Some(entry)
+            })
+    }
+
+    /// Turns an href, which may be a full URL, into a path on the server.
+    fn path_of(&self, href: &str) -> String {
+        match href
+            .strip_prefix("http://")
+            .or(href.strip_prefix("https://"))
+        {
+            Some(rest) => rest[rest.find('/').unwrap_or(rest.len())..].to_owned(),
+            None => href.to_owned(),
+        }
+    }
+
+    fn unexpected(&self, method: &str, path: &str, response: &HttpResponse) -> SyncError {
+        SyncError::UnexpectedStatus {
+            request: format!("{method} {path}"),
+            status: response.status,
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Result<HttpResponse, SyncError> {
+        let address = if self.host.contains(':') {
+            self.host.clone()
+        } else {
+            format!("{}:80", self.host)
+        };
+        let mut stream = TcpStream::connect(address)?;
+        let mut request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            self.host,
+            body.len()
+        );
+        if !body.is_empty() && !headers.iter().any(|(name, _)| *name == "Content-Type") {
+            request += "Content-Type: application/xml; charset=utf-8\r\n";
+        }
+        for (name, value) in headers {
+            request += &format!("{name}: {value}\r\n");
+        }
+        request += "\r\n";
+        request += body;
+        stream.write_all(request.as_bytes())?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply)?;
+        parse_response(&reply, method == "HEAD")
+            .ok_or_else(|| SyncError::MalformedResponse(format!("{method} {path}")))
+    }
+}
+
+fn parse_response(reply: &[u8], head_only: bool) -> Option<HttpResponse> {
+    let split = reply.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&reply[..split]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers: Vec<_> = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_owned(), value.trim().to_owned()))
+        })
+        .collect();
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: String::new(),
+    };
+    let mut body = reply[split + 4..].to_vec();
+    if head_only {
+        body.clear();
+    } else if response
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        body = dechunk(&body)?;
+    } else if let Some(length) = response.header("Content-Length") {
+        body.truncate(length.parse().ok()?);
+    }
+    response.body = String::from_utf8(body).ok()?;
+    Some(response)
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`.
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Document<'_>, SyncError> {
+    Document::parse(text).map_err(|e| SyncError::MalformedResponse(e.to_string()))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: (&str, &str)) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
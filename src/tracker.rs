@@ -0,0 +1,174 @@
+use chrono::prelude::*;
+use derive_more::derive::{Display, Error};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::{EventBody, Outcome},
+    repository::Repository,
+    TimeSpan,
+};
+
+/// Records what is actually done, as it is done. While a timer runs, the
+/// tracker holds the body of the event being tracked; stopping the timer adds
+/// that body to the repository along with an instance covering the time the
+/// timer ran.
+///
+/// The tracker does not live in the repository, so that a running timer can
+/// be kept somewhere cheaper to update than the whole repository.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    running: Option<RunningTimer>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RunningTimer {
+    pub start: DateTime<Utc>,
+    pub body: EventBody,
+}
+
+/// What to do when starting a timer while another one is running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overlap {
+    /// Leave the running timer alone and fail.
+    Refuse,
+    /// Stop the running timer at the time the new one starts.
+    AutoClose,
+}
+
+#[derive(Debug, Display, Error, PartialEq, Eq)]
+pub enum TrackerError {
+    #[display("a timer has been running since {_0}")]
+    AlreadyRunning(#[error(not(source))] DateTime<Utc>),
+    #[display("no timer is running")]
+    NotRunning,
+    #[display("a timer cannot stop before it starts")]
+    StopBeforeStart,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restores a tracker whose timer was already running, e.g. before the
+    /// program was restarted.
+    pub fn resume(running: RunningTimer) -> Self {
+        Self {
+            running: Some(running),
+        }
+    }
+
+    /// The timer that is running, if any.
+    pub fn current(&self) -> Option<&RunningTimer> {
+        self.running.as_ref()
+    }
+
+    /// Starts a timer for `body` at `at`. If a timer is already running, it is
+    /// either closed first, in which case the ID of the instance it recorded
+    /// is returned, or the new timer is refused.
+    pub fn start<R: Repository>(
+        &mut self,
+        repo: &R,
+        body: EventBody,
+        at: DateTime<Utc>,
+        overlap: Overlap,
+    ) -> Result<Option<R::EventInstanceId>, TrackerError> {
+        let closed = match (&self.running, overlap) {
+            (None, _) => None,
+            (Some(running), Overlap::Refuse) => {
+                return Err(TrackerError::AlreadyRunning(running.start));
+            }
+            (Some(_), Overlap::AutoClose) => Some(self.stop(repo, at)?),
+        };
+        self.running = Some(RunningTimer { start: at, body });
+        Ok(closed)
+    }
+
+    /// Stops the running timer at `at`, recording the time it ran as an
+    /// event instance whose ID is returned.
+    pub fn stop<R: Repository>(
+        &mut self,
+        repo: &R,
+        at: DateTime<Utc>,
+    ) -> Result<R::EventInstanceId, TrackerError> {
+        let Some(running) = &self.running else {
+            return Err(TrackerError::NotRunning);
+        };
+        if at < running.start {
+            return Err(TrackerError::StopBeforeStart);
+        }
+        let RunningTimer { start, body } = self.running.take().unwrap();
+        let (body_id, _) = repo.add_event_body(body);
+        let time_span = TimeSpan::Interval {
+            start,
+            duration: at - start,
+        };
+        let (instance_id, mut instance) = crate::add_event_instance(repo, body_id, time_span);
+        // the timer only records what has already happened
+        instance.actual = Some(time_span);
+        instance.outcome = Some(Outcome::Done);
+        Ok(instance_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventStatus, MemoryRepo};
+
+    fn body(summary: &str) -> EventBody {
+        EventBody {
+            summary: summary.to_owned(),
+            description: String::new(),
+            location: String::new(),
+            categories: Vec::new(),
+            status: EventStatus::default(),
+            alarms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn timers_record_intervals() {
+        let repo = MemoryRepo::new();
+        let mut tracker = Tracker::new();
+        let nine = Utc.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+        let hour = chrono::TimeDelta::hours(1);
+
+        assert_eq!(tracker.stop(&repo, nine), Err(TrackerError::NotRunning));
+        assert_eq!(
+            tracker.start(&repo, body("Email"), nine, Overlap::Refuse),
+            Ok(None)
+        );
+        assert_eq!(
+            tracker.start(&repo, body("Coding"), nine + hour, Overlap::Refuse),
+            Err(TrackerError::AlreadyRunning(nine))
+        );
+        assert_eq!(tracker.current().unwrap().body.summary, "Email");
+
+        let email = tracker
+            .start(&repo, body("Coding"), nine + hour, Overlap::AutoClose)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            tracker.stop(&repo, nine),
+            Err(TrackerError::StopBeforeStart)
+        );
+        let coding = tracker.stop(&repo, nine + hour * 3).unwrap();
+        assert!(tracker.current().is_none());
+
+        let instance = repo.get_event_instance(email).unwrap();
+        assert_eq!(
+            instance.time_span,
+            TimeSpan::Interval {
+                start: nine,
+                duration: hour
+            }
+        );
+        assert_eq!(repo.get_event_body(instance.body).unwrap().summary, "Email");
+        let instance = repo.get_event_instance(coding).unwrap();
+        assert_eq!(instance.time_span.latest(), nine + hour * 3);
+        assert_eq!(repo.get_timeline().unwrap().events.len(), 2);
+    }
+}